@@ -1,3 +1,4 @@
+use crate::models::S3Config;
 use serde::{Deserialize, Serialize};
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -20,12 +21,12 @@ pub struct AuthSession {
     pub has_drive_access: bool,
 }
 
+/// The S3 settings as kept in Drive, so signing in on another device
+/// restores the whole library configuration.
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct CloudConfig {
-    pub bucket: String,
-    pub region: String,
-    pub access_key: String,
-    pub secret_key: String,
+    #[serde(flatten)]
+    pub storage: S3Config,
     pub last_updated: String,
 }
 
@@ -345,3 +346,53 @@ pub async fn load_metadata_from_drive(access_token: &str) -> Result<Option<Strin
     Ok(None)
 }
 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::{AssumeRole, CredentialSource, ServerSideEncryption, StorageClass};
+
+    #[test]
+    fn config_synced_before_credential_sources_still_loads() {
+        let config: CloudConfig = serde_json::from_str(
+            r#"{"bucket":"photos","region":"us-east-1","access_key":"AKIA","secret_key":"secret","last_updated":"2024-01-01T00:00:00Z"}"#,
+        )
+        .unwrap();
+
+        assert_eq!(config.storage.credentials, CredentialSource::Keys);
+        assert!(config.storage.session_token.is_none());
+        assert!(config.storage.assume_role.is_none());
+        assert!(!config.storage.private_bucket);
+    }
+
+    #[test]
+    fn the_whole_storage_config_survives_the_round_trip() {
+        let config = CloudConfig {
+            storage: S3Config {
+                bucket: "photos".to_string(),
+                region: "us-east-1".to_string(),
+                access_key: String::new(),
+                secret_key: String::new(),
+                session_token: None,
+                credentials: CredentialSource::Profile {
+                    name: Some("work".to_string()),
+                },
+                assume_role: Some(AssumeRole {
+                    role_arn: "arn:aws:iam::123456789012:role/galleria".to_string(),
+                    external_id: Some("shared-secret".to_string()),
+                    session_name: None,
+                }),
+                endpoint_url: None,
+                force_path_style: false,
+                public_base_url: None,
+                private_bucket: true,
+                encryption: Some(ServerSideEncryption::Kms { key_id: None }),
+                storage_classes: [("original".to_string(), StorageClass::GlacierIr)].into(),
+            },
+            last_updated: "2024-01-01T00:00:00Z".to_string(),
+        };
+
+        let restored: CloudConfig = serde_json::from_str(&serde_json::to_string(&config).unwrap()).unwrap();
+        assert_eq!(restored.storage, config.storage);
+    }
+}
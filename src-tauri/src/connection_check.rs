@@ -0,0 +1,130 @@
+use crate::models::StorageConfig;
+use crate::storage::{self, ErrorKind, ObjectSource, PutOptions, StorageBackend, StorageError, StorageResult};
+use crate::upload_sessions::UploadSessionStore;
+use serde::Serialize;
+use uuid::Uuid;
+
+// Outside the library prefixes, so a probe left behind by an interrupted
+// check never shows up in listings
+const PROBE_PREFIX: &str = "probes/";
+const PROBE_BODY: &[u8] = b"Galleria connection check";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Permission {
+    /// The bucket or container exists and is reachable
+    Bucket,
+    List,
+    Put,
+    Get,
+    Delete,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CheckStatus {
+    Passed,
+    Failed,
+    /// Not tried because an earlier check it depends on failed
+    Skipped,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct PermissionCheck {
+    pub permission: Permission,
+    pub status: CheckStatus,
+    pub error: Option<StorageError>,
+}
+
+/// Outcome of every check, in the order they ran.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ConnectionReport {
+    pub ok: bool,
+    pub checks: Vec<PermissionCheck>,
+}
+
+impl ConnectionReport {
+    // Returns whether the check passed
+    fn record(&mut self, permission: Permission, result: StorageResult<()>) -> bool {
+        let (status, error) = match result {
+            Ok(()) => (CheckStatus::Passed, None),
+            Err(error) => (CheckStatus::Failed, Some(error)),
+        };
+        self.checks.push(PermissionCheck {
+            permission,
+            status,
+            error,
+        });
+
+        status == CheckStatus::Passed
+    }
+
+    fn skip(&mut self, permissions: &[Permission]) {
+        for &permission in permissions {
+            self.checks.push(PermissionCheck {
+                permission,
+                status: CheckStatus::Skipped,
+                error: None,
+            });
+        }
+    }
+}
+
+/// Connects with `config` without saving it and probes what the
+/// credentials are allowed to do. Fails only when the settings are
+/// incomplete or no client can be built from them.
+pub async fn check_connection(
+    config: &StorageConfig,
+    sessions: &UploadSessionStore,
+) -> StorageResult<ConnectionReport> {
+    if !config.is_configured() {
+        return Err(StorageError::new(
+            ErrorKind::NotConfigured,
+            "Fill in all required storage settings first",
+        ));
+    }
+
+    let storage = storage::connect(config, sessions).await?;
+    Ok(probe(storage.as_ref()).await)
+}
+
+/// Checks the bucket, then lists, writes, reads back and deletes a probe
+/// object.
+pub async fn probe(storage: &dyn StorageBackend) -> ConnectionReport {
+    let mut report = ConnectionReport::default();
+
+    if !report.record(Permission::Bucket, storage.check_bucket().await) {
+        report.skip(&[Permission::List, Permission::Put, Permission::Get, Permission::Delete]);
+        return report;
+    }
+
+    let list = storage.list(PROBE_PREFIX, None, Some(1)).await.map(|_| ());
+    report.record(Permission::List, list);
+
+    let key = format!("{}{}.txt", PROBE_PREFIX, Uuid::new_v4());
+    let put = storage
+        .put(&key, ObjectSource::Bytes(PROBE_BODY), &PutOptions::content_type("text/plain"))
+        .await;
+
+    if report.record(Permission::Put, put) {
+        let get = match storage.get(&key).await {
+            Ok(Some(object)) if object.data == PROBE_BODY => Ok(()),
+            Ok(Some(_)) => Err(StorageError::other("The probe object was read back with different contents")),
+            Ok(None) => Err(StorageError::new(
+                ErrorKind::NotFound,
+                "The probe object was not found after writing it",
+            )),
+            Err(e) => Err(e),
+        };
+        report.record(Permission::Get, get);
+        report.record(Permission::Delete, storage.delete(&key).await);
+    } else {
+        report.skip(&[Permission::Get, Permission::Delete]);
+    }
+
+    report.ok = report
+        .checks
+        .iter()
+        .all(|check| check.status == CheckStatus::Passed);
+    report
+}
@@ -0,0 +1,612 @@
+use crate::image_processor;
+use crate::library_index;
+use crate::models::{PhotoMetadata, PhotoStack, StackAsset, StorageConfig};
+use crate::s3_uploader;
+use crate::stacks;
+use crate::storage::{
+    self, ErrorKind, ObjectInfo, ObjectSource, PutOptions, StorageBackend, StorageError, StorageResult,
+    SHORT_CACHE_CONTROL,
+};
+use crate::tiering;
+use crate::upload_sessions::UploadSessionStore;
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum FsckIssue {
+    /// Renditions without a metadata or trash entry, left by an upload that
+    /// stopped before its metadata was written
+    Orphaned {
+        photo_id: String,
+        keys: Vec<String>,
+        /// The import is still pending on this device; importing the same
+        /// file again finishes it
+        pending_upload: bool,
+        /// An original is there to rebuild the metadata from
+        can_relink: bool,
+    },
+    /// A photo whose metadata names objects that are gone, or that lacks a
+    /// size its dimensions call for
+    Incomplete {
+        photo_id: String,
+        missing: Vec<String>,
+        /// The original is there to regenerate the other sizes from
+        can_regenerate: bool,
+    },
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct FsckReport {
+    pub photos_checked: usize,
+    pub issues: Vec<FsckIssue>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RepairAction {
+    /// Recreates missing sizes from the original and unlinks a missing
+    /// Live Photo video or RAW file
+    Regenerate,
+    /// Writes new metadata for orphaned renditions
+    Relink,
+    /// Deletes whatever is left of the photo
+    Delete,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Repair {
+    pub photo_id: String,
+    pub action: RepairAction,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct RepairFailure {
+    pub photo_id: String,
+    pub error: StorageError,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct RepairReport {
+    pub repaired: Vec<String>,
+    pub failed: Vec<RepairFailure>,
+}
+
+// Stored objects of one photo, by size name
+type StoredSizes = BTreeMap<String, ObjectInfo>;
+
+/// Compares the renditions in storage with the library's metadata and the
+/// trash. Read only; see `repair_photo` for fixing what it finds.
+pub async fn check_library(
+    storage: &dyn StorageBackend,
+    sessions: &UploadSessionStore,
+) -> StorageResult<FsckReport> {
+    let stored = stored_photos(storage).await?;
+    // Straight from the metadata objects; loading through the library
+    // index would write it back
+    let keys = storage::list_all(storage, "metadata/")
+        .await?
+        .into_iter()
+        .map(|object| object.key)
+        .collect();
+    let photos = s3_uploader::fetch_metadata_batch(storage, keys).await?;
+    let trashed = trashed_ids(storage).await?;
+    let pending = sessions.pending_photo_ids();
+
+    let mut report = FsckReport {
+        photos_checked: photos.len(),
+        ..Default::default()
+    };
+
+    for photo in &photos {
+        let sizes = stored.get(&photo.id);
+        let missing = missing_sizes(photo, sizes);
+
+        if !missing.is_empty() {
+            report.issues.push(FsckIssue::Incomplete {
+                photo_id: photo.id.clone(),
+                can_regenerate: !missing.iter().any(|size_name| size_name == "original"),
+                missing,
+            });
+        }
+    }
+
+    let known: HashSet<&str> = photos.iter().map(|photo| photo.id.as_str()).collect();
+    for (photo_id, sizes) in &stored {
+        if known.contains(photo_id.as_str()) || trashed.contains(photo_id) {
+            continue;
+        }
+
+        report.issues.push(FsckIssue::Orphaned {
+            photo_id: photo_id.clone(),
+            keys: sizes.values().map(|object| object.key.clone()).collect(),
+            pending_upload: pending.contains(photo_id),
+            can_relink: sizes.contains_key("original"),
+        });
+    }
+
+    Ok(report)
+}
+
+/// Applies repairs one photo at a time; a failed repair doesn't stop the
+/// rest.
+pub async fn repair_library(
+    storage: &dyn StorageBackend,
+    config: &StorageConfig,
+    sessions: &UploadSessionStore,
+    repairs: Vec<Repair>,
+) -> RepairReport {
+    let mut report = RepairReport::default();
+
+    for repair in repairs {
+        match repair_photo(storage, config, sessions, &repair.photo_id, repair.action).await {
+            Ok(()) => report.repaired.push(repair.photo_id),
+            Err(error) => report.failed.push(RepairFailure {
+                photo_id: repair.photo_id,
+                error,
+            }),
+        }
+    }
+
+    report
+}
+
+/// Applies one repair. The photo is looked at again first, so a repair
+/// chosen from an older report can't undo a later upload.
+pub async fn repair_photo(
+    storage: &dyn StorageBackend,
+    config: &StorageConfig,
+    sessions: &UploadSessionStore,
+    photo_id: &str,
+    action: RepairAction,
+) -> StorageResult<()> {
+    let sizes = stored_sizes(storage, photo_id).await?;
+    let metadata_key = format!("metadata/{}.json", photo_id);
+
+    match action {
+        RepairAction::Delete => {
+            check_still_broken(storage, sessions, photo_id, &sizes).await?;
+            let report = s3_uploader::delete_photos(storage, &[photo_id.to_string()]).await?;
+            if let Some(failure) = report.failed.into_iter().next() {
+                return Err(failure.error.context(&format!("Failed to delete {}", failure.key)));
+            }
+        }
+        RepairAction::Relink => {
+            if storage.head(&metadata_key).await?.is_some() {
+                return Err(StorageError::other(format!("Photo {} already has metadata", photo_id)));
+            }
+            let photo = relinked_metadata(storage, photo_id, &sizes).await?;
+            complete_photo(storage, config, photo, &sizes).await?;
+        }
+        RepairAction::Regenerate => {
+            let photo = s3_uploader::fetch_metadata(storage, &metadata_key).await?;
+            complete_photo(storage, config, photo, &sizes).await?;
+        }
+    }
+
+    // Whatever the import had uploaded is now deleted or part of a photo
+    sessions.abandon_import(photo_id)?;
+
+    Ok(())
+}
+
+// Delete is only for photos the check reported: orphaned renditions or a
+// photo with missing sizes. Anything else may have been finished, trashed or
+// repaired since, and deleting it would lose a good photo.
+async fn check_still_broken(
+    storage: &dyn StorageBackend,
+    sessions: &UploadSessionStore,
+    photo_id: &str,
+    sizes: &StoredSizes,
+) -> StorageResult<()> {
+    if storage.head(&format!("trash/{}.json", photo_id)).await?.is_some() {
+        return Err(StorageError::other(format!(
+            "Photo {} is in the trash now, empty the trash to delete it",
+            photo_id
+        )));
+    }
+    if sessions.pending_photo_ids().contains(photo_id) {
+        return Err(StorageError::other(format!(
+            "Photo {} is still being uploaded from this device",
+            photo_id
+        )));
+    }
+
+    if let Some(object) = storage.get(&format!("metadata/{}.json", photo_id)).await? {
+        let photo: PhotoMetadata = serde_json::from_slice(&object.data)?;
+        if missing_sizes(&photo, Some(sizes)).is_empty() {
+            return Err(StorageError::other(format!("Photo {} is complete now", photo_id)));
+        }
+    }
+
+    Ok(())
+}
+
+// Size names a photo should have but storage doesn't
+fn missing_sizes(photo: &PhotoMetadata, sizes: Option<&StoredSizes>) -> Vec<String> {
+    let mut expected: BTreeSet<String> = photo.object_keys.keys().cloned().collect();
+    expected.extend(
+        image_processor::size_names_for(photo.width, photo.height)
+            .into_iter()
+            .map(String::from),
+    );
+
+    expected
+        .into_iter()
+        .filter(|size_name| sizes.is_none_or(|sizes| !sizes.contains_key(size_name)))
+        .collect()
+}
+
+/// Regenerates missing sizes from the original, drops a missing stacked
+/// asset and writes the metadata.
+async fn complete_photo(
+    storage: &dyn StorageBackend,
+    config: &StorageConfig,
+    mut photo: PhotoMetadata,
+    sizes: &StoredSizes,
+) -> StorageResult<()> {
+    let original = sizes.get("original").ok_or_else(|| {
+        StorageError::new(
+            ErrorKind::NotFound,
+            format!("The original of photo {} is gone, it can only be deleted", photo.id),
+        )
+    })?;
+
+    let missing = missing_sizes(&photo, Some(sizes));
+    if missing.iter().any(|size_name| size_name != "secondary") {
+        let data = storage
+            .get(&original.key)
+            .await?
+            .ok_or_else(|| StorageError::new(ErrorKind::NotFound, format!("{} is gone", original.key)))?
+            .data;
+        let renditions = image_processor::process_image_bytes(&data).map_err(StorageError::other)?;
+
+        for rendition in renditions {
+            if !missing.contains(&rendition.size_name) {
+                continue;
+            }
+
+            let key = format!("photos/{}/{}.jpg", photo.id, rendition.size_name);
+            let source = ObjectSource::Bytes(&rendition.data);
+            let sha256 = source.sha256().await?;
+            let options =
+                s3_uploader::rendition_options(config, &photo.id, &rendition.size_name, "image/jpeg", &sha256, None);
+            storage.put(&key, source, &options).await?;
+
+            photo.set_url(&rendition.size_name, storage.public_url(&key).unwrap_or_default());
+            photo.object_keys.insert(rendition.size_name.clone(), key);
+            photo.checksums.insert(rendition.size_name, sha256);
+        }
+    }
+
+    // A Live Photo video or RAW file can't be regenerated, so the photo
+    // becomes a plain still
+    if missing.iter().any(|size_name| size_name == "secondary") {
+        photo.stack = None;
+        photo.object_keys.remove("secondary");
+        photo.checksums.remove("secondary");
+    }
+
+    let metadata_key = format!("metadata/{}.json", photo.id);
+    storage
+        .put(
+            &metadata_key,
+            ObjectSource::Bytes(&serde_json::to_vec(&photo)?),
+            &PutOptions {
+                cache_control: Some(SHORT_CACHE_CONTROL.to_string()),
+                ..PutOptions::content_type("application/json")
+            },
+        )
+        .await?;
+
+    if let Err(e) = library_index::upsert(storage, &photo).await {
+        log::warn!("Failed to add repaired photo {} to the library index: {}", photo.id, e);
+    }
+
+    Ok(())
+}
+
+/// Metadata for orphaned renditions, built from what is in storage. File
+/// names come from the names the files were uploaded under, and are made up
+/// where storage didn't keep them.
+async fn relinked_metadata(
+    storage: &dyn StorageBackend,
+    photo_id: &str,
+    sizes: &StoredSizes,
+) -> StorageResult<PhotoMetadata> {
+    let original = sizes.get("original").ok_or_else(|| {
+        StorageError::new(
+            ErrorKind::NotFound,
+            format!("Photo {} has no original to relink, it can only be deleted", photo_id),
+        )
+    })?;
+    let data = storage
+        .get(&original.key)
+        .await?
+        .ok_or_else(|| StorageError::new(ErrorKind::NotFound, format!("{} is gone", original.key)))?
+        .data;
+    let (width, height) = image::load_from_memory(&data)
+        .map(|img| (img.width(), img.height()))
+        .map_err(StorageError::other)?;
+
+    let mut photo = PhotoMetadata {
+        id: photo_id.to_string(),
+        original_name: uploaded_name(storage, &original.key)
+            .await?
+            .unwrap_or_else(|| format!("recovered-{}.jpg", photo_id)),
+        upload_date: original.last_modified.clone().unwrap_or_else(|| Utc::now().to_rfc3339()),
+        file_size: original.size,
+        thumbnail_url: String::new(),
+        small_url: String::new(),
+        medium_url: String::new(),
+        large_url: String::new(),
+        original_url: String::new(),
+        width,
+        height,
+        stack: None,
+        object_keys: Default::default(),
+        checksums: Default::default(),
+    };
+    photo
+        .checksums
+        .insert("original".to_string(), ObjectSource::Bytes(&data).sha256().await?);
+
+    for (size_name, object) in sizes {
+        photo.set_url(size_name, storage.public_url(&object.key).unwrap_or_default());
+        photo.object_keys.insert(size_name.clone(), object.key.clone());
+    }
+
+    if let Some(secondary) = sizes.get("secondary") {
+        let secondary_name = match uploaded_name(storage, &secondary.key).await? {
+            Some(name) => name,
+            None => format!("recovered-{}.{}", photo_id, stacks::extension_of(&secondary.key)),
+        };
+        photo.stack = Some(PhotoStack {
+            kind: stacks::stack_kind_for(&secondary.key),
+            primary: StackAsset {
+                name: photo.original_name.clone(),
+                key: original.key.clone(),
+                url: photo.original_url.clone(),
+                content_type: "image/jpeg".to_string(),
+                file_size: original.size,
+            },
+            secondary: StackAsset {
+                name: secondary_name,
+                key: secondary.key.clone(),
+                url: storage.public_url(&secondary.key).unwrap_or_default(),
+                content_type: stacks::content_type_for(&secondary.key).to_string(),
+                file_size: secondary.size,
+            },
+        });
+    }
+
+    Ok(photo)
+}
+
+// Name the object was uploaded under, kept in its `Content-Disposition`
+async fn uploaded_name(storage: &dyn StorageBackend, key: &str) -> StorageResult<Option<String>> {
+    Ok(storage
+        .content_disposition(key)
+        .await?
+        .as_deref()
+        .and_then(s3_uploader::download_name_of))
+}
+
+// Every stored rendition, grouped by photo id
+async fn stored_photos(storage: &dyn StorageBackend) -> StorageResult<HashMap<String, StoredSizes>> {
+    let mut stored: HashMap<String, StoredSizes> = HashMap::new();
+
+    for object in storage::list_all(storage, "photos/").await? {
+        let Some(photo_id) = s3_uploader::photo_id_of(&object.key) else {
+            continue;
+        };
+        stored
+            .entry(photo_id.to_string())
+            .or_default()
+            .insert(tiering::size_name_of(&object.key).to_string(), object);
+    }
+
+    Ok(stored)
+}
+
+async fn stored_sizes(storage: &dyn StorageBackend, photo_id: &str) -> StorageResult<StoredSizes> {
+    Ok(storage::list_all(storage, &format!("photos/{}/", photo_id))
+        .await?
+        .into_iter()
+        .map(|object| (tiering::size_name_of(&object.key).to_string(), object))
+        .collect())
+}
+
+// Trashed photos still own their renditions
+async fn trashed_ids(storage: &dyn StorageBackend) -> StorageResult<HashSet<String>> {
+    Ok(storage::list_all(storage, "trash/")
+        .await?
+        .into_iter()
+        .filter_map(|object| {
+            object
+                .key
+                .strip_prefix("trash/")
+                .and_then(|name| name.strip_suffix(".json"))
+                .map(String::from)
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::fixtures;
+    use crate::storage::MemoryBackend;
+
+    // Session store on a temp file that is removed with it
+    struct TestSessions {
+        store: UploadSessionStore,
+        path: std::path::PathBuf,
+    }
+
+    impl std::ops::Deref for TestSessions {
+        type Target = UploadSessionStore;
+
+        fn deref(&self) -> &UploadSessionStore {
+            &self.store
+        }
+    }
+
+    impl Drop for TestSessions {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_file(&self.path);
+        }
+    }
+
+    fn sessions() -> TestSessions {
+        let path = std::env::temp_dir().join(format!("galleria-test-{}.json", uuid::Uuid::new_v4()));
+        TestSessions {
+            store: UploadSessionStore::load(path.clone()),
+            path,
+        }
+    }
+
+    // A 100x100 JPEG, so repairs can decode the original
+    fn jpeg() -> Vec<u8> {
+        let mut data = std::io::Cursor::new(Vec::new());
+        image::RgbImage::new(100, 100)
+            .write_to(&mut data, image::ImageFormat::Jpeg)
+            .unwrap();
+        data.into_inner()
+    }
+
+    async fn add_renditions(storage: &MemoryBackend, photo_id: &str) -> BTreeMap<String, String> {
+        let options = PutOptions::content_type("image/jpeg");
+        let data = jpeg();
+        let mut object_keys = BTreeMap::new();
+
+        for size_name in image_processor::size_names_for(100, 100) {
+            let key = format!("photos/{}/{}.jpg", photo_id, size_name);
+            storage.put(&key, ObjectSource::Bytes(&data), &options).await.unwrap();
+            object_keys.insert(size_name.to_string(), key);
+        }
+
+        object_keys
+    }
+
+    async fn add_photo(storage: &MemoryBackend, photo_id: &str) {
+        let photo = PhotoMetadata {
+            object_keys: add_renditions(storage, photo_id).await,
+            ..fixtures::photo(photo_id)
+        };
+        fixtures::write_metadata(storage, &photo).await;
+    }
+
+    async fn delete(storage: &MemoryBackend, sessions: &UploadSessionStore, photo_id: &str) -> StorageResult<()> {
+        repair_photo(storage, &StorageConfig::Memory, sessions, photo_id, RepairAction::Delete).await
+    }
+
+    async fn has_renditions(storage: &MemoryBackend, photo_id: &str) -> bool {
+        !stored_sizes(storage, photo_id).await.unwrap().is_empty()
+    }
+
+    #[tokio::test]
+    async fn delete_removes_orphaned_renditions() {
+        let storage = MemoryBackend::default();
+        add_renditions(&storage, "a").await;
+
+        delete(&storage, &sessions(), "a").await.unwrap();
+
+        assert!(!has_renditions(&storage, "a").await);
+    }
+
+    #[tokio::test]
+    async fn delete_refuses_a_photo_that_is_complete_now() {
+        let storage = MemoryBackend::default();
+        add_photo(&storage, "a").await;
+
+        assert!(delete(&storage, &sessions(), "a").await.is_err());
+        assert!(has_renditions(&storage, "a").await);
+    }
+
+    #[tokio::test]
+    async fn delete_refuses_a_trashed_photo() {
+        let storage = MemoryBackend::default();
+        add_renditions(&storage, "a").await;
+        storage
+            .put("trash/a.json", ObjectSource::Bytes(b"{}"), &PutOptions::content_type("application/json"))
+            .await
+            .unwrap();
+
+        assert!(delete(&storage, &sessions(), "a").await.is_err());
+        assert!(has_renditions(&storage, "a").await);
+    }
+
+    #[tokio::test]
+    async fn delete_refuses_a_photo_still_being_uploaded() {
+        let storage = MemoryBackend::default();
+        let sessions = sessions();
+        let photo_id = sessions.photo_id_for("fingerprint").unwrap();
+        add_renditions(&storage, &photo_id).await;
+
+        assert!(delete(&storage, &sessions, &photo_id).await.is_err());
+        assert!(has_renditions(&storage, &photo_id).await);
+    }
+
+    #[tokio::test]
+    async fn regenerate_recreates_missing_sizes_from_the_original() {
+        let storage = MemoryBackend::default();
+        let sessions = sessions();
+        add_photo(&storage, "a").await;
+        storage.delete("photos/a/thumbnail.jpg").await.unwrap();
+
+        let report = check_library(&storage, &sessions).await.unwrap();
+        assert!(matches!(
+            report.issues.as_slice(),
+            [FsckIssue::Incomplete { missing, can_regenerate: true, .. }] if missing == &["thumbnail"]
+        ));
+
+        repair_photo(&storage, &StorageConfig::Memory, &sessions, "a", RepairAction::Regenerate)
+            .await
+            .unwrap();
+
+        assert!(storage.head("photos/a/thumbnail.jpg").await.unwrap().is_some());
+        let photo = s3_uploader::fetch_metadata(&storage, "metadata/a.json").await.unwrap();
+        assert!(photo.checksums.contains_key("thumbnail"));
+        assert!(check_library(&storage, &sessions).await.unwrap().issues.is_empty());
+    }
+
+    #[tokio::test]
+    async fn relink_restores_the_uploaded_file_names() {
+        let storage = MemoryBackend::default();
+        let sessions = sessions();
+        let config = StorageConfig::Memory;
+        let data = jpeg();
+        let original_options =
+            s3_uploader::rendition_options(&config, "b", "original", "image/jpeg", "", Some("IMG_1.HEIC"));
+        storage
+            .put("photos/b/original.jpg", ObjectSource::Bytes(&data), &original_options)
+            .await
+            .unwrap();
+        let video_options =
+            s3_uploader::rendition_options(&config, "b", "secondary", "video/quicktime", "", Some("IMG_1.MOV"));
+        storage
+            .put("photos/b/secondary.mov", ObjectSource::Bytes(b"mov"), &video_options)
+            .await
+            .unwrap();
+
+        let report = check_library(&storage, &sessions).await.unwrap();
+        assert!(matches!(
+            report.issues.as_slice(),
+            [FsckIssue::Orphaned { can_relink: true, .. }]
+        ));
+        // Checking never writes, not even the library index
+        assert!(storage.list("index/", None, None).await.unwrap().objects.is_empty());
+
+        repair_photo(&storage, &config, &sessions, "b", RepairAction::Relink).await.unwrap();
+
+        let photo = s3_uploader::fetch_metadata(&storage, "metadata/b.json").await.unwrap();
+        assert_eq!(photo.original_name, "IMG_1.HEIC");
+        assert_eq!((photo.width, photo.height), (100, 100));
+        assert_eq!(photo.stack.unwrap().secondary.name, "IMG_1.MOV");
+        // The missing thumbnail was made from the original
+        assert!(storage.head("photos/b/thumbnail.jpg").await.unwrap().is_some());
+        assert!(check_library(&storage, &sessions).await.unwrap().issues.is_empty());
+    }
+}
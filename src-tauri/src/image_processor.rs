@@ -1,6 +1,8 @@
 use crate::models::CompressedImage;
 use image::{imageops::FilterType, ImageFormat, GenericImageView};
 use std::io::Cursor;
+use std::path::Path;
+use std::process::Command;
 
 const THUMBNAIL_SIZE: u32 = 200;
 const SMALL_SIZE: u32 = 640;
@@ -8,14 +10,95 @@ const MEDIUM_SIZE: u32 = 1280;
 const LARGE_SIZE: u32 = 1920;
 
 pub fn process_image(file_path: &str) -> Result<Vec<CompressedImage>, Box<dyn std::error::Error>> {
-    let img = image::open(file_path)?;
+    let img = open_image(file_path)?;
+    compress_all(&img)
+}
+
+// The image crate has no HEIC decoder, so those go through the platform's
+// converter first
+fn open_image(file_path: &str) -> Result<image::DynamicImage, Box<dyn std::error::Error>> {
+    match image::open(file_path) {
+        Ok(img) => Ok(img),
+        Err(e) if is_heif(file_path) => decode_heif(file_path).map_err(|heif_error| {
+            format!("{} (HEIC conversion failed: {})", e, heif_error).into()
+        }),
+        Err(e) => Err(e.into()),
+    }
+}
+
+fn is_heif(file_path: &str) -> bool {
+    matches!(
+        crate::stacks::extension_of(file_path).as_str(),
+        "heic" | "heif"
+    )
+}
+
+fn decode_heif(file_path: &str) -> Result<image::DynamicImage, Box<dyn std::error::Error>> {
+    let converted = std::env::temp_dir().join(format!("galleria-heic-{}.jpg", uuid::Uuid::new_v4()));
+    let result = convert_heif(Path::new(file_path), &converted).and_then(|()| Ok(image::open(&converted)?));
+    let _ = std::fs::remove_file(&converted);
+    result
+}
+
+#[cfg(target_os = "macos")]
+fn convert_heif(from: &Path, to: &Path) -> Result<(), Box<dyn std::error::Error>> {
+    let status = Command::new("sips")
+        .args(["-s", "format", "jpeg"])
+        .arg(from)
+        .arg("--out")
+        .arg(to)
+        .output()?
+        .status;
+    if !status.success() {
+        return Err(format!("sips exited with {}", status).into());
+    }
+    Ok(())
+}
+
+#[cfg(not(target_os = "macos"))]
+fn convert_heif(from: &Path, to: &Path) -> Result<(), Box<dyn std::error::Error>> {
+    let status = Command::new("heif-convert")
+        .arg(from)
+        .arg(to)
+        .output()
+        .map_err(|e| format!("heif-convert (libheif) is needed to read HEIC files: {}", e))?
+        .status;
+    if !status.success() {
+        return Err(format!("heif-convert exited with {}", status).into());
+    }
+    Ok(())
+}
+
+/// Same as `process_image` for an image already in memory, e.g. an original
+/// downloaded to regenerate its smaller sizes.
+pub fn process_image_bytes(data: &[u8]) -> Result<Vec<CompressedImage>, Box<dyn std::error::Error>> {
+    let img = image::load_from_memory(data)?;
+    compress_all(&img)
+}
+
+/// Size names `process_image` produces for an original of these dimensions.
+pub fn size_names_for(width: u32, height: u32) -> Vec<&'static str> {
+    let longest = width.max(height);
+    let mut size_names = vec!["thumbnail"];
+    
+    for (size, name) in [(SMALL_SIZE, "small"), (MEDIUM_SIZE, "medium"), (LARGE_SIZE, "large")] {
+        if longest > size {
+            size_names.push(name);
+        }
+    }
+    size_names.push("original");
+    
+    size_names
+}
+
+fn compress_all(img: &image::DynamicImage) -> Result<Vec<CompressedImage>, Box<dyn std::error::Error>> {
     let (original_width, original_height) = img.dimensions();
     
     let mut compressed_images = Vec::new();
     
     // Generate thumbnail
     compressed_images.push(resize_and_compress(
-        &img,
+        img,
         THUMBNAIL_SIZE,
         "thumbnail",
         85,
@@ -24,7 +107,7 @@ pub fn process_image(file_path: &str) -> Result<Vec<CompressedImage>, Box<dyn st
     // Generate small version (only if original is larger)
     if original_width > SMALL_SIZE || original_height > SMALL_SIZE {
         compressed_images.push(resize_and_compress(
-            &img,
+            img,
             SMALL_SIZE,
             "small",
             85,
@@ -34,7 +117,7 @@ pub fn process_image(file_path: &str) -> Result<Vec<CompressedImage>, Box<dyn st
     // Generate medium version (only if original is larger)
     if original_width > MEDIUM_SIZE || original_height > MEDIUM_SIZE {
         compressed_images.push(resize_and_compress(
-            &img,
+            img,
             MEDIUM_SIZE,
             "medium",
             90,
@@ -44,7 +127,7 @@ pub fn process_image(file_path: &str) -> Result<Vec<CompressedImage>, Box<dyn st
     // Generate large version (only if original is larger)
     if original_width > LARGE_SIZE || original_height > LARGE_SIZE {
         compressed_images.push(resize_and_compress(
-            &img,
+            img,
             LARGE_SIZE,
             "large",
             92,
@@ -0,0 +1,519 @@
+use crate::models::PhotoMetadata;
+use crate::s3_uploader;
+use crate::storage::{
+    ErrorKind, ObjectInfo, ObjectSource, PutOptions, StorageBackend, StorageError, StorageResult,
+    IMMUTABLE_CACHE_CONTROL, SHORT_CACHE_CONTROL,
+};
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use futures::stream::{self, StreamExt};
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+use uuid::Uuid;
+
+const MANIFEST_KEY: &str = "index/manifest.json";
+const FORMAT_VERSION: u32 = 3;
+const MAX_COMMIT_ATTEMPTS: usize = 5;
+const SHARD_FETCH_CONCURRENCY: usize = 8;
+
+/// Entry point of the library index. Each shard holds the metadata of the
+/// photos whose id starts with the shard name, two hex characters long; shard objects are immutable
+/// and a new generation is written on every change, so the manifest always
+/// points at a complete set of shards.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Manifest {
+    pub format_version: u32,
+    pub generation: u64,
+    pub updated_at: String,
+    pub shards: BTreeMap<String, String>,
+}
+
+impl Manifest {
+    fn empty() -> Self {
+        Self {
+            format_version: FORMAT_VERSION,
+            generation: 0,
+            updated_at: String::new(),
+            shards: BTreeMap::new(),
+        }
+    }
+}
+
+/// A photo as indexed, with the version of its metadata object at the time.
+/// A listing that finds a different version refreshes the entry, so
+/// metadata rewritten by a restore or repair is not served stale.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct IndexEntry {
+    photo: PhotoMetadata,
+    #[serde(default)]
+    version: Option<String>,
+}
+
+type Shard = BTreeMap<String, IndexEntry>;
+
+struct LoadedManifest {
+    manifest: Manifest,
+    e_tag: String,
+}
+
+fn metadata_key(photo_id: &str) -> String {
+    format!("metadata/{}.json", photo_id)
+}
+
+// Not every backend reports an ETag in listings; the modification time
+// changes on every rewrite as well
+fn version_of(info: &ObjectInfo) -> Option<String> {
+    info.e_tag.clone().or_else(|| info.last_modified.clone())
+}
+
+fn shard_for(photo_id: &str) -> String {
+    let prefix: String = photo_id.chars().take(2).map(|c| c.to_ascii_lowercase()).collect();
+    if prefix.is_empty() {
+        "_".to_string()
+    } else {
+        prefix
+    }
+}
+
+async fn load_manifest(
+    storage: &dyn StorageBackend,
+) -> StorageResult<Option<LoadedManifest>> {
+    let Some(object) = storage.get(MANIFEST_KEY).await? else {
+        return Ok(None);
+    };
+    let e_tag = object.e_tag.unwrap_or_default();
+
+    // An index written by an incompatible version is treated as empty, so
+    // the next listing rebuilds it in place
+    let manifest = match serde_json::from_slice::<Manifest>(&object.data) {
+        Ok(manifest) if manifest.format_version == FORMAT_VERSION => manifest,
+        _ => Manifest::empty(),
+    };
+
+    Ok(Some(LoadedManifest { manifest, e_tag }))
+}
+
+async fn load_shard(
+    storage: &dyn StorageBackend,
+    key: &str,
+) -> StorageResult<Shard> {
+    let object = storage
+        .get(key)
+        .await?
+        .ok_or_else(|| {
+            StorageError::new(ErrorKind::NotFound, format!("Library index shard {} is missing", key))
+        })?;
+
+    Ok(serde_json::from_slice(&object.data)?)
+}
+
+/// Writes the changed shards and swaps the manifest with a conditional put.
+/// Returns `false` when another writer updated the index first.
+async fn commit(
+    storage: &dyn StorageBackend,
+    base: Option<&LoadedManifest>,
+    changed: BTreeMap<String, Shard>,
+) -> StorageResult<bool> {
+    let mut manifest = base
+        .map(|b| b.manifest.clone())
+        .unwrap_or_else(Manifest::empty);
+    manifest.generation += 1;
+    manifest.updated_at = Utc::now().to_rfc3339();
+
+    let mut written = Vec::new();
+    let mut replaced = Vec::new();
+
+    for (shard_name, shard) in changed {
+        if let Some(old_key) = manifest.shards.remove(&shard_name) {
+            replaced.push(old_key);
+        }
+        if shard.is_empty() {
+            continue;
+        }
+
+        // Unique per writer, so a writer that loses the race only cleans up its own shards
+        let key = format!(
+            "index/shards/{}-{}-{}.json",
+            shard_name,
+            manifest.generation,
+            Uuid::new_v4().simple()
+        );
+        storage
+            .put(
+                &key,
+                ObjectSource::Bytes(&serde_json::to_vec(&shard)?),
+                &PutOptions {
+                    cache_control: Some(IMMUTABLE_CACHE_CONTROL.to_string()),
+                    ..PutOptions::content_type("application/json")
+                },
+            )
+            .await?;
+
+        manifest.shards.insert(shard_name, key.clone());
+        written.push(key);
+    }
+
+    let options = PutOptions {
+        if_match: base.map(|b| b.e_tag.clone()),
+        if_none_match: base.is_none(),
+        cache_control: Some(SHORT_CACHE_CONTROL.to_string()),
+        ..PutOptions::content_type("application/json")
+    };
+    let manifest_json = serde_json::to_vec(&manifest)?;
+
+    let (committed, orphaned) = match storage
+        .put(MANIFEST_KEY, ObjectSource::Bytes(&manifest_json), &options)
+        .await
+    {
+        Ok(()) => (true, replaced),
+        Err(e) if e.kind == ErrorKind::PreconditionFailed => (false, written),
+        Err(e) => return Err(e),
+    };
+
+    // Shards that are no longer referenced by the manifest
+    for key in orphaned {
+        let _ = storage.delete(&key).await;
+    }
+
+    Ok(committed)
+}
+
+async fn update_shard<F>(
+    storage: &dyn StorageBackend,
+    photo_id: &str,
+    apply: F,
+) -> StorageResult<()>
+where
+    F: Fn(&mut Shard),
+{
+    let shard_name = shard_for(photo_id);
+
+    for _ in 0..MAX_COMMIT_ATTEMPTS {
+        // Without an index there is nothing to keep in sync; the next
+        // listing rebuilds it from the metadata objects
+        let Some(base) = load_manifest(storage).await? else {
+            return Ok(());
+        };
+        if base.manifest.generation == 0 {
+            return Ok(());
+        }
+
+        let mut shard = match base.manifest.shards.get(&shard_name) {
+            Some(key) => load_shard(storage, key).await?,
+            None => Shard::new(),
+        };
+        apply(&mut shard);
+
+        let changed = BTreeMap::from([(shard_name.clone(), shard)]);
+        if commit(storage, Some(&base), changed).await? {
+            return Ok(());
+        }
+    }
+
+    Err("Library index kept changing while it was being updated".into())
+}
+
+/// Indexes a photo whose metadata object has just been written.
+pub async fn upsert(
+    storage: &dyn StorageBackend,
+    photo: &PhotoMetadata,
+) -> StorageResult<()> {
+    let version = storage
+        .head(&metadata_key(&photo.id))
+        .await?
+        .and_then(|info| version_of(&info));
+    let entry = IndexEntry {
+        photo: photo.clone(),
+        version,
+    };
+
+    update_shard(storage, &photo.id, |shard| {
+        shard.insert(photo.id.clone(), entry.clone());
+    })
+    .await
+}
+
+pub async fn remove(
+    storage: &dyn StorageBackend,
+    photo_id: &str,
+) -> StorageResult<()> {
+    update_shard(storage, photo_id, |shard| {
+        shard.remove(photo_id);
+    })
+    .await
+}
+
+/// Removes many photos in a single index commit.
+pub async fn remove_many(
+    storage: &dyn StorageBackend,
+    photo_ids: &[String],
+) -> StorageResult<()> {
+    for _ in 0..MAX_COMMIT_ATTEMPTS {
+        let Some(base) = load_manifest(storage).await? else {
+            return Ok(());
+        };
+        if base.manifest.generation == 0 {
+            return Ok(());
+        }
+
+        let mut changed: BTreeMap<String, Shard> = BTreeMap::new();
+        for photo_id in photo_ids {
+            let shard_name = shard_for(photo_id);
+            if !changed.contains_key(&shard_name) {
+                let shard = match base.manifest.shards.get(&shard_name) {
+                    Some(key) => load_shard(storage, key).await?,
+                    None => Shard::new(),
+                };
+                changed.insert(shard_name.clone(), shard);
+            }
+            if let Some(shard) = changed.get_mut(&shard_name) {
+                shard.remove(photo_id);
+            }
+        }
+
+        if commit(storage, Some(&base), changed).await? {
+            return Ok(());
+        }
+    }
+
+    Err("Library index kept changing while it was being updated".into())
+}
+
+/// Loads every photo through the index, reconciling it against the
+/// per-photo metadata objects. A missing or stale index is rebuilt, with
+/// photos handed to `on_batch` as each listing page is reconciled; every
+/// photo is handed over once, in its current version.
+pub async fn load_library(
+    storage: &dyn StorageBackend,
+    on_batch: &(dyn Fn(&[PhotoMetadata]) + Send + Sync),
+) -> StorageResult<Vec<PhotoMetadata>> {
+    let base = load_manifest(storage).await?;
+    let mut shards = match &base {
+        Some(base) => load_shards(storage, &base.manifest).await?,
+        None => BTreeMap::new(),
+    };
+
+    let mut listed = HashSet::new();
+    let mut refreshed: BTreeMap<String, IndexEntry> = BTreeMap::new();
+    let mut cursor: Option<String> = None;
+
+    // Fetch the photos the index does not know about or holds an older
+    // version of, page by page
+    loop {
+        let page = storage.list("metadata/", cursor.take(), None).await?;
+
+        let mut batch = Vec::new();
+        let mut stale = Vec::new();
+        let mut versions = Vec::new();
+        for info in page.objects {
+            let Some(id) = info.key.strip_prefix("metadata/").and_then(|k| k.strip_suffix(".json")) else {
+                continue;
+            };
+            let version = version_of(&info);
+            let current = shards
+                .get(&shard_for(id))
+                .and_then(|shard| shard.get(id))
+                .filter(|entry| entry.version == version);
+            match current {
+                Some(entry) => batch.push(entry.photo.clone()),
+                None => {
+                    stale.push(info.key.clone());
+                    versions.push(version);
+                }
+            }
+            listed.insert(id.to_string());
+        }
+
+        let fetched = s3_uploader::fetch_metadata_batch(storage, stale).await?;
+        batch.extend(fetched.iter().cloned());
+        if !batch.is_empty() {
+            on_batch(&batch);
+        }
+
+        for (photo, version) in fetched.into_iter().zip(versions) {
+            let entry = IndexEntry { photo, version };
+            shards
+                .entry(shard_for(&entry.photo.id))
+                .or_default()
+                .insert(entry.photo.id.clone(), entry.clone());
+            refreshed.insert(entry.photo.id.clone(), entry);
+        }
+
+        match page.next_cursor {
+            Some(next) => cursor = Some(next),
+            None => break,
+        }
+    }
+
+    // Photos whose metadata object is gone
+    let removed: BTreeSet<String> = shards
+        .values()
+        .flat_map(|shard| shard.keys())
+        .filter(|id| !listed.contains(*id))
+        .cloned()
+        .collect();
+    for id in &removed {
+        if let Some(shard) = shards.get_mut(&shard_for(id)) {
+            shard.remove(id);
+        }
+    }
+
+    if !refreshed.is_empty() || !removed.is_empty() {
+        save_changes(storage, base, &refreshed, &removed).await?;
+    }
+
+    Ok(shards
+        .into_values()
+        .flat_map(|shard| shard.into_values().map(|entry| entry.photo))
+        .collect())
+}
+
+async fn load_shards(
+    storage: &dyn StorageBackend,
+    manifest: &Manifest,
+) -> StorageResult<BTreeMap<String, Shard>> {
+    let mut loads = stream::iter(manifest.shards.clone())
+        .map(|(shard_name, key)| async move {
+            load_shard(storage, &key)
+                .await
+                .map(|shard| (shard_name, shard))
+        })
+        .buffer_unordered(SHARD_FETCH_CONCURRENCY);
+
+    let mut shards = BTreeMap::new();
+    while let Some(loaded) = loads.next().await {
+        let (shard_name, shard) = loaded?;
+        shards.insert(shard_name, shard);
+    }
+
+    Ok(shards)
+}
+
+/// Commits what a listing found out. When another writer updated the index
+/// in the meantime, the changes are applied again on top of its version.
+async fn save_changes(
+    storage: &dyn StorageBackend,
+    mut base: Option<LoadedManifest>,
+    refreshed: &BTreeMap<String, IndexEntry>,
+    removed: &BTreeSet<String>,
+) -> StorageResult<()> {
+    for _ in 0..MAX_COMMIT_ATTEMPTS {
+        let mut changed: BTreeMap<String, Shard> = BTreeMap::new();
+        let touched = refreshed.keys().chain(removed).map(|id| shard_for(id));
+        for shard_name in touched.collect::<BTreeSet<_>>() {
+            let shard = match base.as_ref().and_then(|b| b.manifest.shards.get(&shard_name)) {
+                Some(key) => load_shard(storage, key).await?,
+                None => Shard::new(),
+            };
+            changed.insert(shard_name, shard);
+        }
+
+        for (id, entry) in refreshed {
+            if let Some(shard) = changed.get_mut(&shard_for(id)) {
+                shard.insert(id.clone(), entry.clone());
+            }
+        }
+        for id in removed {
+            if let Some(shard) = changed.get_mut(&shard_for(id)) {
+                shard.remove(id);
+            }
+        }
+
+        if commit(storage, base.as_ref(), changed).await? {
+            return Ok(());
+        }
+        base = load_manifest(storage).await?;
+    }
+
+    Err("Library index kept changing while it was being updated".into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::fixtures;
+    use crate::storage::MemoryBackend;
+
+    async fn write_metadata(storage: &MemoryBackend, photo_id: &str, original_name: &str) -> PhotoMetadata {
+        let photo = PhotoMetadata {
+            original_name: original_name.to_string(),
+            ..fixtures::photo(photo_id)
+        };
+        fixtures::write_metadata(storage, &photo).await;
+        photo
+    }
+
+    async fn names(storage: &MemoryBackend) -> Vec<String> {
+        let mut photos = load_library(storage, &|_| {}).await.unwrap();
+        photos.sort_by(|a, b| a.id.cmp(&b.id));
+        photos.into_iter().map(|photo| photo.original_name).collect()
+    }
+
+    #[tokio::test]
+    async fn rewritten_metadata_refreshes_the_index() {
+        let storage = MemoryBackend::default();
+        write_metadata(&storage, "a1", "first.jpg").await;
+        write_metadata(&storage, "b1", "other.jpg").await;
+        assert_eq!(names(&storage).await, ["first.jpg", "other.jpg"]);
+
+        // Rewritten behind the index's back, as a restore from another device does
+        write_metadata(&storage, "a1", "second.jpg").await;
+        assert_eq!(names(&storage).await, ["second.jpg", "other.jpg"]);
+
+        // The refreshed entry was saved, so the index alone now has it
+        let base = load_manifest(&storage).await.unwrap().unwrap();
+        let shards = load_shards(&storage, &base.manifest).await.unwrap();
+        assert_eq!(shards["a1"]["a1"].photo.original_name, "second.jpg");
+    }
+
+    #[tokio::test]
+    async fn upsert_keeps_the_index_current() {
+        let storage = MemoryBackend::default();
+        write_metadata(&storage, "a1", "first.jpg").await;
+        names(&storage).await;
+
+        let photo = write_metadata(&storage, "a2", "added.jpg").await;
+        upsert(&storage, &photo).await.unwrap();
+
+        let base = load_manifest(&storage).await.unwrap().unwrap();
+        let shards = load_shards(&storage, &base.manifest).await.unwrap();
+        let listed = storage.head(&metadata_key("a2")).await.unwrap().unwrap();
+        assert_eq!(shards["a2"]["a2"].version, version_of(&listed));
+    }
+
+    #[tokio::test]
+    async fn deleted_metadata_is_dropped_from_the_index() {
+        let storage = MemoryBackend::default();
+        write_metadata(&storage, "a1", "first.jpg").await;
+        write_metadata(&storage, "a2", "second.jpg").await;
+        names(&storage).await;
+
+        storage.delete(&metadata_key("a1")).await.unwrap();
+        assert_eq!(names(&storage).await, ["second.jpg"]);
+    }
+
+    #[tokio::test]
+    async fn batches_hold_each_current_photo_once() {
+        let storage = MemoryBackend::default();
+        write_metadata(&storage, "a1", "first.jpg").await;
+        write_metadata(&storage, "a2", "second.jpg").await;
+        write_metadata(&storage, "b1", "other.jpg").await;
+        names(&storage).await;
+
+        write_metadata(&storage, "a1", "rewritten.jpg").await;
+        storage.delete(&metadata_key("a2")).await.unwrap();
+
+        let batched = std::sync::Mutex::new(Vec::new());
+        load_library(&storage, &|photos| {
+            batched
+                .lock()
+                .unwrap()
+                .extend(photos.iter().map(|photo| photo.original_name.clone()));
+        })
+        .await
+        .unwrap();
+
+        let mut batched = batched.into_inner().unwrap();
+        batched.sort();
+        assert_eq!(batched, ["other.jpg", "rewritten.jpg"]);
+    }
+}
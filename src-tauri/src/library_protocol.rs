@@ -0,0 +1,158 @@
+use crate::storage::{ErrorKind, StorageBackend, StorageError};
+use tauri::http::{header, Request, Response, StatusCode};
+
+// Only renditions are ever handed out through the protocol
+const SERVED_PREFIX: &str = "photos/";
+
+/// Answers a request of the `library` protocol with the object its path
+/// names, honouring `Range` so videos can be played and seeked.
+pub async fn serve(storage: &dyn StorageBackend, request: &Request<Vec<u8>>) -> Response<Vec<u8>> {
+    let key = match urlencoding::decode(request.uri().path().trim_start_matches('/')) {
+        Ok(key) => key.into_owned(),
+        Err(_) => return status(StatusCode::BAD_REQUEST),
+    };
+    if !key.starts_with(SERVED_PREFIX) || key.split('/').any(|segment| segment == "..") {
+        return status(StatusCode::NOT_FOUND);
+    }
+
+    let object = match storage.get(&key).await {
+        Ok(Some(object)) => object,
+        Ok(None) => return status(StatusCode::NOT_FOUND),
+        Err(error) => return failed(error),
+    };
+    let content_type = object
+        .content_type
+        .unwrap_or_else(|| crate::stacks::content_type_for(&key).to_string());
+    let total = object.data.len() as u64;
+
+    let range = request
+        .headers()
+        .get(header::RANGE)
+        .and_then(|value| value.to_str().ok());
+    let Some(range) = range else {
+        return Response::builder()
+            .header(header::CONTENT_TYPE, content_type)
+            .header(header::ACCEPT_RANGES, "bytes")
+            .body(object.data)
+            .unwrap_or_else(|_| status(StatusCode::INTERNAL_SERVER_ERROR));
+    };
+
+    let Some((start, end)) = byte_range(range, total) else {
+        return Response::builder()
+            .status(StatusCode::RANGE_NOT_SATISFIABLE)
+            .header(header::CONTENT_RANGE, format!("bytes */{}", total))
+            .body(Vec::new())
+            .unwrap_or_else(|_| status(StatusCode::INTERNAL_SERVER_ERROR));
+    };
+
+    Response::builder()
+        .status(StatusCode::PARTIAL_CONTENT)
+        .header(header::CONTENT_TYPE, content_type)
+        .header(header::ACCEPT_RANGES, "bytes")
+        .header(header::CONTENT_RANGE, format!("bytes {}-{}/{}", start, end, total))
+        .body(object.data[start as usize..=end as usize].to_vec())
+        .unwrap_or_else(|_| status(StatusCode::INTERNAL_SERVER_ERROR))
+}
+
+// First and last byte of a single `bytes=` range, `None` if unsatisfiable
+fn byte_range(range: &str, total: u64) -> Option<(u64, u64)> {
+    let (start, end) = range.strip_prefix("bytes=")?.split_once('-')?;
+    if total == 0 {
+        return None;
+    }
+
+    let (start, end) = match (start.trim(), end.trim()) {
+        // The last `end` bytes
+        ("", suffix) => {
+            let length: u64 = suffix.parse().ok()?;
+            (total.saturating_sub(length), total - 1)
+        }
+        (start, "") => (start.parse().ok()?, total - 1),
+        (start, end) => (start.parse().ok()?, end.parse::<u64>().ok()?.min(total - 1)),
+    };
+
+    (start <= end && start < total).then_some((start, end))
+}
+
+fn failed(error: StorageError) -> Response<Vec<u8>> {
+    let code = match error.kind {
+        ErrorKind::NotFound => StatusCode::NOT_FOUND,
+        ErrorKind::Locked | ErrorKind::Auth => StatusCode::FORBIDDEN,
+        _ => StatusCode::BAD_GATEWAY,
+    };
+
+    Response::builder()
+        .status(code)
+        .header(header::CONTENT_TYPE, "text/plain")
+        .body(error.to_string().into_bytes())
+        .unwrap_or_else(|_| status(StatusCode::INTERNAL_SERVER_ERROR))
+}
+
+fn status(code: StatusCode) -> Response<Vec<u8>> {
+    let mut response = Response::new(Vec::new());
+    *response.status_mut() = code;
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::{MemoryBackend, ObjectSource, PutOptions};
+
+    async fn get(storage: &MemoryBackend, uri: &str, range: Option<&str>) -> Response<Vec<u8>> {
+        let mut request = Request::builder().uri(uri);
+        if let Some(range) = range {
+            request = request.header(header::RANGE, range);
+        }
+        serve(storage, &request.body(Vec::new()).unwrap()).await
+    }
+
+    async fn storage() -> MemoryBackend {
+        let storage = MemoryBackend::default();
+        storage
+            .put(
+                "photos/a/secondary.mov",
+                ObjectSource::Bytes(b"0123456789"),
+                &PutOptions::content_type("video/quicktime"),
+            )
+            .await
+            .unwrap();
+        storage
+    }
+
+    #[tokio::test]
+    async fn serves_whole_objects_and_ranges() {
+        let storage = storage().await;
+
+        let whole = get(&storage, "library://localhost/photos/a/secondary.mov", None).await;
+        assert_eq!(whole.status(), StatusCode::OK);
+        assert_eq!(whole.headers()[header::CONTENT_TYPE], "video/quicktime");
+        assert_eq!(whole.body(), b"0123456789");
+
+        let part = get(&storage, "library://localhost/photos/a/secondary.mov", Some("bytes=2-4")).await;
+        assert_eq!(part.status(), StatusCode::PARTIAL_CONTENT);
+        assert_eq!(part.headers()[header::CONTENT_RANGE], "bytes 2-4/10");
+        assert_eq!(part.body(), b"234");
+
+        let tail = get(&storage, "library://localhost/photos/a/secondary.mov", Some("bytes=-3")).await;
+        assert_eq!(tail.body(), b"789");
+
+        let beyond = get(&storage, "library://localhost/photos/a/secondary.mov", Some("bytes=20-")).await;
+        assert_eq!(beyond.status(), StatusCode::RANGE_NOT_SATISFIABLE);
+    }
+
+    #[tokio::test]
+    async fn only_renditions_are_served() {
+        let storage = storage().await;
+        storage
+            .put("keys/keyring.json", ObjectSource::Bytes(b"{}"), &PutOptions::content_type("application/json"))
+            .await
+            .unwrap();
+
+        let response = get(&storage, "library://localhost/keys/keyring.json", None).await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+        let missing = get(&storage, "library://localhost/photos/b/original.jpg", None).await;
+        assert_eq!(missing.status(), StatusCode::NOT_FOUND);
+    }
+}
@@ -2,13 +2,28 @@
 #![cfg_attr(not(debug_assertions), windows_subsystem = "windows")]
 
 mod image_processor;
+mod library_index;
+mod library_protocol;
+mod migration;
 mod s3_uploader;
+mod scrub;
 mod models;
 mod database;
 mod auth;
+mod connection_check;
+mod fsck;
 mod oauth;
+mod stacks;
+mod storage;
+mod tiering;
+mod trash;
+mod upload_limits;
+mod upload_sessions;
+mod usage;
+mod url_cache;
 
 use tauri::{Manager, Emitter};
+use std::sync::atomic::AtomicBool;
 use std::sync::Arc;
 use tokio::sync::Mutex;
 
@@ -19,9 +34,43 @@ struct UploadProgress {
     status: String,
 }
 
+/// Photos uploaded by `upload_photos`, and the files that could not be read
+/// as images. Storage errors still stop the whole upload.
+#[derive(Debug, Default, serde::Serialize)]
+struct UploadReport {
+    uploaded: Vec<models::PhotoMetadata>,
+    failed: Vec<s3_uploader::UploadFailure>,
+}
+
 #[derive(Clone)]
 struct AppState {
     current_user_id: Arc<Mutex<Option<i64>>>,
+    url_cache: Arc<Mutex<url_cache::UrlCache>>,
+    storage: Arc<Mutex<storage::ConnectedStorage>>,
+    scrubbing: Arc<AtomicBool>,
+}
+
+// Storage backend of the configured library, connected on first use
+async fn storage_backend(
+    app_handle: &tauri::AppHandle,
+) -> Result<Arc<dyn storage::StorageBackend>, storage::StorageError> {
+    let state = app_handle.state::<AppState>();
+    let mut storage = state.storage.lock().await;
+    
+    let sessions = app_handle.state::<upload_sessions::UploadSessionStore>();
+    
+    storage.backend(&sessions).await
+}
+
+async fn storage_config(
+    app_handle: &tauri::AppHandle,
+) -> Result<models::StorageConfig, storage::StorageError> {
+    let state = app_handle.state::<AppState>();
+    let storage = state.storage.lock().await;
+    
+    storage.config().cloned().ok_or_else(|| {
+        storage::StorageError::new(storage::ErrorKind::NotConfigured, "Storage is not configured")
+    })
 }
 
 #[tauri::command]
@@ -123,11 +172,21 @@ async fn google_login(
 }
 
 #[tauri::command]
+#[allow(clippy::too_many_arguments)]
 async fn configure_s3(
     bucket: String,
     region: String,
     access_key: String,
     secret_key: String,
+    session_token: Option<String>,
+    credentials: Option<models::CredentialSource>,
+    assume_role: Option<models::AssumeRole>,
+    endpoint_url: Option<String>,
+    force_path_style: Option<bool>,
+    public_base_url: Option<String>,
+    private_bucket: Option<bool>,
+    encryption: Option<models::ServerSideEncryption>,
+    storage_classes: Option<std::collections::BTreeMap<String, models::StorageClass>>,
     app_handle: tauri::AppHandle,
 ) -> Result<String, String> {
     let state = app_handle.state::<AppState>();
@@ -144,55 +203,148 @@ async fn configure_s3(
         region: region.clone(),
         access_key: access_key.clone(),
         secret_key: secret_key.clone(),
+        session_token: session_token.filter(|s| !s.trim().is_empty()),
+        credentials: credentials.unwrap_or_default(),
+        assume_role: assume_role.filter(|role| !role.role_arn.trim().is_empty()),
+        endpoint_url: endpoint_url.filter(|s| !s.trim().is_empty()),
+        force_path_style: force_path_style.unwrap_or(false),
+        public_base_url: public_base_url.filter(|s| !s.trim().is_empty()),
+        private_bucket: private_bucket.unwrap_or(false),
+        encryption,
+        storage_classes: storage_classes.unwrap_or_default(),
     };
     
-    app_handle.manage(Arc::new(Mutex::new(config)));
+    if !config.is_configured() {
+        return Err("Bucket, region and credentials are required".to_string());
+    }
+    
+    // Keeps the existing client when the configuration did not change;
+    // URLs signed for the previous storage are of no use any more
+    if state.storage.lock().await.configure(models::StorageConfig::S3(config)) {
+        state.url_cache.lock().await.clear();
+    }
     
     Ok("S3 configured successfully".to_string())
 }
 
+#[tauri::command]
+async fn configure_storage(
+    config: models::StorageConfig,
+    app_handle: tauri::AppHandle,
+) -> Result<String, String> {
+    let state = app_handle.state::<AppState>();
+    let user_id = state.current_user_id.lock().await;
+    
+    let _user_id = user_id.ok_or("Not logged in")?;
+    
+    if state.storage.lock().await.configure(config) {
+        state.url_cache.lock().await.clear();
+    }
+    
+    Ok("Storage configured successfully".to_string())
+}
+
+// Tests `config` before it is saved, or the current configuration
+#[tauri::command]
+async fn test_storage_connection(
+    config: Option<models::StorageConfig>,
+    app_handle: tauri::AppHandle,
+) -> Result<connection_check::ConnectionReport, storage::StorageError> {
+    let config = match config {
+        Some(config) => config,
+        None => storage_config(&app_handle).await?,
+    };
+    let sessions = app_handle.state::<upload_sessions::UploadSessionStore>();
+    
+    connection_check::check_connection(&config, &sessions).await
+}
+
 #[tauri::command]
 async fn upload_photos(
     files: Vec<String>,
     app_handle: tauri::AppHandle,
-) -> Result<Vec<models::PhotoMetadata>, String> {
+) -> Result<UploadReport, storage::StorageError> {
     let state = app_handle.state::<AppState>();
     let user_id = state.current_user_id.lock().await;
-    let _user_id = user_id.ok_or("Not logged in")?;
+    user_id.ok_or("Not logged in")?;
+    drop(user_id);
+    
+    let storage = storage_backend(&app_handle).await?;
+    let config = storage_config(&app_handle).await?;
     
-    let config = app_handle.state::<Arc<Mutex<models::S3Config>>>();
-    let config = config.lock().await;
+    // Progress of earlier, interrupted uploads
+    let sessions = app_handle.state::<upload_sessions::UploadSessionStore>();
+    let limits = app_handle.state::<upload_limits::UploadLimitsStore>();
     
-    let mut results = Vec::new();
+    // Pair Live Photo videos and RAW files with their still image
+    let (items, unattached) = stacks::group_files(&files);
     
-    for (index, file_path) in files.iter().enumerate() {
+    let mut report = UploadReport::default();
+    report.failed.extend(unattached.into_iter().map(|file| s3_uploader::UploadFailure {
+        file,
+        error: storage::StorageError::other("No photo with the same name to attach this file to"),
+    }));
+    
+    for (index, item) in items.iter().enumerate() {
+        let file_path = &item.primary;
+        
+        // Outside the configured upload windows the queue waits, without
+        // holding the login lock that other commands need meanwhile
+        limits
+            .wait_for_window(|| {
+                let _ = app_handle.emit(
+                    "upload-progress",
+                    UploadProgress {
+                        file_name: file_path.clone(),
+                        progress: (index as f32 / items.len() as f32) * 100.0,
+                        status: "Waiting for upload window".to_string(),
+                    },
+                );
+            })
+            .await;
+        
+        let user_id = state.current_user_id.lock().await;
+        let _user_id = user_id.ok_or("Not logged in")?;
+        
         // Emit progress
         let _ = app_handle.emit(
             "upload-progress",
             UploadProgress {
                 file_name: file_path.clone(),
-                progress: (index as f32 / files.len() as f32) * 100.0,
+                progress: (index as f32 / items.len() as f32) * 100.0,
                 status: "Processing".to_string(),
             },
         );
         
-        // Process and compress image
-        let compressed_images = image_processor::process_image(file_path)
-            .map_err(|e| format!("Failed to process image: {}", e))?;
+        // Process and compress image. A still the decoder can't read is
+        // reported with the rest of its group and the batch carries on.
+        let compressed_images = match s3_uploader::process_item(item) {
+            Ok(compressed_images) => compressed_images,
+            Err(failures) => {
+                report.failed.extend(failures);
+                continue;
+            }
+        };
         
-        // Upload to S3
+        // Upload to storage
         let metadata = s3_uploader::upload_to_s3(
+            storage.as_ref(),
             &config,
+            &sessions,
             file_path,
             compressed_images,
+            item.companion.as_ref(),
+            &item.sidecars,
         )
         .await
-        .map_err(|e| format!("Failed to upload to S3: {}", e))?;
+        .map_err(|e| e.context("Failed to upload photo"))?;
         
         // Store in local database for caching
         // TODO: Add database insert here
         
-        results.push(metadata);
+        state.url_cache.lock().await.remember(&metadata);
+        
+        report.uploaded.push(metadata);
     }
     
     // Final progress
@@ -205,14 +357,14 @@ async fn upload_photos(
         },
     );
     
-    Ok(results)
+    Ok(report)
 }
 
 #[tauri::command]
 async fn list_photos(
     use_cache: bool,
     app_handle: tauri::AppHandle,
-) -> Result<Vec<models::PhotoMetadata>, String> {
+) -> Result<Vec<models::PhotoMetadata>, storage::StorageError> {
     let state = app_handle.state::<AppState>();
     let _user_id = state.current_user_id.lock().await;
     
@@ -221,46 +373,564 @@ async fn list_photos(
         // TODO: Query database for cached photos
     }
     
-    // Otherwise, fetch from S3
-    let config = app_handle.state::<Arc<Mutex<models::S3Config>>>();
-    let config = config.lock().await;
+    // Otherwise, fetch from storage
+    let storage = storage_backend(&app_handle).await?;
     
-    let photos = s3_uploader::list_photos(&config)
+    // Stream photos to the gallery while the rest of the library loads
+    let emit_batch = |batch: &[models::PhotoMetadata]| {
+        let _ = app_handle.emit("photos-batch", batch.to_vec());
+    };
+    
+    let photos = s3_uploader::list_photos(storage.as_ref(), &emit_batch)
         .await
-        .map_err(|e| format!("Failed to list photos: {}", e))?;
+        .map_err(|e| e.context("Failed to list photos"))?;
+    
+    // Purge expired trash in the background whenever the library is opened;
+    // whatever fails is tried again the next time
+    let purge_storage = storage.clone();
+    tauri::async_runtime::spawn(async move {
+        let _ = trash::purge_expired(purge_storage.as_ref()).await;
+    });
+    
+    // Same for the scheduled integrity scrub, which keeps its report with
+    // the schedule, unless one is already running
+    if let Some(running) = scrub::RunningScrub::start(&state.scrubbing) {
+        let scrub_storage = storage.clone();
+        tauri::async_runtime::spawn(async move {
+            let _ = scrub::scrub_if_due(scrub_storage.as_ref()).await;
+            drop(running);
+        });
+    }
     
     // Update cache
     // TODO: Store photos in database
     
+    let mut url_cache = state.url_cache.lock().await;
+    for photo in &photos {
+        url_cache.remember(photo);
+    }
+    
     Ok(photos)
 }
 
+#[tauri::command]
+async fn list_photos_page(
+    cursor: Option<String>,
+    page_size: Option<u32>,
+    app_handle: tauri::AppHandle,
+) -> Result<models::PhotoPage, storage::StorageError> {
+    let state = app_handle.state::<AppState>();
+    
+    let storage = storage_backend(&app_handle).await?;
+    
+    // S3 caps a single listing at 1,000 keys
+    let page_size = page_size.unwrap_or(200).clamp(1, 1000) as usize;
+    
+    let page = s3_uploader::list_photos_page(storage.as_ref(), cursor, page_size)
+        .await
+        .map_err(|e| e.context("Failed to list photos"))?;
+    
+    let mut url_cache = state.url_cache.lock().await;
+    for photo in &page.photos {
+        url_cache.remember(photo);
+    }
+    
+    Ok(page)
+}
+
 #[tauri::command]
 async fn delete_photo(
     photo_id: String,
     app_handle: tauri::AppHandle,
-) -> Result<String, String> {
-    let config = app_handle.state::<Arc<Mutex<models::S3Config>>>();
-    let config = config.lock().await;
+) -> Result<String, storage::StorageError> {
+    let storage = storage_backend(&app_handle).await?;
     
-    s3_uploader::delete_photo(&config, &photo_id)
+    trash::trash_photo(storage.as_ref(), &photo_id)
         .await
-        .map_err(|e| format!("Failed to delete photo: {}", e))?;
+        .map_err(|e| e.context("Failed to delete photo"))?;
     
     // Remove from database cache
     // TODO: Delete from database
     
-    Ok("Photo deleted successfully".to_string())
+    let state = app_handle.state::<AppState>();
+    state.url_cache.lock().await.forget(&photo_id);
+    
+    Ok("Photo moved to trash".to_string())
+}
+
+/// Deletes many photos at once: into the trash, or for good with `permanent`.
+/// Photos that could not be deleted are left in place and reported by key.
+#[tauri::command]
+async fn delete_photos(
+    photo_ids: Vec<String>,
+    permanent: Option<bool>,
+    app_handle: tauri::AppHandle,
+) -> Result<s3_uploader::DeleteReport, storage::StorageError> {
+    let storage = storage_backend(&app_handle).await?;
+    
+    let report = if permanent.unwrap_or(false) {
+        s3_uploader::delete_photos(storage.as_ref(), &photo_ids).await
+    } else {
+        trash::trash_photos(storage.as_ref(), &photo_ids).await
+    }
+    .map_err(|e| e.context("Failed to delete photos"))?;
+    
+    let state = app_handle.state::<AppState>();
+    let mut url_cache = state.url_cache.lock().await;
+    for photo_id in &report.deleted {
+        url_cache.forget(photo_id);
+    }
+    
+    Ok(report)
+}
+
+#[tauri::command]
+async fn list_trash(app_handle: tauri::AppHandle) -> Result<Vec<trash::TrashedPhoto>, storage::StorageError> {
+    let storage = storage_backend(&app_handle).await?;
+    
+    let entries = trash::list_trash(storage.as_ref())
+        .await
+        .map_err(|e| e.context("Failed to list trash"))?;
+    
+    // Trashed photos are still shown in the trash view
+    let state = app_handle.state::<AppState>();
+    let mut url_cache = state.url_cache.lock().await;
+    for entry in &entries {
+        url_cache.remember(&entry.photo);
+    }
+    
+    Ok(entries)
+}
+
+#[tauri::command]
+async fn restore_from_trash(
+    photo_id: String,
+    app_handle: tauri::AppHandle,
+) -> Result<models::PhotoMetadata, storage::StorageError> {
+    let storage = storage_backend(&app_handle).await?;
+    
+    trash::restore(storage.as_ref(), &photo_id)
+        .await
+        .map_err(|e| e.context("Failed to restore photo"))
+}
+
+/// Permanently deletes the given photos from the trash, or all of it.
+#[tauri::command]
+async fn empty_trash(
+    photo_ids: Option<Vec<String>>,
+    app_handle: tauri::AppHandle,
+) -> Result<s3_uploader::DeleteReport, storage::StorageError> {
+    let storage = storage_backend(&app_handle).await?;
+    
+    trash::empty_trash(storage.as_ref(), photo_ids)
+        .await
+        .map_err(|e| e.context("Failed to empty trash"))
+}
+
+#[tauri::command]
+async fn get_trash_settings(app_handle: tauri::AppHandle) -> Result<trash::TrashSettings, storage::StorageError> {
+    let storage = storage_backend(&app_handle).await?;
+    
+    trash::load_settings(storage.as_ref()).await
+}
+
+#[tauri::command]
+async fn set_trash_retention(
+    retention_days: u32,
+    app_handle: tauri::AppHandle,
+) -> Result<String, storage::StorageError> {
+    let storage = storage_backend(&app_handle).await?;
+    
+    trash::save_settings(storage.as_ref(), &trash::TrashSettings { retention_days })
+        .await
+        .map_err(|e| e.context("Failed to save trash settings"))?;
+    
+    Ok(format!("Trashed photos are kept for {} days", retention_days))
 }
 
 #[tauri::command]
 async fn get_cached_image_url(
-    _photo_id: String,
-    _size_type: String,
-) -> Result<Option<String>, String> {
-    // Return cached URL if available and recently accessed
-    // This reduces S3 GET requests
-    Ok(None) // TODO: Implement cache lookup
+    photo_id: String,
+    size_type: String,
+    app_handle: tauri::AppHandle,
+) -> Result<Option<String>, storage::StorageError> {
+    // Reuse a pre-signed URL until shortly before it expires
+    let state = app_handle.state::<AppState>();
+    let key = {
+        let url_cache = state.url_cache.lock().await;
+        if let Some(url) = url_cache.get(&photo_id, &size_type) {
+            return Ok(Some(url));
+        }
+        url_cache.key_for(&photo_id, &size_type)
+    };
+    
+    let Ok(storage) = storage_backend(&app_handle).await else {
+        return Ok(None);
+    };
+    
+    // Public buckets are served from the URLs stored in the metadata
+    if storage.public_url(&key).is_some() {
+        return Ok(None);
+    }
+    
+    let url = storage
+        .presign(&key, url_cache::PRESIGN_TTL)
+        .await
+        .map_err(|e| e.context("Failed to sign image URL"))?;
+    
+    state
+        .url_cache
+        .lock()
+        .await
+        .insert(&photo_id, &size_type, url.clone());
+    
+    Ok(Some(url))
+}
+
+/// Moves existing renditions to the storage classes currently configured.
+#[tauri::command]
+async fn retier_photos(app_handle: tauri::AppHandle) -> Result<tiering::RetierSummary, storage::StorageError> {
+    let storage = storage_backend(&app_handle).await?;
+    let config = storage_config(&app_handle).await?;
+    
+    let on_progress = |checked: usize| {
+        let _ = app_handle.emit("retier-progress", checked);
+    };
+    
+    tiering::retier_library(storage.as_ref(), &config, &on_progress)
+        .await
+        .map_err(|e| e.context("Failed to change storage classes"))
+}
+
+#[derive(Clone, serde::Serialize)]
+struct MigrationProgress {
+    done: usize,
+    total: usize,
+}
+
+/// Copies the library to another storage configuration. The current
+/// configuration stays in use; switch to the target once nothing failed.
+/// Running it again resumes an interrupted migration.
+#[tauri::command]
+async fn migrate_library(
+    target: models::StorageConfig,
+    app_handle: tauri::AppHandle,
+) -> Result<migration::MigrationSummary, storage::StorageError> {
+    let state = app_handle.state::<AppState>();
+    let sessions = app_handle.state::<upload_sessions::UploadSessionStore>();
+    
+    if !target.is_configured() {
+        return Err(storage::StorageError::new(
+            storage::ErrorKind::NotConfigured,
+            "Fill in all required settings of the target storage",
+        ));
+    }
+    
+    // Copied without client-side decryption, so encrypted libraries stay encrypted
+    let source = {
+        let mut storage = state.storage.lock().await;
+        if storage.config() == Some(&target) {
+            return Err(storage::StorageError::other("The target is the storage the library is already in"));
+        }
+        storage.connection(&sessions).await?
+    };
+    let target_storage = storage::connect(&target, &sessions).await?;
+    
+    let on_progress = |done: usize, total: usize| {
+        let _ = app_handle.emit("migration-progress", MigrationProgress { done, total });
+    };
+    
+    migration::migrate_library(source.as_ref(), target_storage.as_ref(), &target, &on_progress)
+        .await
+        .map_err(|e| e.context("Failed to migrate library"))
+}
+
+/// Verifies the library against its recorded checksums, in the scheduled
+/// mode unless `mode` is given.
+#[tauri::command]
+async fn scrub_library(
+    mode: Option<scrub::ScrubMode>,
+    app_handle: tauri::AppHandle,
+) -> Result<scrub::ScrubReport, storage::StorageError> {
+    let state = app_handle.state::<AppState>();
+    let Some(_running) = scrub::RunningScrub::start(&state.scrubbing) else {
+        return Err(storage::StorageError::other("A scrub is already running"));
+    };
+    
+    let storage = storage_backend(&app_handle).await?;
+    let mode = match mode {
+        Some(mode) => mode,
+        None => scrub::load_schedule(storage.as_ref()).await?.mode,
+    };
+    
+    let on_progress = |checked: usize| {
+        let _ = app_handle.emit("scrub-progress", checked);
+    };
+    
+    scrub::scrub_library(storage.as_ref(), mode, &on_progress)
+        .await
+        .map_err(|e| e.context("Failed to scrub library"))
+}
+
+#[tauri::command]
+async fn get_scrub_schedule(app_handle: tauri::AppHandle) -> Result<scrub::ScrubSchedule, storage::StorageError> {
+    let storage = storage_backend(&app_handle).await?;
+    
+    scrub::load_schedule(storage.as_ref()).await
+}
+
+#[tauri::command]
+async fn set_scrub_schedule(
+    interval_days: Option<u32>,
+    mode: scrub::ScrubMode,
+    app_handle: tauri::AppHandle,
+) -> Result<String, storage::StorageError> {
+    let storage = storage_backend(&app_handle).await?;
+    
+    let mut schedule = scrub::load_schedule(storage.as_ref()).await?;
+    schedule.interval_days = interval_days;
+    schedule.mode = mode;
+    scrub::save_schedule(storage.as_ref(), &schedule)
+        .await
+        .map_err(|e| e.context("Failed to save scrub schedule"))?;
+    
+    Ok("Scrub schedule saved".to_string())
+}
+
+#[tauri::command]
+async fn get_upload_limits(app_handle: tauri::AppHandle) -> Result<upload_limits::UploadLimits, String> {
+    Ok(app_handle.state::<upload_limits::UploadLimitsStore>().limits())
+}
+
+/// Sets the upload bandwidth cap and time windows for this device; the cap
+/// applies to uploads already running.
+#[tauri::command]
+async fn set_upload_limits(
+    limits: upload_limits::UploadLimits,
+    app_handle: tauri::AppHandle,
+) -> Result<String, String> {
+    app_handle
+        .state::<upload_limits::UploadLimitsStore>()
+        .set(limits)
+        .map_err(|e| format!("Failed to save upload limits: {}", e))?;
+    
+    Ok("Upload limits saved".to_string())
+}
+
+/// Object counts and bytes by tier, month and storage class, with the
+/// estimated monthly cost at the saved prices.
+#[tauri::command]
+async fn storage_usage(app_handle: tauri::AppHandle) -> Result<usage::UsageReport, storage::StorageError> {
+    let storage = storage_backend(&app_handle).await?;
+    
+    usage::usage_report(storage.as_ref())
+        .await
+        .map_err(|e| e.context("Failed to report storage usage"))
+}
+
+#[tauri::command]
+async fn set_pricing(pricing: usage::Pricing, app_handle: tauri::AppHandle) -> Result<String, storage::StorageError> {
+    let storage = storage_backend(&app_handle).await?;
+    
+    usage::save_pricing(storage.as_ref(), &pricing)
+        .await
+        .map_err(|e| e.context("Failed to save prices"))?;
+    
+    Ok("Prices saved".to_string())
+}
+
+/// Looks for orphaned renditions and photos with missing sizes.
+#[tauri::command]
+async fn check_library(app_handle: tauri::AppHandle) -> Result<fsck::FsckReport, storage::StorageError> {
+    let storage = storage_backend(&app_handle).await?;
+    let sessions = app_handle.state::<upload_sessions::UploadSessionStore>();
+    
+    fsck::check_library(storage.as_ref(), &sessions)
+        .await
+        .map_err(|e| e.context("Failed to check library"))
+}
+
+#[tauri::command]
+async fn repair_library(
+    repairs: Vec<fsck::Repair>,
+    app_handle: tauri::AppHandle,
+) -> Result<fsck::RepairReport, storage::StorageError> {
+    let storage = storage_backend(&app_handle).await?;
+    let config = storage_config(&app_handle).await?;
+    let sessions = app_handle.state::<upload_sessions::UploadSessionStore>();
+    
+    let report = fsck::repair_library(storage.as_ref(), &config, &sessions, repairs).await;
+    
+    let state = app_handle.state::<AppState>();
+    let mut url_cache = state.url_cache.lock().await;
+    for photo_id in &report.repaired {
+        url_cache.forget(photo_id);
+    }
+    
+    Ok(report)
+}
+
+/// Requests archived renditions of a photo back from Glacier; returns how
+/// many restores were requested.
+#[tauri::command]
+async fn restore_photo(
+    photo_id: String,
+    days: Option<u32>,
+    app_handle: tauri::AppHandle,
+) -> Result<usize, storage::StorageError> {
+    let storage = storage_backend(&app_handle).await?;
+    
+    tiering::restore_photo(
+        storage.as_ref(),
+        &photo_id,
+        days.unwrap_or(tiering::DEFAULT_RESTORE_DAYS),
+    )
+    .await
+    .map_err(|e| e.context("Failed to request restore"))
+}
+
+const MIN_PASSPHRASE_LEN: usize = 8;
+
+fn check_passphrase(passphrase: &str) -> Result<(), storage::StorageError> {
+    if passphrase.chars().count() < MIN_PASSPHRASE_LEN {
+        return Err(storage::StorageError::other(format!(
+            "The passphrase must be at least {} characters",
+            MIN_PASSPHRASE_LEN
+        )));
+    }
+    Ok(())
+}
+
+#[derive(Clone, serde::Serialize)]
+struct EncryptionStatus {
+    enabled: bool,
+    unlocked: bool,
+}
+
+#[tauri::command]
+async fn encryption_status(app_handle: tauri::AppHandle) -> Result<EncryptionStatus, storage::StorageError> {
+    let state = app_handle.state::<AppState>();
+    let mut storage = state.storage.lock().await;
+    let sessions = app_handle.state::<upload_sessions::UploadSessionStore>();
+    
+    Ok(EncryptionStatus {
+        enabled: storage.is_encrypted(&sessions).await?,
+        unlocked: storage.is_unlocked(),
+    })
+}
+
+/// Returns the recovery key, which is only ever shown this once.
+#[tauri::command]
+async fn enable_encryption(
+    passphrase: String,
+    app_handle: tauri::AppHandle,
+) -> Result<String, storage::StorageError> {
+    check_passphrase(&passphrase)?;
+    
+    let state = app_handle.state::<AppState>();
+    let mut storage = state.storage.lock().await;
+    let sessions = app_handle.state::<upload_sessions::UploadSessionStore>();
+    let connection = storage.connection(&sessions).await?;
+    
+    let (keys, recovery_key) = storage::encryption::enable(connection.as_ref(), &passphrase)
+        .await
+        .map_err(|e| e.context("Failed to enable encryption"))?;
+    storage.unlock(keys);
+    
+    Ok(recovery_key)
+}
+
+#[tauri::command]
+async fn unlock_library(
+    passphrase: String,
+    app_handle: tauri::AppHandle,
+) -> Result<String, storage::StorageError> {
+    let state = app_handle.state::<AppState>();
+    let mut storage = state.storage.lock().await;
+    let sessions = app_handle.state::<upload_sessions::UploadSessionStore>();
+    let connection = storage.connection(&sessions).await?;
+    
+    let keys = storage::encryption::unlock(connection.as_ref(), &passphrase).await?;
+    storage.unlock(keys);
+    
+    Ok("Library unlocked".to_string())
+}
+
+/// Replaces the library keys and returns the new recovery key. Objects are
+/// moved to the new key by `reencrypt_library`.
+#[tauri::command]
+async fn rotate_encryption_key(
+    passphrase: String,
+    new_passphrase: Option<String>,
+    app_handle: tauri::AppHandle,
+) -> Result<String, storage::StorageError> {
+    if let Some(new_passphrase) = &new_passphrase {
+        check_passphrase(new_passphrase)?;
+    }
+    
+    let state = app_handle.state::<AppState>();
+    let mut storage = state.storage.lock().await;
+    let sessions = app_handle.state::<upload_sessions::UploadSessionStore>();
+    let connection = storage.connection(&sessions).await?;
+    
+    let (keys, recovery_key) =
+        storage::encryption::rotate(connection.as_ref(), &passphrase, new_passphrase.as_deref())
+            .await
+            .map_err(|e| e.context("Failed to rotate keys"))?;
+    storage.unlock(keys);
+    
+    Ok(recovery_key)
+}
+
+#[tauri::command]
+async fn recover_library(
+    recovery_key: String,
+    new_passphrase: String,
+    app_handle: tauri::AppHandle,
+) -> Result<String, storage::StorageError> {
+    check_passphrase(&new_passphrase)?;
+    
+    let state = app_handle.state::<AppState>();
+    let mut storage = state.storage.lock().await;
+    let sessions = app_handle.state::<upload_sessions::UploadSessionStore>();
+    let connection = storage.connection(&sessions).await?;
+    
+    let keys = storage::encryption::recover(connection.as_ref(), &recovery_key, &new_passphrase).await?;
+    storage.unlock(keys);
+    
+    Ok("New passphrase set".to_string())
+}
+
+/// Encrypts objects stored before encryption was enabled and moves
+/// everything onto the current key after a rotation.
+#[tauri::command]
+async fn reencrypt_library(
+    app_handle: tauri::AppHandle,
+) -> Result<storage::encryption::ReencryptReport, storage::StorageError> {
+    let state = app_handle.state::<AppState>();
+    
+    // Don't hold the storage lock for the whole run
+    let (connection, keys) = {
+        let mut storage = state.storage.lock().await;
+        let sessions = app_handle.state::<upload_sessions::UploadSessionStore>();
+        let connection = storage.connection(&sessions).await?;
+        let keys = storage.keys().ok_or_else(|| {
+            storage::StorageError::new(storage::ErrorKind::Locked, "Unlock the library first")
+        })?;
+        (connection, keys)
+    };
+    
+    let on_progress = |processed: usize| {
+        let _ = app_handle.emit("encryption-progress", processed);
+    };
+    
+    let (keys, report) =
+        storage::encryption::reencrypt_library(connection.as_ref(), &keys, &on_progress)
+            .await
+            .map_err(|e| e.context("Failed to re-encrypt library"))?;
+    state.storage.lock().await.unlock(keys);
+    
+    Ok(report)
 }
 
 #[tauri::command]
@@ -351,10 +1021,7 @@ struct SyncStatus {
 #[tauri::command]
 async fn sync_config_to_drive(
     access_token: String,
-    bucket: String,
-    region: String,
-    access_key: String,
-    secret_key: String,
+    app_handle: tauri::AppHandle,
 ) -> Result<String, String> {
     use chrono::Utc;
     
@@ -365,11 +1032,13 @@ async fn sync_config_to_drive(
         return Err("Google Drive access not granted. Your settings will only be saved locally.".to_string());
     }
     
+    // The configuration as saved by `configure_s3`, with every setting
+    let storage = match storage_config(&app_handle).await.map_err(|e| e.to_string())? {
+        models::StorageConfig::S3(storage) => storage,
+        _ => return Err("Only S3 settings are synced to Google Drive".to_string()),
+    };
     let config = auth::CloudConfig {
-        bucket,
-        region,
-        access_key,
-        secret_key,
+        storage,
         last_updated: Utc::now().to_rfc3339(),
     };
     
@@ -433,18 +1102,70 @@ fn main() {
         .plugin(tauri_plugin_fs::init())
         .plugin(tauri_plugin_http::init())
         .plugin(tauri_plugin_sql::Builder::default().build())
+        .plugin(tauri_plugin_log::Builder::new().build())
         .manage(AppState {
             current_user_id: Arc::new(Mutex::new(None)),
+            url_cache: Arc::new(Mutex::new(url_cache::UrlCache::default())),
+            storage: Arc::new(Mutex::new(storage::ConnectedStorage::default())),
+            scrubbing: Arc::new(AtomicBool::new(false)),
+        })
+        // Objects the webview can't load from the provider are served from here
+        .register_asynchronous_uri_scheme_protocol(storage::LIBRARY_SCHEME, |ctx, request, responder| {
+            let app_handle = ctx.app_handle().clone();
+            tauri::async_runtime::spawn(async move {
+                let response = match storage_backend(&app_handle).await {
+                    Ok(storage) => library_protocol::serve(storage.as_ref(), &request).await,
+                    Err(error) => tauri::http::Response::builder()
+                        .status(tauri::http::StatusCode::SERVICE_UNAVAILABLE)
+                        .body(error.to_string().into_bytes())
+                        .unwrap_or_default(),
+                };
+                responder.respond(response);
+            });
+        })
+        .setup(|app| {
+            let sessions_path = upload_sessions::get_sessions_path(app.handle())?;
+            app.manage(upload_sessions::UploadSessionStore::load(sessions_path));
+            let limits_path = upload_limits::get_limits_path(app.handle())?;
+            app.manage(upload_limits::UploadLimitsStore::load(limits_path));
+            Ok(())
         })
         .invoke_handler(tauri::generate_handler![
             init_database,
             google_login,
             complete_oauth_flow,
             configure_s3,
+            configure_storage,
+            test_storage_connection,
             upload_photos,
             list_photos,
+            list_photos_page,
             delete_photo,
+            delete_photos,
+            list_trash,
+            restore_from_trash,
+            empty_trash,
+            get_trash_settings,
+            set_trash_retention,
             get_cached_image_url,
+            retier_photos,
+            migrate_library,
+            scrub_library,
+            get_scrub_schedule,
+            set_scrub_schedule,
+            check_library,
+            repair_library,
+            storage_usage,
+            set_pricing,
+            get_upload_limits,
+            set_upload_limits,
+            restore_photo,
+            encryption_status,
+            enable_encryption,
+            unlock_library,
+            rotate_encryption_key,
+            recover_library,
+            reencrypt_library,
             logout,
             sync_config_to_drive,
             load_config_from_drive,
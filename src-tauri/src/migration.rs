@@ -0,0 +1,480 @@
+use crate::image_processor;
+use crate::models::{PhotoMetadata, StorageConfig};
+use crate::s3_uploader;
+use crate::stacks;
+use crate::storage::{
+    self, encryption, ObjectInfo, ObjectSource, PutOptions, StorageBackend, StorageError, StorageResult,
+    IMMUTABLE_CACHE_CONTROL, SHORT_CACHE_CONTROL,
+};
+use crate::tiering;
+use crate::trash::TrashedPhoto;
+use futures::stream::{self, StreamExt};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use uuid::Uuid;
+
+// Objects copied in parallel
+const MIGRATION_CONCURRENCY: usize = 8;
+
+/// An object that could not be copied or did not verify.
+#[derive(Debug, Clone, Serialize)]
+pub struct CopyFailure {
+    pub key: String,
+    pub error: StorageError,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct MigrationSummary {
+    pub total: usize,
+    pub copied: usize,
+    /// Already at the target from an earlier run
+    pub skipped: usize,
+    pub failed: Vec<CopyFailure>,
+}
+
+struct Migration<'a> {
+    source: &'a dyn StorageBackend,
+    target: &'a dyn StorageBackend,
+    target_config: &'a StorageConfig,
+    /// Size of every object already at the target
+    existing: HashMap<String, u64>,
+    /// Encrypted libraries are copied byte for byte; their URLs are empty
+    /// and their metadata can't be read without the keys anyway
+    encrypted: bool,
+}
+
+enum Copied {
+    Written,
+    Skipped,
+}
+
+/// Copies the whole library from `source` to `target`, rewriting photo URLs
+/// for the target. Objects already at the target with the expected size, and
+/// the recorded checksum where the target keeps one, are skipped, so an
+/// interrupted migration picks up where it stopped. Objects are copied
+/// through temporary files rather than memory, and every written object is
+/// checked against the target before it counts as copied.
+///
+/// The library index is not copied; the target rebuilds it from the
+/// metadata on first load.
+pub async fn migrate_library(
+    source: &dyn StorageBackend,
+    target: &dyn StorageBackend,
+    target_config: &StorageConfig,
+    on_progress: &(dyn Fn(usize, usize) + Send + Sync),
+) -> StorageResult<MigrationSummary> {
+    let encrypted = encryption::has_keyring(source).await?;
+    let existing = storage::list_all(target, "")
+        .await?
+        .into_iter()
+        .map(|object| (object.key, object.size))
+        .collect();
+
+    let migration = Migration {
+        source,
+        target,
+        target_config,
+        existing,
+        encrypted,
+    };
+
+    // Encrypted index shards can't be rebuilt without the keys, so they are
+    // copied like everything else
+    let objects = storage::list_all(source, "")
+        .await?
+        .into_iter()
+        .filter(|object| encrypted || !object.key.starts_with("index/"))
+        .filter(|object| !object.key.starts_with("probes/"));
+    let (renditions, others): (Vec<ObjectInfo>, Vec<ObjectInfo>) =
+        objects.partition(|object| object.key.starts_with("photos/"));
+
+    let mut summary = MigrationSummary {
+        total: renditions.len() + others.len(),
+        ..Default::default()
+    };
+    let mut done = 0;
+
+    // Metadata goes first, so renditions can keep their download names
+    let mut photos: HashMap<String, PhotoMetadata> = HashMap::new();
+    let mut copies = stream::iter(others)
+        .map(|object| {
+            let migration = &migration;
+            async move {
+                let result = migration.copy_document(&object).await;
+                (object.key, result)
+            }
+        })
+        .buffer_unordered(MIGRATION_CONCURRENCY);
+
+    while let Some((key, result)) = copies.next().await {
+        let result = result.map(|(copied, photo)| {
+            if let Some(photo) = photo {
+                photos.insert(photo.id.clone(), photo);
+            }
+            copied
+        });
+        summary.record(key, result);
+        done += 1;
+        on_progress(done, summary.total);
+    }
+
+    let mut copies = stream::iter(renditions)
+        .map(|object| {
+            let migration = &migration;
+            let photo = s3_uploader::photo_id_of(&object.key).and_then(|id| photos.get(id));
+            async move {
+                let result = migration.copy_rendition(&object, photo).await;
+                (object.key, result)
+            }
+        })
+        .buffer_unordered(MIGRATION_CONCURRENCY);
+
+    while let Some((key, result)) = copies.next().await {
+        summary.record(key, result);
+        done += 1;
+        on_progress(done, summary.total);
+    }
+
+    Ok(summary)
+}
+
+impl MigrationSummary {
+    fn record(&mut self, key: String, result: StorageResult<Copied>) {
+        match result {
+            Ok(Copied::Written) => self.copied += 1,
+            Ok(Copied::Skipped) => self.skipped += 1,
+            Err(error) => self.failed.push(CopyFailure { key, error }),
+        }
+    }
+}
+
+impl Migration<'_> {
+    fn is_copied(&self, key: &str, size: u64) -> bool {
+        self.existing.get(key) == Some(&size)
+    }
+
+    // A target object of the right size can still be a different version,
+    // e.g. from a run against a source that has changed since
+    async fn is_rendition_copied(&self, key: &str, size: u64, expected: Option<&String>) -> StorageResult<bool> {
+        if !self.is_copied(key, size) {
+            return Ok(false);
+        }
+        let Some(expected) = expected else {
+            return Ok(true);
+        };
+
+        Ok(match self.target.stored_sha256(key).await? {
+            Some(stored) => stored == *expected,
+            None => true,
+        })
+    }
+
+    /// Copies a non-rendition object, returning the rewritten photo for
+    /// metadata and trash entries.
+    async fn copy_document(&self, object: &ObjectInfo) -> StorageResult<(Copied, Option<PhotoMetadata>)> {
+        let key = object.key.as_str();
+        let rewritable = !self.encrypted && (key.starts_with("metadata/") || key.starts_with("trash/"));
+
+        if !rewritable {
+            if self.is_copied(key, object.size) {
+                return Ok((Copied::Skipped, None));
+            }
+
+            let path = temp_path();
+            let result = self.copy_file(key, &path).await;
+            let _ = tokio::fs::remove_file(&path).await;
+            return result.map(|()| (Copied::Written, None));
+        }
+
+        // Rewritten objects change size, so they are always read. They are
+        // small JSON documents and are rewritten in memory.
+        let object = self
+            .source
+            .get(key)
+            .await?
+            .ok_or_else(|| disappeared(key))?;
+        let (data, photo) = self.rewrite(key, &object.data)?;
+
+        if self.is_copied(key, data.len() as u64) {
+            return Ok((Copied::Skipped, Some(photo)));
+        }
+
+        let options = PutOptions {
+            cache_control: Some(SHORT_CACHE_CONTROL.to_string()),
+            ..PutOptions::content_type("application/json")
+        };
+        self.write(key, ObjectSource::Bytes(&data), data.len() as u64, &options).await?;
+
+        Ok((Copied::Written, Some(photo)))
+    }
+
+    async fn copy_file(&self, key: &str, path: &Path) -> StorageResult<()> {
+        let (size, content_type) = self.download(key, path).await?;
+        let options = PutOptions {
+            cache_control: key.ends_with(".json").then(|| SHORT_CACHE_CONTROL.to_string()),
+            ..PutOptions::content_type(&content_type)
+        };
+
+        self.write(key, ObjectSource::File(path), size, &options).await
+    }
+
+    async fn copy_rendition(&self, object: &ObjectInfo, photo: Option<&PhotoMetadata>) -> StorageResult<Copied> {
+        let key = object.key.as_str();
+        let size_name = tiering::size_name_of(key);
+        let expected = photo.and_then(|photo| photo.checksums.get(size_name));
+
+        if self.is_rendition_copied(key, object.size, expected).await? {
+            return Ok(Copied::Skipped);
+        }
+
+        let path = temp_path();
+        let result = self.copy_rendition_file(key, &path, expected, photo).await;
+        let _ = tokio::fs::remove_file(&path).await;
+
+        result.map(|()| Copied::Written)
+    }
+
+    async fn copy_rendition_file(
+        &self,
+        key: &str,
+        path: &Path,
+        expected: Option<&String>,
+        photo: Option<&PhotoMetadata>,
+    ) -> StorageResult<()> {
+        let (size, content_type) = self.download(key, path).await?;
+        let size_name = tiering::size_name_of(key);
+        let sha256 = ObjectSource::File(path).sha256().await?;
+
+        // Don't spread a damaged source object to the new storage
+        if expected.is_some_and(|expected| *expected != sha256) {
+            return Err(StorageError::other(format!(
+                "{} does not match its recorded checksum at the source",
+                key
+            )));
+        }
+
+        let options = if self.encrypted {
+            PutOptions {
+                storage_class: self.target_config.storage_class_for(size_name),
+                cache_control: Some(IMMUTABLE_CACHE_CONTROL.to_string()),
+                ..PutOptions::content_type(&content_type)
+            }
+        } else {
+            let download_name = photo.and_then(|photo| match size_name {
+                "original" => Some(photo.original_name.as_str()),
+                "secondary" => photo.stack.as_ref().map(|stack| stack.secondary.name.as_str()),
+                _ => None,
+            });
+            let photo_id = s3_uploader::photo_id_of(key).unwrap_or_default();
+
+            s3_uploader::rendition_options(
+                self.target_config,
+                photo_id,
+                size_name,
+                &content_type,
+                &sha256,
+                download_name,
+            )
+        };
+
+        self.write(key, ObjectSource::File(path), size, &options).await
+    }
+
+    // Saves the source object to `path`, returning its size and content type
+    async fn download(&self, key: &str, path: &Path) -> StorageResult<(u64, String)> {
+        let object = self
+            .source
+            .download(key, path)
+            .await?
+            .ok_or_else(|| disappeared(key))?;
+        let content_type = object
+            .content_type
+            .unwrap_or_else(|| stacks::content_type_for(key).to_string());
+
+        Ok((object.size, content_type))
+    }
+
+    // Writes the object and checks that the target stored all of it
+    async fn write(&self, key: &str, body: ObjectSource<'_>, size: u64, options: &PutOptions) -> StorageResult<()> {
+        self.target.put(key, body, options).await?;
+
+        let stored = self.target.head(key).await?.map(|info| info.size);
+        if stored != Some(size) {
+            return Err(StorageError::other(format!(
+                "{} did not verify: wrote {} bytes, the target has {}",
+                key,
+                size,
+                stored.map_or_else(|| "nothing".to_string(), |size| format!("{} bytes", size))
+            )));
+        }
+
+        Ok(())
+    }
+
+    fn rewrite(&self, key: &str, data: &[u8]) -> StorageResult<(Vec<u8>, PhotoMetadata)> {
+        if key.starts_with("trash/") {
+            let mut entry: TrashedPhoto = serde_json::from_slice(data)?;
+            rewrite_urls(&mut entry.photo, self.target);
+            Ok((serde_json::to_vec(&entry)?, entry.photo))
+        } else {
+            let mut photo: PhotoMetadata = serde_json::from_slice(data)?;
+            rewrite_urls(&mut photo, self.target);
+            Ok((serde_json::to_vec(&photo)?, photo))
+        }
+    }
+}
+
+/// Points every URL of a photo at `target`.
+fn rewrite_urls(photo: &mut PhotoMetadata, target: &dyn StorageBackend) {
+    // Metadata written before `object_keys` only has the renditions that
+    // were generated for its dimensions
+    if photo.object_keys.is_empty() {
+        for size_name in image_processor::size_names_for(photo.width, photo.height) {
+            photo
+                .object_keys
+                .insert(size_name.to_string(), format!("photos/{}/{}.jpg", photo.id, size_name));
+        }
+    }
+
+    for (size_name, key) in photo.object_keys.clone() {
+        photo.set_url(&size_name, target.public_url(&key).unwrap_or_default());
+    }
+
+    if let Some(stack) = &mut photo.stack {
+        if let Some(key) = photo.object_keys.get("original").filter(|_| stack.primary.key.is_empty()) {
+            stack.primary.key = key.clone();
+        }
+        stack.primary.url = target.public_url(&stack.primary.key).unwrap_or_default();
+        if let Some(key) = photo.object_keys.get("secondary") {
+            stack.secondary.key = key.clone();
+        }
+        stack.secondary.url = target.public_url(&stack.secondary.key).unwrap_or_default();
+    }
+}
+
+fn temp_path() -> PathBuf {
+    std::env::temp_dir().join(format!("galleria-migration-{}", Uuid::new_v4()))
+}
+
+fn disappeared(key: &str) -> StorageError {
+    StorageError::other(format!("{} disappeared from the source", key))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::fixtures;
+    use crate::storage::MemoryBackend;
+
+    // A photo with one rendition, its checksum recorded in the metadata
+    async fn add_photo(storage: &MemoryBackend, rendition: &[u8]) {
+        let sha256 = ObjectSource::Bytes(rendition).sha256().await.unwrap();
+        let photo = PhotoMetadata {
+            file_size: rendition.len() as u64,
+            object_keys: [("original".to_string(), "photos/a1/original.jpg".to_string())].into(),
+            checksums: [("original".to_string(), sha256)].into(),
+            ..fixtures::photo("a1")
+        };
+
+        storage
+            .put("photos/a1/original.jpg", ObjectSource::Bytes(rendition), &PutOptions::content_type("image/jpeg"))
+            .await
+            .unwrap();
+        fixtures::write_metadata(storage, &photo).await;
+    }
+
+    async fn migrate(source: &MemoryBackend, target: &MemoryBackend) -> MigrationSummary {
+        migrate_library(source, target, &StorageConfig::Memory, &|_, _| {})
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn second_run_skips_copied_objects() {
+        let (source, target) = (MemoryBackend::default(), MemoryBackend::default());
+        add_photo(&source, b"original").await;
+
+        let summary = migrate(&source, &target).await;
+        assert_eq!((summary.copied, summary.skipped), (2, 0));
+        assert_eq!(target.get("photos/a1/original.jpg").await.unwrap().unwrap().data, b"original");
+
+        let summary = migrate(&source, &target).await;
+        assert_eq!((summary.copied, summary.skipped), (0, 2));
+    }
+
+    #[tokio::test]
+    async fn same_size_object_with_another_checksum_is_copied_again() {
+        let (source, target) = (MemoryBackend::default(), MemoryBackend::default());
+        add_photo(&source, b"original").await;
+        migrate(&source, &target).await;
+
+        // Left behind by an earlier version of the photo, same length
+        target
+            .put("photos/a1/original.jpg", ObjectSource::Bytes(b"outdated"), &PutOptions::content_type("image/jpeg"))
+            .await
+            .unwrap();
+
+        let summary = migrate(&source, &target).await;
+        assert_eq!((summary.copied, summary.skipped), (1, 1));
+        assert_eq!(target.get("photos/a1/original.jpg").await.unwrap().unwrap().data, b"original");
+    }
+
+    #[tokio::test]
+    async fn damaged_source_object_is_not_copied() {
+        let (source, target) = (MemoryBackend::default(), MemoryBackend::default());
+        add_photo(&source, b"original").await;
+        source
+            .put("photos/a1/original.jpg", ObjectSource::Bytes(b"damaged!"), &PutOptions::content_type("image/jpeg"))
+            .await
+            .unwrap();
+
+        let summary = migrate(&source, &target).await;
+        assert_eq!(summary.failed.len(), 1);
+        assert_eq!(summary.failed[0].key, "photos/a1/original.jpg");
+        assert!(target.head("photos/a1/original.jpg").await.unwrap().is_none());
+    }
+
+    async fn migrated_photo(target: &MemoryBackend) -> PhotoMetadata {
+        serde_json::from_slice(&target.get("metadata/a1.json").await.unwrap().unwrap().data).unwrap()
+    }
+
+    #[tokio::test]
+    async fn only_stored_renditions_get_keys_and_urls() {
+        let (source, target) = (MemoryBackend::default(), MemoryBackend::default());
+        add_photo(&source, b"original").await;
+        migrate(&source, &target).await;
+
+        let photo = migrated_photo(&target).await;
+        assert_eq!(photo.object_keys.keys().collect::<Vec<_>>(), ["original"]);
+        assert!(photo.large_url.is_empty());
+    }
+
+    #[tokio::test]
+    async fn metadata_without_object_keys_gets_the_sizes_of_its_dimensions() {
+        let (source, target) = (MemoryBackend::default(), MemoryBackend::default());
+        add_photo(&source, b"original").await;
+
+        // Written before `object_keys`, by a version that made no `large` below 1920 pixels
+        let mut photo: PhotoMetadata =
+            serde_json::from_slice(&source.get("metadata/a1.json").await.unwrap().unwrap().data).unwrap();
+        photo.object_keys.clear();
+        photo.width = 1600;
+        photo.height = 1200;
+        source
+            .put(
+                "metadata/a1.json",
+                ObjectSource::Bytes(&serde_json::to_vec(&photo).unwrap()),
+                &PutOptions::content_type("application/json"),
+            )
+            .await
+            .unwrap();
+        migrate(&source, &target).await;
+
+        let photo = migrated_photo(&target).await;
+        assert_eq!(
+            photo.object_keys.keys().collect::<Vec<_>>(),
+            ["medium", "original", "small", "thumbnail"]
+        );
+    }
+}
@@ -1,11 +1,232 @@
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct S3Config {
     pub bucket: String,
     pub region: String,
+    #[serde(default)]
     pub access_key: String,
+    #[serde(default)]
     pub secret_key: String,
+    /// Session token of temporary keys, e.g. from `aws sts get-session-token`
+    #[serde(default)]
+    pub session_token: Option<String>,
+    /// Where credentials come from; the keys above are only used for `Keys`
+    #[serde(default)]
+    pub credentials: CredentialSource,
+    /// Role assumed with those credentials before accessing the bucket
+    #[serde(default)]
+    pub assume_role: Option<AssumeRole>,
+    /// Custom endpoint for S3-compatible providers (MinIO, Cloudflare R2, ...)
+    #[serde(default)]
+    pub endpoint_url: Option<String>,
+    #[serde(default)]
+    pub force_path_style: bool,
+    /// Base URL that objects are served from, e.g. a CDN or R2 public domain
+    #[serde(default)]
+    pub public_base_url: Option<String>,
+    /// Objects are not publicly readable and are served via pre-signed URLs
+    #[serde(default)]
+    pub private_bucket: bool,
+    /// Server-side encryption applied to every uploaded object
+    #[serde(default)]
+    pub encryption: Option<ServerSideEncryption>,
+    /// Storage class by rendition size name (`original`, `thumbnail`,
+    /// `secondary`, ...); renditions not listed are stored as Standard
+    #[serde(default)]
+    pub storage_classes: BTreeMap<String, StorageClass>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "source", rename_all = "snake_case")]
+pub enum CredentialSource {
+    /// `access_key` and `secret_key`, plus `session_token` for temporary keys
+    #[default]
+    Keys,
+    /// A profile from `~/.aws/config` and `~/.aws/credentials`, including
+    /// SSO, `credential_process` and role profiles. `None` is the default
+    /// profile, or the one named by `AWS_PROFILE`.
+    Profile {
+        #[serde(default)]
+        name: Option<String>,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AssumeRole {
+    pub role_arn: String,
+    /// Required by roles that trust another account with an external id
+    #[serde(default)]
+    pub external_id: Option<String>,
+    /// Shown in CloudTrail; defaults to `galleria`
+    #[serde(default)]
+    pub session_name: Option<String>,
+}
+
+/// S3 storage classes, named as in the S3 API.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum StorageClass {
+    Standard,
+    StandardIa,
+    OnezoneIa,
+    IntelligentTiering,
+    GlacierIr,
+    /// Glacier Flexible Retrieval
+    Glacier,
+    DeepArchive,
+}
+
+impl StorageClass {
+    const ALL: [StorageClass; 7] = [
+        StorageClass::Standard,
+        StorageClass::StandardIa,
+        StorageClass::OnezoneIa,
+        StorageClass::IntelligentTiering,
+        StorageClass::GlacierIr,
+        StorageClass::Glacier,
+        StorageClass::DeepArchive,
+    ];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            StorageClass::Standard => "STANDARD",
+            StorageClass::StandardIa => "STANDARD_IA",
+            StorageClass::OnezoneIa => "ONEZONE_IA",
+            StorageClass::IntelligentTiering => "INTELLIGENT_TIERING",
+            StorageClass::GlacierIr => "GLACIER_IR",
+            StorageClass::Glacier => "GLACIER",
+            StorageClass::DeepArchive => "DEEP_ARCHIVE",
+        }
+    }
+
+    pub fn parse(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|class| class.as_str() == name)
+    }
+
+    /// Objects in these classes have to be restored before they can be read.
+    pub fn is_archived(self) -> bool {
+        matches!(self, StorageClass::Glacier | StorageClass::DeepArchive)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "mode", rename_all = "snake_case")]
+pub enum ServerSideEncryption {
+    /// SSE-S3: AES-256 with keys managed by S3
+    S3,
+    /// SSE-KMS: the bucket's default KMS key unless `key_id` is set
+    Kms {
+        #[serde(default)]
+        key_id: Option<String>,
+    },
+    /// SSE-C: a base64 encoded 256-bit key that S3 never stores, so it must
+    /// be sent with every read as well
+    Customer { key: String },
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct GcsConfig {
+    pub bucket: String,
+    /// Service account key file contents; omitted for the fake-gcs-server emulator
+    #[serde(default)]
+    pub service_account_json: Option<String>,
+    /// Custom endpoint, e.g. `http://localhost:4443` for fake-gcs-server
+    #[serde(default)]
+    pub endpoint_url: Option<String>,
+    #[serde(default)]
+    pub private_bucket: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AzureConfig {
+    pub account: String,
+    /// Base64 storage account key
+    pub access_key: String,
+    pub container: String,
+    /// Custom endpoint, e.g. `http://127.0.0.1:10000/devstoreaccount1` for Azurite
+    #[serde(default)]
+    pub endpoint_url: Option<String>,
+    #[serde(default)]
+    pub private_container: bool,
+}
+
+/// Storage the library is kept in, selected per library.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[allow(clippy::large_enum_variant)]
+#[serde(tag = "provider", rename_all = "snake_case")]
+pub enum StorageConfig {
+    S3(S3Config),
+    Gcs(GcsConfig),
+    Azure(AzureConfig),
+    /// A directory on this machine, for offline libraries
+    Local { root: String },
+    /// A WebDAV collection, e.g. on Nextcloud or a NAS
+    #[serde(rename = "webdav")]
+    WebDav {
+        url: String,
+        username: String,
+        password: String,
+    },
+    /// Kept in memory until the app exits
+    Memory,
+}
+
+impl StorageConfig {
+    /// Whether every setting needed to connect is filled in.
+    pub fn is_configured(&self) -> bool {
+        match self {
+            StorageConfig::S3(config) => config.is_configured(),
+            StorageConfig::Gcs(config) => !config.bucket.is_empty(),
+            StorageConfig::Azure(config) => {
+                !config.account.is_empty() && !config.access_key.is_empty() && !config.container.is_empty()
+            }
+            StorageConfig::Local { root } => !root.is_empty(),
+            StorageConfig::WebDav { url, .. } => !url.is_empty(),
+            StorageConfig::Memory => true,
+        }
+    }
+
+    /// Identifies the library the config points at: the provider, where it
+    /// is reached and the bucket, container or directory, without secrets.
+    pub fn library_identity(&self) -> String {
+        match self {
+            StorageConfig::S3(config) => format!(
+                "s3:{}:{}",
+                config.endpoint_url.as_deref().unwrap_or(&config.region),
+                config.bucket
+            ),
+            StorageConfig::Gcs(config) => format!(
+                "gcs:{}:{}",
+                config.endpoint_url.as_deref().unwrap_or(""),
+                config.bucket
+            ),
+            StorageConfig::Azure(config) => format!(
+                "azure:{}:{}:{}",
+                config.endpoint_url.as_deref().unwrap_or(""),
+                config.account,
+                config.container
+            ),
+            StorageConfig::Local { root } => format!("local:{}", root),
+            StorageConfig::WebDav { url, .. } => format!("webdav:{}", url),
+            StorageConfig::Memory => "memory".to_string(),
+        }
+    }
+
+    /// Storage class for a rendition; only S3 libraries have storage classes.
+    pub fn storage_class_for(&self, size_name: &str) -> Option<StorageClass> {
+        match self {
+            StorageConfig::S3(config) => Some(
+                config
+                    .storage_classes
+                    .get(size_name)
+                    .copied()
+                    .unwrap_or(StorageClass::Standard),
+            ),
+            _ => None,
+        }
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -21,6 +242,63 @@ pub struct PhotoMetadata {
     pub original_url: String,
     pub width: u32,
     pub height: u32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stack: Option<PhotoStack>,
+    /// Object key of every stored rendition, by size name. For private
+    /// buckets the `*_url` fields are left empty and these keys are signed
+    /// on demand instead.
+    #[serde(default)]
+    pub object_keys: BTreeMap<String, String>,
+    /// Hex SHA-256 of every rendition as uploaded, by size name. Empty for
+    /// photos uploaded before checksums were recorded.
+    #[serde(default)]
+    pub checksums: BTreeMap<String, String>,
+}
+
+impl PhotoMetadata {
+    /// Sets the `*_url` field of a rendition; other size names have no field.
+    pub fn set_url(&mut self, size_name: &str, url: String) {
+        match size_name {
+            "thumbnail" => self.thumbnail_url = url,
+            "small" => self.small_url = url,
+            "medium" => self.medium_url = url,
+            "large" => self.large_url = url,
+            "original" => self.original_url = url,
+            _ => {}
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PhotoPage {
+    pub photos: Vec<PhotoMetadata>,
+    pub next_cursor: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StackKind {
+    LivePhoto,
+    RawPlusJpeg,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StackAsset {
+    pub name: String,
+    #[serde(default)]
+    pub key: String,
+    pub url: String,
+    pub content_type: String,
+    pub file_size: u64,
+}
+
+/// Files that were imported together under a single photo id, e.g. a
+/// Live Photo still and its video, or a JPEG and its RAW negative.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PhotoStack {
+    pub kind: StackKind,
+    pub primary: StackAsset,
+    pub secondary: StackAsset,
 }
 
 #[derive(Debug, Clone)]
@@ -32,12 +310,57 @@ pub struct CompressedImage {
 }
 
 impl S3Config {
-    #[allow(dead_code)]
     pub fn is_configured(&self) -> bool {
-        !self.bucket.is_empty()
-            && !self.region.is_empty()
-            && !self.access_key.is_empty()
-            && !self.secret_key.is_empty()
+        let has_credentials = match self.credentials {
+            CredentialSource::Keys => !self.access_key.is_empty() && !self.secret_key.is_empty(),
+            CredentialSource::Profile { .. } => true,
+        };
+        let has_role = self
+            .assume_role
+            .as_ref()
+            .is_none_or(|role| !role.role_arn.is_empty());
+
+        !self.bucket.is_empty() && !self.region.is_empty() && has_credentials && has_role
     }
 }
 
+
+/// Photos for the tests of the modules that read and write the library.
+#[cfg(test)]
+pub mod fixtures {
+    use super::PhotoMetadata;
+    use crate::storage::{ObjectSource, PutOptions, StorageBackend};
+    use chrono::Utc;
+
+    /// A 100x100 photo uploaded now, with no objects recorded yet.
+    pub fn photo(photo_id: &str) -> PhotoMetadata {
+        PhotoMetadata {
+            id: photo_id.to_string(),
+            original_name: format!("{}.jpg", photo_id),
+            upload_date: Utc::now().to_rfc3339(),
+            file_size: 3,
+            thumbnail_url: String::new(),
+            small_url: String::new(),
+            medium_url: String::new(),
+            large_url: String::new(),
+            original_url: String::new(),
+            width: 100,
+            height: 100,
+            stack: None,
+            object_keys: Default::default(),
+            checksums: Default::default(),
+        }
+    }
+
+    /// Stores the metadata object of `photo`.
+    pub async fn write_metadata(storage: &dyn StorageBackend, photo: &PhotoMetadata) {
+        storage
+            .put(
+                &format!("metadata/{}.json", photo.id),
+                ObjectSource::Bytes(&serde_json::to_vec(photo).unwrap()),
+                &PutOptions::content_type("application/json"),
+            )
+            .await
+            .unwrap();
+    }
+}
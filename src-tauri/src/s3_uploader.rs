@@ -1,18 +1,139 @@
-use crate::models::{CompressedImage, PhotoMetadata, S3Config};
-use aws_config::meta::region::RegionProviderChain;
-use aws_config::BehaviorVersion;
-use aws_sdk_s3::{Client, primitives::ByteStream};
+use crate::library_index;
+use crate::models::{CompressedImage, PhotoMetadata, PhotoPage, PhotoStack, StackAsset, StorageConfig};
+use crate::image_processor;
+use crate::stacks::{self, Companion, ImportItem};
+use crate::storage::{
+    self, DeleteFailure, ErrorKind, ObjectSource, PutOptions, StorageBackend, StorageError, StorageResult,
+    IMMUTABLE_CACHE_CONTROL, SHORT_CACHE_CONTROL,
+};
+use crate::upload_sessions::{self, UploadSessionStore};
 use chrono::Utc;
-use uuid::Uuid;
+use futures::stream::{self, StreamExt};
+use serde::Serialize;
+use std::collections::{BTreeMap, HashSet};
+use std::path::Path;
+
+// Metadata objects fetched in parallel while listing
+const METADATA_FETCH_CONCURRENCY: usize = 16;
+
+/// Options for a rendition or stacked asset of a photo: cached forever,
+/// tagged with the photo id, size name and checksum, and saved under
+/// `download_name` when given.
+pub fn rendition_options(
+    config: &StorageConfig,
+    photo_id: &str,
+    size_name: &str,
+    content_type: &str,
+    sha256: &str,
+    download_name: Option<&str>,
+) -> PutOptions {
+    let metadata = BTreeMap::from([
+        ("photo_id".to_string(), photo_id.to_string()),
+        ("size_name".to_string(), size_name.to_string()),
+        ("sha256".to_string(), sha256.to_string()),
+    ]);
+
+    PutOptions {
+        storage_class: config.storage_class_for(size_name),
+        cache_control: Some(IMMUTABLE_CACHE_CONTROL.to_string()),
+        content_disposition: download_name.map(content_disposition),
+        metadata,
+        ..PutOptions::content_type(content_type)
+    }
+}
+
+// `inline` so the webview still displays it; the ASCII fallback is for
+// clients that ignore `filename*`
+fn content_disposition(file_name: &str) -> String {
+    let fallback: String = file_name
+        .chars()
+        .map(|c| match c {
+            '"' | '\\' => '_',
+            ' ' => c,
+            _ if c.is_ascii_graphic() => c,
+            _ => '_',
+        })
+        .collect();
+
+    format!(
+        "inline; filename=\"{}\"; filename*=UTF-8''{}",
+        fallback,
+        urlencoding::encode(file_name)
+    )
+}
+
+/// File name a `Content-Disposition` header names, preferring the UTF-8
+/// `filename*` over the ASCII fallback.
+pub fn download_name_of(disposition: &str) -> Option<String> {
+    let params: Vec<(&str, &str)> = disposition
+        .split(';')
+        .filter_map(|param| param.split_once('='))
+        .map(|(name, value)| (name.trim(), value.trim()))
+        .collect();
+
+    let encoded = params
+        .iter()
+        .find(|(name, _)| name.eq_ignore_ascii_case("filename*"))
+        .and_then(|(_, value)| value.strip_prefix("UTF-8''"))
+        .and_then(|value| urlencoding::decode(value).ok())
+        .map(|name| name.into_owned());
+    let fallback = || {
+        params
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case("filename"))
+            .map(|(_, value)| value.trim_matches('"').to_string())
+    };
+
+    encoded.or_else(fallback).filter(|name| !name.is_empty())
+}
+
+/// A file of an upload that could not be imported.
+#[derive(Debug, Serialize)]
+pub struct UploadFailure {
+    pub file: String,
+    pub error: StorageError,
+}
+
+/// Renditions of an import item's still. When the still can't be read the
+/// whole group fails with it, so its video, RAW and sidecars are reported
+/// rather than dropped.
+pub fn process_item(item: &ImportItem) -> Result<Vec<CompressedImage>, Vec<UploadFailure>> {
+    image_processor::process_image(&item.primary).map_err(|e| {
+        let still_name = file_name_of(&item.primary);
+        item.files()
+            .map(|file| UploadFailure {
+                file: file.clone(),
+                error: if *file == item.primary {
+                    StorageError::other(format!("Failed to process image: {}", e))
+                } else {
+                    StorageError::other(format!("Not uploaded because {} could not be processed: {}", still_name, e))
+                },
+            })
+            .collect()
+    })
+}
+
+fn file_name_of(path: &str) -> String {
+    Path::new(path)
+        .file_name()
+        .and_then(|n| n.to_str())
+        .unwrap_or("unknown")
+        .to_string()
+}
 
 pub async fn upload_to_s3(
-    config: &S3Config,
+    storage: &dyn StorageBackend,
+    config: &StorageConfig,
+    sessions: &UploadSessionStore,
     original_file_path: &str,
     compressed_images: Vec<CompressedImage>,
-) -> Result<PhotoMetadata, Box<dyn std::error::Error>> {
-    let client = create_s3_client(config).await?;
+    companion: Option<&Companion>,
+    sidecars: &[String],
+) -> StorageResult<PhotoMetadata> {
+    // Reuse the photo id of an interrupted upload of the same file
+    let fingerprint = upload_sessions::fingerprint(&config.library_identity(), original_file_path)?;
+    let photo_id = sessions.photo_id_for(&fingerprint)?;
     
-    let photo_id = Uuid::new_v4().to_string();
     let original_name = std::path::Path::new(original_file_path)
         .file_name()
         .and_then(|n| n.to_str())
@@ -31,88 +152,149 @@ pub async fn upload_to_s3(
         original_url: String::new(),
         width: 0,
         height: 0,
+        stack: None,
+        object_keys: Default::default(),
+        checksums: Default::default(),
     };
     
     // Upload all compressed versions
     for compressed in compressed_images {
         let key = format!("photos/{}/{}.jpg", photo_id, compressed.size_name);
+        let source = ObjectSource::Bytes(&compressed.data);
+        // Hashed even when resuming, so the metadata has every checksum
+        let sha256 = source.sha256().await?;
+        
+        if !sessions.is_uploaded(&fingerprint, &key) {
+            let download_name = (compressed.size_name == "original").then_some(metadata.original_name.as_str());
+            let options = rendition_options(
+                config,
+                &photo_id,
+                &compressed.size_name,
+                "image/jpeg",
+                &sha256,
+                download_name,
+            );
+            storage.put(&key, source, &options).await?;
+            sessions.mark_uploaded(&fingerprint, &key)?;
+        }
         
-        client
-            .put_object()
-            .bucket(&config.bucket)
-            .key(&key)
-            .body(ByteStream::from(compressed.data.clone()))
-            .content_type("image/jpeg")
-            .send()
-            .await?;
+        metadata
+            .checksums
+            .insert(compressed.size_name.clone(), sha256);
         
-        let url = format!(
-            "https://{}.s3.{}.amazonaws.com/{}",
-            config.bucket, config.region, key
+        metadata.set_url(&compressed.size_name, storage.public_url(&key).unwrap_or_default());
+        metadata
+            .object_keys
+            .insert(compressed.size_name.clone(), key);
+        
+        if compressed.size_name == "original" {
+            metadata.file_size = compressed.data.len() as u64;
+            metadata.width = compressed.width;
+            metadata.height = compressed.height;
+        }
+    }
+    
+    // Upload the paired file (Live Photo video or RAW) untouched
+    if let Some(companion) = companion {
+        let source = ObjectSource::File(Path::new(&companion.path));
+        let file_size = source.len().await?;
+        let sha256 = source.sha256().await?;
+        let content_type = stacks::content_type_for(&companion.path);
+        let key = format!(
+            "photos/{}/secondary.{}",
+            photo_id,
+            stacks::extension_of(&companion.path)
         );
         
-        match compressed.size_name.as_str() {
-            "thumbnail" => metadata.thumbnail_url = url,
-            "small" => metadata.small_url = url,
-            "medium" => metadata.medium_url = url,
-            "large" => metadata.large_url = url,
-            "original" => {
-                metadata.original_url = url;
-                metadata.file_size = compressed.data.len() as u64;
-                metadata.width = compressed.width;
-                metadata.height = compressed.height;
-            }
-            _ => {}
+        let name = file_name_of(&companion.path);
+        
+        if !sessions.is_uploaded(&fingerprint, &key) {
+            let options = rendition_options(config, &photo_id, "secondary", content_type, &sha256, Some(&name));
+            storage.put(&key, source, &options).await?;
+            sessions.mark_uploaded(&fingerprint, &key)?;
         }
+        metadata.checksums.insert("secondary".to_string(), sha256);
+        
+        let original_key = metadata
+            .object_keys
+            .get("original")
+            .cloned()
+            .unwrap_or_default();
+        metadata
+            .object_keys
+            .insert("secondary".to_string(), key.clone());
+        
+        metadata.stack = Some(PhotoStack {
+            kind: companion.kind,
+            primary: StackAsset {
+                name: metadata.original_name.clone(),
+                key: original_key,
+                url: metadata.original_url.clone(),
+                // The original is re-encoded as JPEG whatever its source format
+                content_type: "image/jpeg".to_string(),
+                file_size: metadata.file_size,
+            },
+            secondary: StackAsset {
+                name,
+                url: storage.public_url(&key).unwrap_or_default(),
+                key: key.clone(),
+                content_type: content_type.to_string(),
+                file_size,
+            },
+        });
     }
     
-    // Store metadata in S3
+    // Edit and metadata sidecars, untouched, under `sidecar-{ext}`
+    for sidecar in sidecars {
+        let extension = stacks::extension_of(sidecar);
+        let size_name = format!("sidecar-{}", extension);
+        let key = format!("photos/{}/{}.{}", photo_id, size_name, extension);
+        let source = ObjectSource::File(Path::new(sidecar));
+        let sha256 = source.sha256().await?;
+        
+        if !sessions.is_uploaded(&fingerprint, &key) {
+            let name = file_name_of(sidecar);
+            let content_type = stacks::content_type_for(sidecar);
+            let options = rendition_options(config, &photo_id, &size_name, content_type, &sha256, Some(&name));
+            storage.put(&key, source, &options).await?;
+            sessions.mark_uploaded(&fingerprint, &key)?;
+        }
+        metadata.checksums.insert(size_name.clone(), sha256);
+        metadata.object_keys.insert(size_name, key);
+    }
+    
+    // Store metadata next to the photos
     let metadata_key = format!("metadata/{}.json", photo_id);
-    let metadata_json = serde_json::to_string(&metadata)?;
-    
-    client
-        .put_object()
-        .bucket(&config.bucket)
-        .key(&metadata_key)
-        .body(ByteStream::from(metadata_json.into_bytes()))
-        .content_type("application/json")
-        .send()
+    let metadata_json = serde_json::to_vec(&metadata)?;
+    
+    storage
+        .put(
+            &metadata_key,
+            ObjectSource::Bytes(&metadata_json),
+            &PutOptions {
+                cache_control: Some(SHORT_CACHE_CONTROL.to_string()),
+                ..PutOptions::content_type("application/json")
+            },
+        )
         .await?;
     
+    sessions.finish_import(&fingerprint)?;
+    
+    // The photo is stored at this point; a missed index update is picked up
+    // by the next listing
+    if let Err(e) = library_index::upsert(storage, &metadata).await {
+        log::warn!("Failed to add photo {} to the library index: {}", photo_id, e);
+    }
+    
     Ok(metadata)
 }
 
 pub async fn list_photos(
-    config: &S3Config,
-) -> Result<Vec<PhotoMetadata>, Box<dyn std::error::Error>> {
-    let client = create_s3_client(config).await?;
-    
-    let response = client
-        .list_objects_v2()
-        .bucket(&config.bucket)
-        .prefix("metadata/")
-        .send()
-        .await?;
-    
-    let mut photos = Vec::new();
-    
-    let contents = response.contents();
-    if !contents.is_empty() {
-        for object in contents {
-            if let Some(key) = object.key() {
-                let get_response = client
-                    .get_object()
-                    .bucket(&config.bucket)
-                    .key(key)
-                    .send()
-                    .await?;
-                
-                let body = get_response.body.collect().await?;
-                let metadata: PhotoMetadata = serde_json::from_slice(&body.into_bytes())?;
-                photos.push(metadata);
-            }
-        }
-    }
+    storage: &dyn StorageBackend,
+    on_batch: &(dyn Fn(&[PhotoMetadata]) + Send + Sync),
+) -> StorageResult<Vec<PhotoMetadata>> {
+    // Served from the library index rather than one GET per photo
+    let mut photos = library_index::load_library(storage, on_batch).await?;
     
     // Sort by upload date (newest first)
     photos.sort_by(|a, b| b.upload_date.cmp(&a.upload_date));
@@ -120,55 +302,194 @@ pub async fn list_photos(
     Ok(photos)
 }
 
-pub async fn delete_photo(
-    config: &S3Config,
-    photo_id: &str,
-) -> Result<(), Box<dyn std::error::Error>> {
-    let client = create_s3_client(config).await?;
-    
-    // Delete all photo sizes
-    let sizes = vec!["thumbnail", "small", "medium", "large", "original"];
-    for size in sizes {
-        let key = format!("photos/{}/{}.jpg", photo_id, size);
-        let _ = client
-            .delete_object()
-            .bucket(&config.bucket)
-            .key(&key)
-            .send()
-            .await;
+/// Lists one page of metadata object keys, returning the cursor for the
+/// next page if there is one.
+pub async fn list_metadata_page(
+    storage: &dyn StorageBackend,
+    cursor: Option<String>,
+    max_keys: Option<usize>,
+) -> StorageResult<(Vec<String>, Option<String>)> {
+    let page = storage.list("metadata/", cursor, max_keys).await?;
+    let keys = page.objects.into_iter().map(|object| object.key).collect();
+    
+    Ok((keys, page.next_cursor))
+}
+
+/// Lists one page of the library. Photos come back in key order; pass the
+/// returned cursor to fetch the next page.
+pub async fn list_photos_page(
+    storage: &dyn StorageBackend,
+    cursor: Option<String>,
+    page_size: usize,
+) -> StorageResult<PhotoPage> {
+    let (keys, next_cursor) = list_metadata_page(storage, cursor, Some(page_size)).await?;
+    let photos = fetch_metadata_batch(storage, keys).await?;
+    
+    Ok(PhotoPage { photos, next_cursor })
+}
+
+/// Fetches metadata objects concurrently, keeping the order of `keys`.
+pub async fn fetch_metadata_batch(
+    storage: &dyn StorageBackend,
+    keys: Vec<String>,
+) -> StorageResult<Vec<PhotoMetadata>> {
+    let mut fetches = stream::iter(keys)
+        .map(|key| async move { fetch_metadata(storage, &key).await })
+        .buffered(METADATA_FETCH_CONCURRENCY);
+    
+    let mut photos = Vec::new();
+    while let Some(metadata) = fetches.next().await {
+        photos.push(metadata?);
     }
     
-    // Delete metadata
-    let metadata_key = format!("metadata/{}.json", photo_id);
-    client
-        .delete_object()
-        .bucket(&config.bucket)
-        .key(&metadata_key)
-        .send()
-        .await?;
+    Ok(photos)
+}
+
+pub async fn fetch_metadata(
+    storage: &dyn StorageBackend,
+    key: &str,
+) -> StorageResult<PhotoMetadata> {
+    let object = storage
+        .get(key)
+        .await?
+        .ok_or_else(|| {
+            StorageError::new(ErrorKind::NotFound, format!("Metadata object {} is missing", key))
+        })?;
     
-    Ok(())
+    Ok(serde_json::from_slice(&object.data)?)
+}
+
+/// Outcome of deleting photos: the photos that are gone, and every object
+/// that could not be deleted. A photo with a failed object keeps its
+/// metadata, so it stays visible and can be deleted again.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct DeleteReport {
+    pub deleted: Vec<String>,
+    pub failed: Vec<DeleteFailure>,
+    /// Photos left alone because they were no longer in the state the
+    /// delete expected, e.g. restored from the trash on another device
+    pub skipped: Vec<String>,
+}
+
+// `photos/{id}/large.jpg` -> `{id}`
+pub fn photo_id_of(key: &str) -> Option<&str> {
+    key.strip_prefix("photos/")?.split('/').next()
 }
 
-async fn create_s3_client(config: &S3Config) -> Result<Client, Box<dyn std::error::Error>> {
-    use aws_sdk_s3::config::Region;
+/// Permanently deletes photos with batched deletes, reporting exactly which
+/// objects failed.
+pub async fn delete_photos(
+    storage: &dyn StorageBackend,
+    photo_ids: &[String],
+) -> StorageResult<DeleteReport> {
+    // All photo sizes and any stacked secondary asset
+    let mut listings = stream::iter(photo_ids.to_vec())
+        .map(|photo_id| async move {
+            storage::list_all(storage, &format!("photos/{}/", photo_id)).await
+        })
+        .buffered(METADATA_FETCH_CONCURRENCY);
     
-    let region = Region::new(config.region.clone());
-    let region_provider = RegionProviderChain::default_provider()
-        .or_else(region);
+    let mut keys = Vec::new();
+    while let Some(objects) = listings.next().await {
+        keys.extend(objects?.into_iter().map(|object| object.key));
+    }
+    
+    let mut failed = storage.delete_many(&keys).await?;
+    
+    // Metadata goes last, and only for photos whose objects are all gone
+    let incomplete: HashSet<&str> = failed
+        .iter()
+        .filter_map(|failure| photo_id_of(&failure.key))
+        .collect();
+    let candidates: Vec<&String> = photo_ids
+        .iter()
+        .filter(|photo_id| !incomplete.contains(photo_id.as_str()))
+        .collect();
+    let metadata_keys: Vec<String> = candidates
+        .iter()
+        .map(|photo_id| format!("metadata/{}.json", photo_id))
+        .collect();
     
-    let sdk_config = aws_config::defaults(BehaviorVersion::latest())
-        .region(region_provider)
-        .credentials_provider(aws_sdk_s3::config::Credentials::new(
-            &config.access_key,
-            &config.secret_key,
-            None,
-            None,
-            "galleria",
-        ))
-        .load()
-        .await;
+    let metadata_failures = storage.delete_many(&metadata_keys).await?;
+    let deleted: Vec<String> = candidates
+        .into_iter()
+        .filter(|photo_id| {
+            let key = format!("metadata/{}.json", photo_id);
+            !metadata_failures.iter().any(|failure| failure.key == key)
+        })
+        .cloned()
+        .collect();
+    failed.extend(metadata_failures);
+    
+    if !deleted.is_empty() {
+        if let Err(e) = library_index::remove_many(storage, &deleted).await {
+            log::warn!("Failed to remove deleted photos from the library index: {}", e);
+        }
+    }
     
-    Ok(Client::new(&sdk_config))
+    Ok(DeleteReport {
+        deleted,
+        failed,
+        skipped: Vec::new(),
+    })
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::MemoryBackend;
+    use uuid::Uuid;
+
+    #[test]
+    fn an_unreadable_still_fails_its_whole_group() {
+        let dir = std::env::temp_dir().join(format!("galleria-test-{}", Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let files: Vec<String> = ["IMG_1234.HEIC", "IMG_1234.MOV"]
+            .iter()
+            .map(|name| {
+                let path = dir.join(name);
+                std::fs::write(&path, b"not an image").unwrap();
+                path.to_string_lossy().to_string()
+            })
+            .collect();
+
+        let (items, _) = stacks::group_files(&files);
+        let failures = process_item(&items[0]).unwrap_err();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let failed: Vec<&str> = failures.iter().map(|f| f.file.as_str()).collect();
+        assert_eq!(failed, [files[0].as_str(), files[1].as_str()]);
+        assert!(failures[1].error.to_string().contains("IMG_1234.HEIC"));
+    }
+
+    #[test]
+    fn download_names_survive_the_disposition() {
+        for name in ["IMG_1234.HEIC", "Été à \"Nice\".jpg"] {
+            assert_eq!(download_name_of(&content_disposition(name)).as_deref(), Some(name));
+        }
+        assert_eq!(download_name_of("inline; filename=\"a.jpg\"").as_deref(), Some("a.jpg"));
+        assert_eq!(download_name_of("inline"), None);
+    }
+
+    #[tokio::test]
+    async fn delete_photos_removes_objects_beyond_the_first_page() {
+        let storage = MemoryBackend::default();
+        let options = PutOptions::content_type("image/jpeg");
+
+        // More than a listing returns at once
+        for index in 0..1001 {
+            let key = format!("photos/a/{:04}.jpg", index);
+            storage.put(&key, ObjectSource::Bytes(b"jpg"), &options).await.unwrap();
+        }
+        storage
+            .put("metadata/a.json", ObjectSource::Bytes(b"{}"), &PutOptions::content_type("application/json"))
+            .await
+            .unwrap();
+
+        let report = delete_photos(&storage, &["a".to_string()]).await.unwrap();
+
+        assert_eq!(report.deleted, ["a"]);
+        assert!(report.failed.is_empty());
+        assert!(storage.list("", None, None).await.unwrap().objects.iter().all(|o| o.key.starts_with("index/")));
+    }
+}
@@ -0,0 +1,268 @@
+use crate::models::PhotoMetadata;
+use crate::s3_uploader;
+use crate::storage::{
+    self, ErrorKind, ObjectSource, PutOptions, StorageBackend, StorageError, StorageResult, SHORT_CACHE_CONTROL,
+};
+use chrono::{DateTime, Duration, Utc};
+use futures::stream::{self, StreamExt};
+use serde::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+/// Libraries are scrubbed this often unless configured otherwise
+pub const DEFAULT_INTERVAL_DAYS: u32 = 30;
+
+// Kept in the library so devices share the schedule and the last report
+const SCHEDULE_KEY: &str = "settings/scrub.json";
+
+// Objects verified in parallel
+const SCRUB_CONCURRENCY: usize = 8;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ScrubMode {
+    /// Compares recorded checksums with the ones the provider verified at
+    /// upload, without downloading anything
+    #[default]
+    Checksums,
+    /// Downloads and hashes every object; catches everything but costs a
+    /// full read of the library
+    Download,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum ScrubProblem {
+    /// The object's contents differ from the checksum recorded at upload
+    Mismatch { expected: String, actual: String },
+    Missing,
+    Unreadable { error: StorageError },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ScrubIssue {
+    pub photo_id: String,
+    pub key: String,
+    pub problem: ScrubProblem,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ScrubReport {
+    pub mode: ScrubMode,
+    pub started_at: String,
+    pub checked: usize,
+    pub verified: usize,
+    /// Objects with nothing to compare against: photos uploaded before
+    /// checksums were recorded, providers that keep no checksum, multipart
+    /// uploads in checksum mode and archived objects in download mode
+    pub unverified: usize,
+    pub issues: Vec<ScrubIssue>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ScrubSchedule {
+    /// `None` turns scheduled scrubs off
+    pub interval_days: Option<u32>,
+    #[serde(default)]
+    pub mode: ScrubMode,
+    /// When the last scrub started, whether or not it finished, so a run
+    /// that keeps failing isn't retried every time the library opens
+    #[serde(default)]
+    pub last_started_at: Option<String>,
+    #[serde(default)]
+    pub last_report: Option<ScrubReport>,
+}
+
+impl Default for ScrubSchedule {
+    fn default() -> Self {
+        Self {
+            interval_days: Some(DEFAULT_INTERVAL_DAYS),
+            mode: ScrubMode::default(),
+            last_started_at: None,
+            last_report: None,
+        }
+    }
+}
+
+impl ScrubSchedule {
+    fn is_due(&self, now: DateTime<Utc>) -> bool {
+        let Some(interval_days) = self.interval_days else {
+            return false;
+        };
+
+        let started_at = self
+            .last_started_at
+            .as_deref()
+            .or(self.last_report.as_ref().map(|report| report.started_at.as_str()));
+
+        match started_at {
+            Some(started_at) => DateTime::parse_from_rfc3339(started_at)
+                .map(|started_at| started_at.with_timezone(&Utc) + Duration::days(interval_days as i64) <= now)
+                .unwrap_or(true),
+            None => true,
+        }
+    }
+}
+
+pub async fn load_schedule(storage: &dyn StorageBackend) -> StorageResult<ScrubSchedule> {
+    match storage.get(SCHEDULE_KEY).await? {
+        Some(object) => Ok(serde_json::from_slice(&object.data)?),
+        None => Ok(ScrubSchedule::default()),
+    }
+}
+
+pub async fn save_schedule(storage: &dyn StorageBackend, schedule: &ScrubSchedule) -> StorageResult<()> {
+    storage
+        .put(
+            SCHEDULE_KEY,
+            ObjectSource::Bytes(&serde_json::to_vec(schedule)?),
+            &PutOptions {
+                cache_control: Some(SHORT_CACHE_CONTROL.to_string()),
+                ..PutOptions::content_type("application/json")
+            },
+        )
+        .await
+}
+
+/// Held while a scrub runs in this process; a second scrub can't start
+/// until it is dropped.
+pub struct RunningScrub(Arc<AtomicBool>);
+
+impl RunningScrub {
+    pub fn start(running: &Arc<AtomicBool>) -> Option<Self> {
+        running
+            .compare_exchange(false, true, Ordering::AcqRel, Ordering::Acquire)
+            .ok()
+            .map(|_| Self(running.clone()))
+    }
+}
+
+impl Drop for RunningScrub {
+    fn drop(&mut self) {
+        self.0.store(false, Ordering::Release);
+    }
+}
+
+enum Verdict {
+    Verified,
+    Unverified,
+    Problem(ScrubProblem),
+}
+
+/// Verifies every rendition of every photo against the checksum recorded
+/// in its metadata, and keeps the report as the schedule's last report.
+pub async fn scrub_library(
+    storage: &dyn StorageBackend,
+    mode: ScrubMode,
+    on_progress: &(dyn Fn(usize) + Send + Sync),
+) -> StorageResult<ScrubReport> {
+    let mut report = ScrubReport {
+        mode,
+        started_at: Utc::now().to_rfc3339(),
+        checked: 0,
+        verified: 0,
+        unverified: 0,
+        issues: Vec::new(),
+    };
+
+    let mut schedule = load_schedule(storage).await?;
+    schedule.last_started_at = Some(report.started_at.clone());
+    save_schedule(storage, &schedule).await?;
+
+    let keys = storage::list_all(storage, "metadata/")
+        .await?
+        .into_iter()
+        .map(|object| object.key)
+        .collect();
+    let photos = s3_uploader::fetch_metadata_batch(storage, keys).await?;
+
+    let mut checks = stream::iter(photos.iter().flat_map(renditions_of))
+        .map(|(photo_id, key, expected)| async move {
+            let verdict = verify(storage, mode, &key, expected).await;
+            (photo_id, key, verdict)
+        })
+        .buffer_unordered(SCRUB_CONCURRENCY);
+
+    while let Some((photo_id, key, verdict)) = checks.next().await {
+        match verdict {
+            Verdict::Verified => report.verified += 1,
+            Verdict::Unverified => report.unverified += 1,
+            Verdict::Problem(problem) => report.issues.push(ScrubIssue {
+                photo_id,
+                key,
+                problem,
+            }),
+        }
+        report.checked += 1;
+        on_progress(report.checked);
+    }
+
+    let mut schedule = load_schedule(storage).await?;
+    schedule.last_report = Some(report.clone());
+    save_schedule(storage, &schedule).await?;
+
+    Ok(report)
+}
+
+/// Runs a scrub in the scheduled mode if the last one is older than the
+/// configured interval.
+pub async fn scrub_if_due(storage: &dyn StorageBackend) -> StorageResult<Option<ScrubReport>> {
+    let schedule = load_schedule(storage).await?;
+    if !schedule.is_due(Utc::now()) {
+        return Ok(None);
+    }
+
+    scrub_library(storage, schedule.mode, &|_| {}).await.map(Some)
+}
+
+// (photo id, key, recorded checksum) of every rendition of a photo
+fn renditions_of(photo: &PhotoMetadata) -> Vec<(String, String, Option<String>)> {
+    photo
+        .object_keys
+        .iter()
+        .map(|(size_name, key)| {
+            (
+                photo.id.clone(),
+                key.clone(),
+                photo.checksums.get(size_name).cloned(),
+            )
+        })
+        .collect()
+}
+
+async fn verify(storage: &dyn StorageBackend, mode: ScrubMode, key: &str, expected: Option<String>) -> Verdict {
+    let Some(expected) = expected else {
+        return Verdict::Unverified;
+    };
+
+    let actual = match mode {
+        ScrubMode::Checksums => match storage.stored_sha256(key).await {
+            Ok(Some(actual)) => actual,
+            Ok(None) => return Verdict::Unverified,
+            Err(error) => return failed(error),
+        },
+        ScrubMode::Download => match storage.get(key).await {
+            Ok(Some(object)) => match ObjectSource::Bytes(&object.data).sha256().await {
+                Ok(actual) => actual,
+                Err(error) => return failed(error),
+            },
+            Ok(None) => return Verdict::Problem(ScrubProblem::Missing),
+            // Archived objects can't be read without a restore
+            Err(error) if error.kind == ErrorKind::Archived => return Verdict::Unverified,
+            Err(error) => return failed(error),
+        },
+    };
+
+    if actual == expected {
+        Verdict::Verified
+    } else {
+        Verdict::Problem(ScrubProblem::Mismatch { expected, actual })
+    }
+}
+
+fn failed(error: StorageError) -> Verdict {
+    match error.kind {
+        ErrorKind::NotFound => Verdict::Problem(ScrubProblem::Missing),
+        _ => Verdict::Problem(ScrubProblem::Unreadable { error }),
+    }
+}
@@ -0,0 +1,266 @@
+use crate::models::StackKind;
+use std::collections::HashMap;
+use std::path::Path;
+
+const IMAGE_EXTENSIONS: &[&str] = &[
+    "jpg", "jpeg", "png", "heic", "heif", "tif", "tiff", "webp", "gif", "bmp",
+];
+const RAW_EXTENSIONS: &[&str] = &[
+    "arw", "cr2", "cr3", "dng", "nef", "nrw", "orf", "pef", "raf", "rw2", "srw",
+];
+const VIDEO_EXTENSIONS: &[&str] = &["mov", "mp4"];
+
+#[derive(Debug, Clone)]
+pub struct Companion {
+    pub path: String,
+    pub kind: StackKind,
+}
+
+#[derive(Debug, Clone)]
+pub struct ImportItem {
+    pub primary: String,
+    pub companion: Option<Companion>,
+    /// Edit and metadata files (.AAE, .XMP) uploaded untouched with the photo
+    pub sidecars: Vec<String>,
+}
+
+impl ImportItem {
+    /// Every file of the item, primary first.
+    pub fn files(&self) -> impl Iterator<Item = &String> {
+        std::iter::once(&self.primary)
+            .chain(self.companion.as_ref().map(|companion| &companion.path))
+            .chain(&self.sidecars)
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum FileRole {
+    Still,
+    Raw,
+    Video,
+    Sidecar,
+}
+
+fn file_role(path: &str) -> FileRole {
+    let extension = extension_of(path);
+    if IMAGE_EXTENSIONS.contains(&extension.as_str()) {
+        FileRole::Still
+    } else if RAW_EXTENSIONS.contains(&extension.as_str()) {
+        FileRole::Raw
+    } else if VIDEO_EXTENSIONS.contains(&extension.as_str()) {
+        FileRole::Video
+    } else {
+        FileRole::Sidecar
+    }
+}
+
+/// Kind of stack a companion file forms with its still.
+pub fn stack_kind_for(path: &str) -> StackKind {
+    match file_role(path) {
+        FileRole::Video => StackKind::LivePhoto,
+        _ => StackKind::RawPlusJpeg,
+    }
+}
+
+pub fn extension_of(path: &str) -> String {
+    Path::new(path)
+        .extension()
+        .and_then(|e| e.to_str())
+        .unwrap_or("")
+        .to_lowercase()
+}
+
+pub fn content_type_for(path: &str) -> &'static str {
+    match extension_of(path).as_str() {
+        "jpg" | "jpeg" => "image/jpeg",
+        "png" => "image/png",
+        "heic" => "image/heic",
+        "heif" => "image/heif",
+        "tif" | "tiff" => "image/tiff",
+        "dng" => "image/x-adobe-dng",
+        "mov" => "video/quicktime",
+        "mp4" => "video/mp4",
+        _ => "application/octet-stream",
+    }
+}
+
+/// Groups the selected files into import items, pairing files that share a
+/// directory and basename (IMG_1234.HEIC + IMG_1234.MOV, DSC01.JPG + DSC01.ARW).
+/// The still image is always the primary so renditions can be generated from it.
+/// Any other file type is a sidecar and goes with the group's first item; the
+/// second list holds sidecars that have no photo to go with.
+pub fn group_files(files: &[String]) -> (Vec<ImportItem>, Vec<String>) {
+    let mut order: Vec<(String, String)> = Vec::new();
+    let mut groups: HashMap<(String, String), Vec<String>> = HashMap::new();
+
+    for file in files {
+        let path = Path::new(file);
+        let parent = path
+            .parent()
+            .map(|p| p.to_string_lossy().to_string())
+            .unwrap_or_default();
+        let stem = path
+            .file_stem()
+            .map(|s| s.to_string_lossy().to_lowercase())
+            .unwrap_or_default();
+
+        let group_key = (parent, stem);
+        if !groups.contains_key(&group_key) {
+            order.push(group_key.clone());
+        }
+        groups.entry(group_key).or_default().push(file.clone());
+    }
+
+    let mut items = Vec::new();
+    let mut unattached = Vec::new();
+
+    for group_key in order {
+        let (sidecars, mut members): (Vec<String>, Vec<String>) = groups
+            .remove(&group_key)
+            .unwrap_or_default()
+            .into_iter()
+            .partition(|f| file_role(f) == FileRole::Sidecar);
+
+        let still = members.iter().position(|f| file_role(f) == FileRole::Still);
+        let companion = still.and_then(|still_index| {
+            let still_path = members.remove(still_index);
+
+            // Prefer the Live Photo video, then the RAW negative
+            let companion_index = members
+                .iter()
+                .position(|f| file_role(f) == FileRole::Video)
+                .or_else(|| members.iter().position(|f| file_role(f) == FileRole::Raw));
+
+            let companion = companion_index.map(|index| {
+                let path = members.remove(index);
+                let kind = stack_kind_for(&path);
+                Companion { path, kind }
+            });
+
+            members.insert(0, still_path);
+            companion
+        });
+
+        let mut members = members.into_iter();
+        match members.next() {
+            Some(primary) => items.push(ImportItem {
+                primary,
+                companion,
+                sidecars,
+            }),
+            None => unattached.extend(sidecars),
+        }
+
+        // Anything that could not be paired is imported on its own
+        for file in members {
+            items.push(ImportItem {
+                primary: file,
+                companion: None,
+                sidecars: Vec::new(),
+            });
+        }
+    }
+
+    (items, unattached)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn group(files: &[&str]) -> Vec<(String, Option<String>)> {
+        let files: Vec<String> = files.iter().map(|f| f.to_string()).collect();
+
+        group_files(&files)
+            .0
+            .into_iter()
+            .map(|item| (item.primary, item.companion.map(|c| c.path)))
+            .collect()
+    }
+
+    fn item(primary: &str, companion: Option<&str>) -> (String, Option<String>) {
+        (primary.to_string(), companion.map(String::from))
+    }
+
+    #[test]
+    fn pairs_files_with_the_same_stem() {
+        assert_eq!(
+            group(&["/p/IMG_1.HEIC", "/p/IMG_1.MOV", "/p/DSC_2.JPG", "/p/DSC_2.ARW"]),
+            [
+                item("/p/IMG_1.HEIC", Some("/p/IMG_1.MOV")),
+                item("/p/DSC_2.JPG", Some("/p/DSC_2.ARW")),
+            ]
+        );
+    }
+
+    #[test]
+    fn companion_kind_follows_its_file_type() {
+        assert_eq!(stack_kind_for("/p/IMG_1.MOV"), StackKind::LivePhoto);
+        assert_eq!(stack_kind_for("/p/DSC_2.ARW"), StackKind::RawPlusJpeg);
+    }
+
+    #[test]
+    fn sidecars_are_never_the_primary() {
+        let files: Vec<String> = ["/p/IMG_1.AAE", "/p/IMG_1.HEIC", "/p/IMG_1.MOV", "/p/IMG_1.xmp"]
+            .iter()
+            .map(|f| f.to_string())
+            .collect();
+        let (items, unattached) = group_files(&files);
+
+        assert_eq!(items.len(), 1);
+        assert_eq!(items[0].primary, "/p/IMG_1.HEIC");
+        assert_eq!(items[0].sidecars, ["/p/IMG_1.AAE", "/p/IMG_1.xmp"]);
+        assert!(unattached.is_empty());
+    }
+
+    #[test]
+    fn sidecars_without_a_photo_are_left_out() {
+        let files = vec!["/p/IMG_1.AAE".to_string()];
+        let (items, unattached) = group_files(&files);
+
+        assert!(items.is_empty());
+        assert_eq!(unattached, ["/p/IMG_1.AAE"]);
+    }
+
+    #[test]
+    fn still_is_the_primary_whatever_the_order() {
+        assert_eq!(
+            group(&["/p/IMG_1.mov", "/p/IMG_1.jpg"]),
+            [item("/p/IMG_1.jpg", Some("/p/IMG_1.mov"))]
+        );
+    }
+
+    #[test]
+    fn stems_match_regardless_of_case() {
+        assert_eq!(
+            group(&["/p/img_1.jpg", "/p/IMG_1.MOV"]),
+            [item("/p/img_1.jpg", Some("/p/IMG_1.MOV"))]
+        );
+    }
+
+    #[test]
+    fn files_in_other_directories_are_not_paired() {
+        assert_eq!(
+            group(&["/a/IMG_1.jpg", "/b/IMG_1.mov"]),
+            [item("/a/IMG_1.jpg", None), item("/b/IMG_1.mov", None)]
+        );
+    }
+
+    #[test]
+    fn raw_without_a_jpeg_is_imported_alone() {
+        assert_eq!(group(&["/p/DSC_2.ARW"]), [item("/p/DSC_2.ARW", None)]);
+    }
+
+    #[test]
+    fn video_without_a_still_is_imported_alone() {
+        assert_eq!(group(&["/p/IMG_1.MOV"]), [item("/p/IMG_1.MOV", None)]);
+    }
+
+    #[test]
+    fn video_is_preferred_over_raw_and_the_rest_stays_unpaired() {
+        assert_eq!(
+            group(&["/p/IMG_1.jpg", "/p/IMG_1.dng", "/p/IMG_1.mov"]),
+            [item("/p/IMG_1.jpg", Some("/p/IMG_1.mov")), item("/p/IMG_1.dng", None)]
+        );
+    }
+}
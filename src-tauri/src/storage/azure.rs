@@ -0,0 +1,549 @@
+use super::{save_response, DownloadedObject, ErrorKind, ListPage, ObjectInfo, ObjectSource, PutOptions, StorageBackend, StorageError, StorageResult, StoredObject, UploadThrottle};
+use crate::models::AzureConfig;
+use crate::upload_sessions::{MultipartSession, UploadSessionStore, UploadedPart};
+use async_trait::async_trait;
+use base64::Engine;
+use chrono::Utc;
+use hmac::{Hmac, Mac};
+use reqwest::{Method, StatusCode};
+use sha2::Sha256;
+use std::path::Path;
+use std::time::Duration;
+
+const API_VERSION: &str = "2021-08-06";
+// Blobs at or above this size are sent in blocks with Put Block List
+const BLOCK_THRESHOLD: u64 = 16 * 1024 * 1024;
+const MIN_BLOCK_SIZE: u64 = 8 * 1024 * 1024;
+const MAX_BLOCKS: u64 = 50_000;
+
+/// Keeps the library in an Azure Blob Storage container, authenticated
+/// with the storage account's shared key. Point `endpoint_url` at
+/// `http://127.0.0.1:10000/devstoreaccount1` to use the Azurite emulator.
+pub struct AzureBlobBackend {
+    client: reqwest::Client,
+    config: AzureConfig,
+    endpoint: String,
+    key: Vec<u8>,
+    sessions: UploadSessionStore,
+}
+
+impl AzureBlobBackend {
+    pub fn new(config: &AzureConfig, sessions: UploadSessionStore) -> StorageResult<Self> {
+        let endpoint = match &config.endpoint_url {
+            Some(endpoint) => endpoint.trim_end_matches('/').to_string(),
+            None => format!("https://{}.blob.core.windows.net", config.account),
+        };
+
+        Ok(Self {
+            client: reqwest::Client::new(),
+            key: base64::engine::general_purpose::STANDARD
+                .decode(&config.access_key)
+                .map_err(|e| StorageError::new(ErrorKind::Auth, format!("Invalid account key: {}", e)))?,
+            config: config.clone(),
+            endpoint,
+            sessions,
+        })
+    }
+
+    fn blob_url(&self, key: &str) -> String {
+        format!("{}/{}/{}", self.endpoint, self.config.container, encode_path(key))
+    }
+
+    fn sign(&self, string_to_sign: &str) -> StorageResult<String> {
+        let mut mac = Hmac::<Sha256>::new_from_slice(&self.key)
+            .map_err(|e| StorageError::new(ErrorKind::Auth, format!("Invalid account key: {}", e)))?;
+        mac.update(string_to_sign.as_bytes());
+        Ok(base64::engine::general_purpose::STANDARD.encode(mac.finalize().into_bytes()))
+    }
+
+    /// Builds a request signed with Shared Key authorization, see
+    /// https://learn.microsoft.com/rest/api/storageservices/authorize-with-shared-key
+    fn request(
+        &self,
+        method: Method,
+        url: &str,
+        headers: &[(&str, String)],
+        body: Option<Vec<u8>>,
+    ) -> StorageResult<reqwest::RequestBuilder> {
+        let parsed = reqwest::Url::parse(url).map_err(StorageError::other)?;
+        let date = Utc::now().format("%a, %d %b %Y %H:%M:%S GMT").to_string();
+
+        let header = |name: &str| {
+            headers
+                .iter()
+                .find(|(n, _)| n.eq_ignore_ascii_case(name))
+                .map(|(_, v)| v.as_str())
+                .unwrap_or("")
+        };
+
+        let mut ms_headers: Vec<(String, String)> = headers
+            .iter()
+            .filter(|(name, _)| name.to_ascii_lowercase().starts_with("x-ms-"))
+            .map(|(name, value)| (name.to_ascii_lowercase(), value.clone()))
+            .collect();
+        ms_headers.push(("x-ms-date".to_string(), date.clone()));
+        ms_headers.push(("x-ms-version".to_string(), API_VERSION.to_string()));
+        ms_headers.sort();
+
+        let mut query: Vec<(String, String)> = parsed
+            .query_pairs()
+            .map(|(name, value)| (name.to_ascii_lowercase(), value.into_owned()))
+            .collect();
+        query.sort();
+
+        let mut canonical_resource = format!("/{}{}", self.config.account, parsed.path());
+        for (name, value) in &query {
+            canonical_resource.push_str(&format!("\n{}:{}", name, value));
+        }
+
+        let content_length = match &body {
+            Some(body) if !body.is_empty() => body.len().to_string(),
+            _ => String::new(),
+        };
+
+        let string_to_sign = format!(
+            "{}\n\n\n{}\n\n{}\n\n\n{}\n{}\n\n\n{}{}",
+            method.as_str(),
+            content_length,
+            header("content-type"),
+            header("if-match"),
+            header("if-none-match"),
+            ms_headers
+                .iter()
+                .map(|(name, value)| format!("{}:{}\n", name, value))
+                .collect::<String>(),
+            canonical_resource,
+        );
+
+        let mut request = self
+            .client
+            .request(method, url)
+            .header("x-ms-date", date)
+            .header("x-ms-version", API_VERSION)
+            .header(
+                "Authorization",
+                format!("SharedKey {}:{}", self.config.account, self.sign(&string_to_sign)?),
+            );
+
+        for (name, value) in headers {
+            request = request.header(*name, value);
+        }
+        if let Some(body) = body {
+            request = request
+                .header(reqwest::header::CONTENT_LENGTH, body.len())
+                .body(UploadThrottle::shared().body(body).into_reqwest());
+        }
+
+        Ok(request)
+    }
+
+    /// Uploads the blob in blocks and commits them with Put Block List, see
+    /// https://learn.microsoft.com/rest/api/storageservices/put-block-list.
+    /// Uploaded blocks are kept with the upload sessions, so an interrupted
+    /// upload only sends the blocks Azure doesn't have yet.
+    async fn put_blocks(
+        &self,
+        key: &str,
+        source: &ObjectSource<'_>,
+        total_size: u64,
+        options: &PutOptions,
+    ) -> StorageResult<()> {
+        let content_sha256 = source.sha256().await?;
+        let session = self.resume_or_start_blocks(key, total_size, &content_sha256).await?;
+        let block_count = total_size.div_ceil(session.part_size);
+        let block_ids: Vec<String> = (1..=block_count as i32)
+            .map(|part_number| block_id(&session.upload_id, part_number))
+            .collect();
+
+        for (index, block_id) in block_ids.iter().enumerate() {
+            let part_number = index as i32 + 1;
+            if session.parts.iter().any(|p| p.part_number == part_number) {
+                continue;
+            }
+
+            let offset = index as u64 * session.part_size;
+            let length = session.part_size.min(total_size - offset);
+            let chunk = source.read_range(offset, length).await?;
+            let url = format!(
+                "{}?comp=block&blockid={}",
+                self.blob_url(key),
+                urlencoding::encode(block_id)
+            );
+
+            let response = self.request(Method::PUT, &url, &[], Some(chunk))?.send().await?;
+            if !response.status().is_success() {
+                return Err(StorageError::from_status(
+                    response.status().as_u16(),
+                    format!("Failed to upload a block of {}: {}", key, response.status()),
+                ));
+            }
+
+            self.sessions.record_part(
+                key,
+                UploadedPart {
+                    part_number,
+                    e_tag: block_id.clone(),
+                    checksum_sha256: None,
+                },
+            )?;
+        }
+
+        let block_list: String = block_ids
+            .iter()
+            .map(|block_id| format!("<Latest>{}</Latest>", block_id))
+            .collect();
+        let body = format!(
+            "<?xml version=\"1.0\" encoding=\"utf-8\"?><BlockList>{}</BlockList>",
+            block_list
+        );
+
+        let mut headers = vec![("Content-Type", "application/xml".to_string())];
+        let properties = blob_properties(options);
+        headers.extend(properties.iter().map(|(name, value)| (name.as_str(), value.clone())));
+
+        let url = format!("{}?comp=blocklist", self.blob_url(key));
+        let response = self
+            .request(Method::PUT, &url, &headers, Some(body.into_bytes()))?
+            .send()
+            .await?;
+
+        let result = match response.status() {
+            status if status.is_success() => Ok(()),
+            StatusCode::PRECONDITION_FAILED => Err(StorageError::precondition_failed()),
+            StatusCode::CONFLICT if options.if_none_match => Err(StorageError::precondition_failed()),
+            status => {
+                return Err(StorageError::from_status(
+                    status.as_u16(),
+                    format!("Failed to commit the blocks of {}: {}", key, status),
+                ))
+            }
+        };
+
+        // Blocks aren't reused once the commit was decided either way
+        self.sessions.finish_multipart(key)?;
+
+        result
+    }
+
+    // The session of an earlier attempt, keeping only the blocks Azure still
+    // holds uncommitted (they are dropped after a week)
+    async fn resume_or_start_blocks(
+        &self,
+        key: &str,
+        total_size: u64,
+        content_sha256: &str,
+    ) -> StorageResult<MultipartSession> {
+        if let Some(mut session) = self.sessions.multipart(key) {
+            if session.matches(total_size, content_sha256) {
+                let url = format!("{}?comp=blocklist&blocklisttype=uncommitted", self.blob_url(key));
+                let response = self.request(Method::GET, &url, &[], None)?.send().await?;
+
+                let uncommitted = match response.status() {
+                    StatusCode::NOT_FOUND => String::new(),
+                    status if status.is_success() => response.text().await?,
+                    status => {
+                        return Err(StorageError::from_status(
+                            status.as_u16(),
+                            format!("Failed to list the blocks of {}: {}", key, status),
+                        ))
+                    }
+                };
+                session
+                    .parts
+                    .retain(|part| uncommitted.contains(&format!("<Name>{}</Name>", part.e_tag)));
+
+                return Ok(session);
+            }
+        }
+
+        // A new id for every body, so blocks of another version are never committed
+        let session = MultipartSession {
+            upload_id: uuid::Uuid::new_v4().simple().to_string(),
+            part_size: MIN_BLOCK_SIZE.max(total_size.div_ceil(MAX_BLOCKS)),
+            total_size,
+            parts: Vec::new(),
+            checksums: false,
+            content_sha256: Some(content_sha256.to_string()),
+        };
+        self.sessions.start_multipart(key, session.clone())?;
+
+        Ok(session)
+    }
+
+    /// Read-only service SAS for a single blob, see
+    /// https://learn.microsoft.com/rest/api/storageservices/create-service-sas
+    fn sas_url(&self, key: &str, expires_in: Duration) -> StorageResult<String> {
+        let expiry = (Utc::now() + chrono::Duration::from_std(expires_in).map_err(StorageError::other)?)
+            .format("%Y-%m-%dT%H:%M:%SZ")
+            .to_string();
+        let resource = format!("/blob/{}/{}/{}", self.config.account, self.config.container, key);
+
+        // Permissions, start, expiry, resource, identifier, IP, protocol,
+        // version, resource type, snapshot, encryption scope, rscc..rsct
+        let string_to_sign = format!(
+            "r\n\n{}\n{}\n\n\n\n{}\nb\n\n\n\n\n\n\n",
+            expiry, resource, API_VERSION
+        );
+
+        Ok(format!(
+            "{}?sp=r&se={}&sv={}&sr=b&sig={}",
+            self.blob_url(key),
+            urlencoding::encode(&expiry),
+            API_VERSION,
+            urlencoding::encode(&self.sign(&string_to_sign)?)
+        ))
+    }
+
+    // GET that leaves reading the body to the caller
+    async fn fetch(&self, key: &str) -> StorageResult<Option<reqwest::Response>> {
+        let response = self
+            .request(Method::GET, &self.blob_url(key), &[], None)?
+            .send()
+            .await?;
+
+        match response.status() {
+            StatusCode::NOT_FOUND => Ok(None),
+            status if !status.is_success() => Err(StorageError::from_status(
+                status.as_u16(),
+                format!("Failed to download {}: {}", key, status),
+            )),
+            _ => Ok(Some(response)),
+        }
+    }
+}
+
+// Block ids of a blob must all have the same length
+fn block_id(upload_id: &str, part_number: i32) -> String {
+    base64::engine::general_purpose::STANDARD.encode(format!("{}-{:06}", upload_id, part_number))
+}
+
+// Properties and conditions of a blob write, set by Put Blob or Put Block List
+fn blob_properties(options: &PutOptions) -> Vec<(String, String)> {
+    let mut headers = vec![("x-ms-blob-content-type".to_string(), options.content_type.clone())];
+    if let Some(e_tag) = &options.if_match {
+        headers.push(("If-Match".to_string(), e_tag.clone()));
+    }
+    if options.if_none_match {
+        headers.push(("If-None-Match".to_string(), "*".to_string()));
+    }
+    if let Some(cache_control) = &options.cache_control {
+        headers.push(("x-ms-blob-cache-control".to_string(), cache_control.clone()));
+    }
+    if let Some(disposition) = &options.content_disposition {
+        headers.push(("x-ms-blob-content-disposition".to_string(), disposition.clone()));
+    }
+    headers.extend(
+        options
+            .metadata
+            .iter()
+            .map(|(name, value)| (format!("x-ms-meta-{}", name), value.clone())),
+    );
+
+    headers
+}
+
+fn encode_path(key: &str) -> String {
+    key.split('/')
+        .map(|segment| urlencoding::encode(segment).into_owned())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+fn header_of(response: &reqwest::Response, name: reqwest::header::HeaderName) -> Option<String> {
+    response
+        .headers()
+        .get(name)
+        .and_then(|v| v.to_str().ok())
+        .map(|v| v.to_string())
+}
+
+#[async_trait]
+impl StorageBackend for AzureBlobBackend {
+    async fn put(&self, key: &str, body: ObjectSource<'_>, options: &PutOptions) -> StorageResult<()> {
+        let total_size = body.len().await?;
+
+        if total_size >= BLOCK_THRESHOLD {
+            return self.put_blocks(key, &body, total_size, options).await;
+        }
+
+        let mut headers = vec![
+            ("Content-Type", options.content_type.clone()),
+            ("x-ms-blob-type", "BlockBlob".to_string()),
+        ];
+        let properties = blob_properties(options);
+        headers.extend(properties.iter().map(|(name, value)| (name.as_str(), value.clone())));
+
+        // Below the block threshold, so small enough to hold in memory
+        let data = body.read_all().await?;
+        let response = self
+            .request(Method::PUT, &self.blob_url(key), &headers, Some(data))?
+            .send()
+            .await?;
+
+        // A create-only write of an existing blob is answered with 409
+        match response.status() {
+            status if status.is_success() => Ok(()),
+            StatusCode::PRECONDITION_FAILED => Err(StorageError::precondition_failed()),
+            StatusCode::CONFLICT if options.if_none_match => Err(StorageError::precondition_failed()),
+            status => Err(StorageError::from_status(
+                status.as_u16(),
+                format!("Failed to upload {}: {}", key, status),
+            )),
+        }
+    }
+
+    async fn get(&self, key: &str) -> StorageResult<Option<StoredObject>> {
+        let Some(response) = self.fetch(key).await? else {
+            return Ok(None);
+        };
+
+        let e_tag = header_of(&response, reqwest::header::ETAG);
+        let content_type = header_of(&response, reqwest::header::CONTENT_TYPE);
+
+        Ok(Some(StoredObject {
+            data: response.bytes().await?.to_vec(),
+            e_tag,
+            content_type,
+        }))
+    }
+
+    async fn download(&self, key: &str, path: &Path) -> StorageResult<Option<DownloadedObject>> {
+        let Some(response) = self.fetch(key).await? else {
+            return Ok(None);
+        };
+
+        let content_type = header_of(&response, reqwest::header::CONTENT_TYPE);
+        let size = save_response(response, path).await?;
+
+        Ok(Some(DownloadedObject { size, content_type }))
+    }
+
+    async fn list(
+        &self,
+        prefix: &str,
+        cursor: Option<String>,
+        max_keys: Option<usize>,
+    ) -> StorageResult<ListPage> {
+        let mut url = format!(
+            "{}/{}?restype=container&comp=list&prefix={}",
+            self.endpoint,
+            self.config.container,
+            urlencoding::encode(prefix)
+        );
+        if let Some(max_keys) = max_keys {
+            url.push_str(&format!("&maxresults={}", max_keys.min(5000)));
+        }
+        if let Some(marker) = &cursor {
+            url.push_str(&format!("&marker={}", urlencoding::encode(marker)));
+        }
+
+        let body = self
+            .request(Method::GET, &url, &[], None)?
+            .send()
+            .await?
+            .error_for_status()?
+            .text()
+            .await?;
+
+        let document = roxmltree::Document::parse(&body).map_err(StorageError::other)?;
+        let child_text = |node: roxmltree::Node, name: &str| {
+            node.descendants()
+                .find(|n| n.has_tag_name(name))
+                .and_then(|n| n.text())
+                .map(|t| t.to_string())
+        };
+
+        let objects = document
+            .descendants()
+            .filter(|n| n.has_tag_name("Blob"))
+            .filter_map(|blob| {
+                Some(ObjectInfo {
+                    key: child_text(blob, "Name")?,
+                    size: child_text(blob, "Content-Length")
+                        .and_then(|s| s.parse().ok())
+                        .unwrap_or(0),
+                    e_tag: child_text(blob, "Etag"),
+                    last_modified: child_text(blob, "Last-Modified"),
+                    storage_class: None,
+                })
+            })
+            .collect();
+
+        let next_cursor = child_text(document.root(), "NextMarker").filter(|m| !m.is_empty());
+
+        Ok(ListPage { objects, next_cursor })
+    }
+
+    async fn delete(&self, key: &str) -> StorageResult<()> {
+        let response = self
+            .request(Method::DELETE, &self.blob_url(key), &[], None)?
+            .send()
+            .await?;
+
+        match response.status() {
+            status if status.is_success() => Ok(()),
+            StatusCode::NOT_FOUND => Ok(()),
+            status => Err(StorageError::from_status(
+                status.as_u16(),
+                format!("Failed to delete {}: {}", key, status),
+            )),
+        }
+    }
+
+    async fn head(&self, key: &str) -> StorageResult<Option<ObjectInfo>> {
+        let response = self
+            .request(Method::HEAD, &self.blob_url(key), &[], None)?
+            .send()
+            .await?;
+
+        match response.status() {
+            StatusCode::NOT_FOUND => return Ok(None),
+            status if !status.is_success() => {
+                return Err(StorageError::from_status(
+                    status.as_u16(),
+                    format!("Failed to read {}: {}", key, status),
+                ))
+            }
+            _ => {}
+        }
+
+        Ok(Some(ObjectInfo {
+            key: key.to_string(),
+            size: header_of(&response, reqwest::header::CONTENT_LENGTH)
+                .and_then(|s| s.parse().ok())
+                .unwrap_or(0),
+            e_tag: header_of(&response, reqwest::header::ETAG),
+            last_modified: header_of(&response, reqwest::header::LAST_MODIFIED),
+            storage_class: None,
+        }))
+    }
+
+    async fn presign(&self, key: &str, expires_in: Duration) -> StorageResult<String> {
+        self.sas_url(key, expires_in)
+    }
+
+    // Private containers only store the object keys
+    fn public_url(&self, key: &str) -> Option<String> {
+        if self.config.private_container {
+            None
+        } else {
+            Some(self.blob_url(key))
+        }
+    }
+
+    async fn content_disposition(&self, key: &str) -> StorageResult<Option<String>> {
+        let response = self
+            .request(Method::HEAD, &self.blob_url(key), &[], None)?
+            .send()
+            .await?;
+
+        match response.status() {
+            StatusCode::NOT_FOUND => Ok(None),
+            status if !status.is_success() => Err(StorageError::from_status(
+                status.as_u16(),
+                format!("Failed to read {}: {}", key, status),
+            )),
+            _ => Ok(header_of(&response, reqwest::header::CONTENT_DISPOSITION)),
+        }
+    }
+}
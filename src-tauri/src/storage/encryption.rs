@@ -0,0 +1,899 @@
+use super::{DeleteFailure, ErrorKind, ListPage, ObjectInfo, ObjectSource, PutOptions, StorageBackend, StorageError, StorageResult, StoredObject};
+use crate::models::StorageClass;
+use aes_gcm::aead::rand_core::RngCore;
+use aes_gcm::aead::{Aead, KeyInit, OsRng, Payload};
+use aes_gcm::{Aes256Gcm, Nonce};
+use argon2::{Algorithm, Argon2, Params, Version};
+use async_trait::async_trait;
+use base64::Engine;
+use chrono::Utc;
+use futures::{stream, StreamExt};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt, BufWriter};
+use uuid::Uuid;
+
+/// Wrapped keys of an encrypted library. Stored unencrypted; everything in
+/// it is useless without the passphrase or the recovery key.
+pub const KEYRING_KEY: &str = "keys/keyring.json";
+const KEYRING_FORMAT_VERSION: u32 = 1;
+
+// Encrypted objects start with this marker, the data key id and the nonce
+const MAGIC: &[u8; 4] = b"GLE1";
+const KEY_ID_LEN: usize = 16;
+const NONCE_LEN: usize = 12;
+const HEADER_LEN: usize = MAGIC.len() + KEY_ID_LEN + NONCE_LEN;
+// Followed by the data in segments of this size, each with its own tag
+const SEGMENT_LEN: usize = 64 * 1024;
+const TAG_LEN: usize = 16;
+
+const REENCRYPT_CONCURRENCY: usize = 8;
+const REENCRYPT_ATTEMPTS: usize = 3;
+
+// OWASP's recommended Argon2id parameters
+const KDF_MEMORY_KIB: u32 = 19 * 1024;
+const KDF_ITERATIONS: u32 = 2;
+const KDF_PARALLELISM: u32 = 1;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct KdfParams {
+    salt: String,
+    memory_kib: u32,
+    iterations: u32,
+    parallelism: u32,
+}
+
+/// The master key, wrapped once with the passphrase and once with the recovery key.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct WrappedMasterKey {
+    by_passphrase: String,
+    by_recovery_key: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Keyring {
+    format_version: u32,
+    updated_at: String,
+    kdf: KdfParams,
+    master_key: WrappedMasterKey,
+    /// Data key id used for new objects
+    active_key: String,
+    /// Data keys by id, wrapped with the master key. Keys replaced by a
+    /// rotation are kept for decrypting: devices that unlocked before it
+    /// keep sealing with the old key until they unlock again.
+    data_keys: BTreeMap<String, String>,
+    /// Set once `reencrypt_library` has encrypted every object. From then on
+    /// objects without an encryption header are rejected rather than read
+    /// as plaintext, so nobody can slip unencrypted objects into the library.
+    #[serde(default)]
+    fully_encrypted: bool,
+}
+
+/// Keys of an unlocked library, kept in memory only.
+#[derive(Clone)]
+pub struct LibraryKeys {
+    master_key: [u8; 32],
+    active_key: Uuid,
+    data_keys: BTreeMap<Uuid, [u8; 32]>,
+    /// Whether objects stored before encryption was enabled may still be around
+    accepts_plaintext: bool,
+}
+
+impl LibraryKeys {
+    fn generate() -> Self {
+        let active_key = Uuid::new_v4();
+
+        Self {
+            master_key: random_key(),
+            active_key,
+            data_keys: BTreeMap::from([(active_key, random_key())]),
+            accepts_plaintext: true,
+        }
+    }
+
+    fn cipher<'a>(&self, key_id: &Uuid, header: &'a [u8], key: &'a str) -> StorageResult<ObjectCipher<'a>> {
+        let data_key = self.data_keys.get(key_id).ok_or_else(|| {
+            StorageError::new(
+                ErrorKind::Locked,
+                "The library keys were rotated on another device, unlock the library again",
+            )
+        })?;
+
+        Ok(ObjectCipher {
+            cipher: Aes256Gcm::new_from_slice(data_key).map_err(StorageError::other)?,
+            header,
+            key,
+        })
+    }
+
+    fn new_header(&self) -> Vec<u8> {
+        let mut nonce = [0u8; NONCE_LEN];
+        OsRng.fill_bytes(&mut nonce);
+
+        [MAGIC.as_slice(), self.active_key.as_bytes(), &nonce].concat()
+    }
+
+    /// Encrypts the object stored at `key`.
+    fn seal(&self, key: &str, plaintext: &[u8]) -> StorageResult<Vec<u8>> {
+        let mut sealed = self.new_header();
+        let header = sealed.clone();
+        let cipher = self.cipher(&self.active_key, &header, key)?;
+
+        let segments = segment_count(plaintext.len() as u64);
+        for index in 0..segments {
+            let start = (index as usize * SEGMENT_LEN).min(plaintext.len());
+            let end = (start + SEGMENT_LEN).min(plaintext.len());
+            sealed.extend(cipher.seal(index, index + 1 == segments, &plaintext[start..end])?);
+        }
+
+        Ok(sealed)
+    }
+
+    /// Encrypts the file at `path` into `sealed_path` a segment at a time.
+    async fn seal_file(&self, key: &str, path: &Path, sealed_path: &Path) -> StorageResult<()> {
+        let header = self.new_header();
+        let cipher = self.cipher(&self.active_key, &header, key)?;
+
+        let mut file = tokio::fs::File::open(path).await?;
+        let mut remaining = file.metadata().await?.len();
+        let mut sealed = BufWriter::new(tokio::fs::File::create(sealed_path).await?);
+        sealed.write_all(&header).await?;
+
+        let segments = segment_count(remaining);
+        let mut buffer = vec![0; SEGMENT_LEN];
+        for index in 0..segments {
+            let length = remaining.min(SEGMENT_LEN as u64) as usize;
+            file.read_exact(&mut buffer[..length]).await?;
+            remaining -= length as u64;
+
+            sealed
+                .write_all(&cipher.seal(index, index + 1 == segments, &buffer[..length])?)
+                .await?;
+        }
+
+        sealed.flush().await?;
+        Ok(())
+    }
+
+    /// Decrypts the object stored at `key`, passing through objects written
+    /// before encryption was enabled until the library is fully encrypted.
+    fn open(&self, key: &str, data: Vec<u8>) -> StorageResult<Vec<u8>> {
+        if !is_encrypted(&data) {
+            if self.accepts_plaintext {
+                return Ok(data);
+            }
+            return Err(StorageError::other(format!(
+                "{} is not encrypted although the library is",
+                key
+            )));
+        }
+
+        let (header, ciphertext) = data.split_at(HEADER_LEN);
+        let key_id = Uuid::from_slice(&header[MAGIC.len()..MAGIC.len() + KEY_ID_LEN]).map_err(StorageError::other)?;
+        let cipher = self.cipher(&key_id, header, key)?;
+
+        // Even an empty object has one segment, holding just its tag
+        let segments: Vec<&[u8]> = ciphertext.chunks(SEGMENT_LEN + TAG_LEN).collect();
+        if segments.is_empty() {
+            return Err(StorageError::other("Encrypted object is corrupted or was tampered with"));
+        }
+
+        let mut plaintext = Vec::with_capacity(ciphertext.len());
+        for (index, segment) in segments.iter().enumerate() {
+            plaintext.extend(cipher.open(index as u64, index + 1 == segments.len(), segment)?);
+        }
+
+        Ok(plaintext)
+    }
+
+    fn is_current(&self, data: &[u8]) -> bool {
+        is_encrypted(data) && data[MAGIC.len()..MAGIC.len() + KEY_ID_LEN] == self.active_key.as_bytes()[..]
+    }
+}
+
+/// Encrypts and decrypts the segments of one object. Each segment is
+/// authenticated with the header, its position, whether it is the last one
+/// and the object key, so segments can't be reordered, dropped or moved to
+/// another object.
+struct ObjectCipher<'a> {
+    cipher: Aes256Gcm,
+    header: &'a [u8],
+    key: &'a str,
+}
+
+impl ObjectCipher<'_> {
+    // The header's nonce with the segment index mixed into its last bytes
+    fn nonce_and_aad(&self, index: u64, last: bool) -> ([u8; NONCE_LEN], Vec<u8>) {
+        let mut nonce = [0u8; NONCE_LEN];
+        nonce.copy_from_slice(&self.header[HEADER_LEN - NONCE_LEN..]);
+        for (byte, counter) in nonce[NONCE_LEN - 8..].iter_mut().zip(index.to_be_bytes()) {
+            *byte ^= counter;
+        }
+
+        let aad = [self.header, &index.to_be_bytes(), &[last as u8], self.key.as_bytes()].concat();
+        (nonce, aad)
+    }
+
+    fn seal(&self, index: u64, last: bool, plaintext: &[u8]) -> StorageResult<Vec<u8>> {
+        let (nonce, aad) = self.nonce_and_aad(index, last);
+
+        self.cipher
+            .encrypt(
+                Nonce::from_slice(&nonce),
+                Payload {
+                    msg: plaintext,
+                    aad: &aad,
+                },
+            )
+            .map_err(|_| StorageError::other("Failed to encrypt object"))
+    }
+
+    fn open(&self, index: u64, last: bool, ciphertext: &[u8]) -> StorageResult<Vec<u8>> {
+        let (nonce, aad) = self.nonce_and_aad(index, last);
+
+        self.cipher
+            .decrypt(
+                Nonce::from_slice(&nonce),
+                Payload {
+                    msg: ciphertext,
+                    aad: &aad,
+                },
+            )
+            .map_err(|_| StorageError::other("Encrypted object is corrupted or was tampered with"))
+    }
+}
+
+fn segment_count(plaintext_len: u64) -> u64 {
+    plaintext_len.div_ceil(SEGMENT_LEN as u64).max(1)
+}
+
+fn is_encrypted(data: &[u8]) -> bool {
+    data.len() >= HEADER_LEN && data.starts_with(MAGIC)
+}
+
+fn random_key() -> [u8; 32] {
+    let mut key = [0u8; 32];
+    OsRng.fill_bytes(&mut key);
+    key
+}
+
+fn b64(data: &[u8]) -> String {
+    base64::engine::general_purpose::STANDARD.encode(data)
+}
+
+fn unb64(data: &str) -> StorageResult<Vec<u8>> {
+    base64::engine::general_purpose::STANDARD
+        .decode(data)
+        .map_err(|_| StorageError::other("Keyring is corrupted"))
+}
+
+/// Encrypts a key with a key-encryption key; the nonce is prepended.
+fn wrap(kek: &[u8; 32], key: &[u8; 32]) -> StorageResult<String> {
+    let mut nonce = [0u8; NONCE_LEN];
+    OsRng.fill_bytes(&mut nonce);
+
+    let wrapped = Aes256Gcm::new_from_slice(kek)
+        .map_err(StorageError::other)?
+        .encrypt(Nonce::from_slice(&nonce), key.as_slice())
+        .map_err(|_| StorageError::other("Failed to wrap key"))?;
+
+    Ok(b64(&[nonce.as_slice(), &wrapped].concat()))
+}
+
+fn unwrap_key(kek: &[u8; 32], wrapped: &str, error: &str) -> StorageResult<[u8; 32]> {
+    let wrapped = unb64(wrapped)?;
+    if wrapped.len() < NONCE_LEN {
+        return Err(StorageError::other("Keyring is corrupted"));
+    }
+    let (nonce, ciphertext) = wrapped.split_at(NONCE_LEN);
+
+    let key = Aes256Gcm::new_from_slice(kek)
+        .map_err(StorageError::other)?
+        .decrypt(Nonce::from_slice(nonce), ciphertext)
+        .map_err(|_| StorageError::new(ErrorKind::Auth, error))?;
+
+    key.try_into()
+        .map_err(|_| StorageError::other("Keyring is corrupted"))
+}
+
+fn derive_key(passphrase: &str, kdf: &KdfParams) -> StorageResult<[u8; 32]> {
+    let params = Params::new(kdf.memory_kib, kdf.iterations, kdf.parallelism, Some(32))
+        .map_err(StorageError::other)?;
+
+    let mut key = [0u8; 32];
+    Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+        .hash_password_into(passphrase.as_bytes(), &unb64(&kdf.salt)?, &mut key)
+        .map_err(StorageError::other)?;
+
+    Ok(key)
+}
+
+fn new_kdf_params() -> KdfParams {
+    let mut salt = [0u8; 16];
+    OsRng.fill_bytes(&mut salt);
+
+    KdfParams {
+        salt: b64(&salt),
+        memory_kib: KDF_MEMORY_KIB,
+        iterations: KDF_ITERATIONS,
+        parallelism: KDF_PARALLELISM,
+    }
+}
+
+/// Recovery keys are shown as groups of hex digits, e.g. `3f2a-91c0-…`.
+fn format_recovery_key(key: &[u8; 32]) -> String {
+    hex::encode(key)
+        .as_bytes()
+        .chunks(4)
+        .map(|chunk| String::from_utf8_lossy(chunk).into_owned())
+        .collect::<Vec<_>>()
+        .join("-")
+}
+
+fn parse_recovery_key(recovery_key: &str) -> StorageResult<[u8; 32]> {
+    let digits: String = recovery_key
+        .chars()
+        .filter(|c| c.is_ascii_hexdigit())
+        .collect();
+
+    hex::decode(digits)
+        .ok()
+        .and_then(|bytes| bytes.try_into().ok())
+        .ok_or_else(|| StorageError::new(ErrorKind::Auth, "That is not a valid recovery key"))
+}
+
+fn build_keyring(keys: &LibraryKeys, passphrase: &str, recovery_key: &[u8; 32]) -> StorageResult<Keyring> {
+    let kdf = new_kdf_params();
+    let passphrase_key = derive_key(passphrase, &kdf)?;
+
+    let mut data_keys = BTreeMap::new();
+    for (id, key) in &keys.data_keys {
+        data_keys.insert(id.to_string(), wrap(&keys.master_key, key)?);
+    }
+
+    Ok(Keyring {
+        format_version: KEYRING_FORMAT_VERSION,
+        updated_at: Utc::now().to_rfc3339(),
+        master_key: WrappedMasterKey {
+            by_passphrase: wrap(&passphrase_key, &keys.master_key)?,
+            by_recovery_key: wrap(recovery_key, &keys.master_key)?,
+        },
+        kdf,
+        active_key: keys.active_key.to_string(),
+        data_keys,
+        fully_encrypted: !keys.accepts_plaintext,
+    })
+}
+
+fn unlock_with_master_key(keyring: &Keyring, master_key: [u8; 32]) -> StorageResult<LibraryKeys> {
+    let mut data_keys = BTreeMap::new();
+    for (id, wrapped) in &keyring.data_keys {
+        let id = Uuid::parse_str(id).map_err(StorageError::other)?;
+        data_keys.insert(id, unwrap_key(&master_key, wrapped, "Keyring is corrupted")?);
+    }
+
+    let active_key = Uuid::parse_str(&keyring.active_key).map_err(StorageError::other)?;
+    if !data_keys.contains_key(&active_key) {
+        return Err(StorageError::other("Keyring is corrupted"));
+    }
+
+    Ok(LibraryKeys {
+        master_key,
+        active_key,
+        data_keys,
+        accepts_plaintext: !keyring.fully_encrypted,
+    })
+}
+
+async fn read_keyring(storage: &dyn StorageBackend) -> StorageResult<Option<(Keyring, Option<String>)>> {
+    let Some(object) = storage.get(KEYRING_KEY).await? else {
+        return Ok(None);
+    };
+
+    let keyring: Keyring = serde_json::from_slice(&object.data)?;
+    if keyring.format_version != KEYRING_FORMAT_VERSION {
+        return Err(StorageError::other(
+            "The library was encrypted by a newer version of Galleria",
+        ));
+    }
+
+    Ok(Some((keyring, object.e_tag)))
+}
+
+async fn write_keyring(
+    storage: &dyn StorageBackend,
+    keyring: &Keyring,
+    e_tag: Option<String>,
+) -> StorageResult<()> {
+    let options = PutOptions {
+        if_none_match: e_tag.is_none(),
+        if_match: e_tag,
+        ..PutOptions::content_type("application/json")
+    };
+
+    storage
+        .put(KEYRING_KEY, ObjectSource::Bytes(&serde_json::to_vec_pretty(keyring)?), &options)
+        .await
+}
+
+pub async fn has_keyring(storage: &dyn StorageBackend) -> StorageResult<bool> {
+    Ok(storage.head(KEYRING_KEY).await?.is_some())
+}
+
+/// Turns on encryption for the library and returns its keys with the
+/// recovery key to show the user once. Existing objects stay readable and
+/// are encrypted by `reencrypt_library`.
+pub async fn enable(storage: &dyn StorageBackend, passphrase: &str) -> StorageResult<(LibraryKeys, String)> {
+    if read_keyring(storage).await?.is_some() {
+        return Err(StorageError::other("The library is already encrypted"));
+    }
+
+    let keys = LibraryKeys::generate();
+    let recovery_key = random_key();
+
+    write_keyring(storage, &build_keyring(&keys, passphrase, &recovery_key)?, None)
+        .await
+        .map_err(|e| match e.kind {
+            ErrorKind::PreconditionFailed => StorageError::other("The library is already encrypted"),
+            _ => e,
+        })?;
+
+    Ok((keys, format_recovery_key(&recovery_key)))
+}
+
+pub async fn unlock(storage: &dyn StorageBackend, passphrase: &str) -> StorageResult<LibraryKeys> {
+    let (keyring, _) = read_keyring(storage)
+        .await?
+        .ok_or_else(|| StorageError::other("The library is not encrypted"))?;
+
+    let passphrase_key = derive_key(passphrase, &keyring.kdf)?;
+    let master_key = unwrap_key(&passphrase_key, &keyring.master_key.by_passphrase, "Wrong passphrase")?;
+
+    unlock_with_master_key(&keyring, master_key)
+}
+
+/// Sets a new passphrase using the recovery key, for when the passphrase is lost.
+pub async fn recover(
+    storage: &dyn StorageBackend,
+    recovery_key: &str,
+    new_passphrase: &str,
+) -> StorageResult<LibraryKeys> {
+    let (mut keyring, e_tag) = read_keyring(storage)
+        .await?
+        .ok_or_else(|| StorageError::other("The library is not encrypted"))?;
+
+    let recovery_key = parse_recovery_key(recovery_key)?;
+    let master_key = unwrap_key(&recovery_key, &keyring.master_key.by_recovery_key, "Wrong recovery key")?;
+    let keys = unlock_with_master_key(&keyring, master_key)?;
+
+    keyring.kdf = new_kdf_params();
+    keyring.master_key.by_passphrase = wrap(&derive_key(new_passphrase, &keyring.kdf)?, &master_key)?;
+    keyring.updated_at = Utc::now().to_rfc3339();
+    write_keyring(storage, &keyring, e_tag).await?;
+
+    Ok(keys)
+}
+
+/// Replaces the master key, data key and recovery key, optionally with a
+/// new passphrase. The previous data keys are kept so existing objects and
+/// objects still written with them by other devices stay readable; returns
+/// the new recovery key.
+pub async fn rotate(
+    storage: &dyn StorageBackend,
+    passphrase: &str,
+    new_passphrase: Option<&str>,
+) -> StorageResult<(LibraryKeys, String)> {
+    let (_, e_tag) = read_keyring(storage)
+        .await?
+        .ok_or_else(|| StorageError::other("The library is not encrypted"))?;
+    let current = unlock(storage, passphrase).await?;
+
+    let mut keys = LibraryKeys::generate();
+    keys.data_keys.extend(current.data_keys);
+    keys.accepts_plaintext = current.accepts_plaintext;
+
+    let recovery_key = random_key();
+    let keyring = build_keyring(&keys, new_passphrase.unwrap_or(passphrase), &recovery_key)?;
+    write_keyring(storage, &keyring, e_tag).await?;
+
+    Ok((keys, format_recovery_key(&recovery_key)))
+}
+
+/// Outcome of `reencrypt_library`.
+#[derive(Debug, Clone, Serialize)]
+pub struct ReencryptReport {
+    pub processed: usize,
+    /// Objects in an archive storage class, which can't be read until they
+    /// are restored; the library stays open to plaintext until they are done
+    pub archived: Vec<String>,
+}
+
+/// Rewrites every object that is not encrypted with the active data key,
+/// then marks the library as fully encrypted. Safe to run again after an
+/// interruption. Returns the updated keys and what was done.
+pub async fn reencrypt_library(
+    storage: &dyn StorageBackend,
+    keys: &LibraryKeys,
+    on_progress: &(dyn Fn(usize) + Send + Sync),
+) -> StorageResult<(LibraryKeys, ReencryptReport)> {
+    let mut report = ReencryptReport {
+        processed: 0,
+        archived: Vec::new(),
+    };
+    let objects = super::list_all(storage, "")
+        .await?
+        .into_iter()
+        .filter(|object| !object.key.starts_with("keys/"));
+
+    let mut rewrites = stream::iter(objects)
+        .map(|object| async move {
+            let result = reencrypt_object(storage, keys, &object).await;
+            (object.key, result)
+        })
+        .buffer_unordered(REENCRYPT_CONCURRENCY);
+
+    while let Some((key, result)) = rewrites.next().await {
+        match result {
+            Ok(()) => {}
+            Err(e) if e.kind == ErrorKind::Archived => report.archived.push(key),
+            Err(e) => return Err(e),
+        }
+        report.processed += 1;
+        on_progress(report.processed);
+    }
+
+    if !report.archived.is_empty() {
+        return Ok((keys.clone(), report));
+    }
+
+    Ok((mark_fully_encrypted(storage, keys).await?, report))
+}
+
+async fn reencrypt_object(storage: &dyn StorageBackend, keys: &LibraryKeys, info: &ObjectInfo) -> StorageResult<()> {
+    let key = info.key.as_str();
+
+    for _ in 0..REENCRYPT_ATTEMPTS {
+        let Some(object) = storage.get(key).await? else {
+            return Ok(());
+        };
+        if keys.is_current(&object.data) {
+            return Ok(());
+        }
+
+        let content_type = object
+            .content_type
+            .unwrap_or_else(|| crate::stacks::content_type_for(key).to_string());
+        let sealed = keys.seal(key, &keys.open(key, object.data)?)?;
+
+        // Only replace the version we read, in case another writer got there first
+        let options = PutOptions {
+            if_match: object.e_tag,
+            storage_class: info.storage_class,
+            cache_control: super::cache_control_for(key),
+            ..PutOptions::content_type(&content_type)
+        };
+
+        match storage.put(key, ObjectSource::Bytes(&sealed), &options).await {
+            Ok(()) => return Ok(()),
+            Err(e) if e.kind == ErrorKind::PreconditionFailed => continue,
+            Err(e) => return Err(e),
+        }
+    }
+
+    Err(StorageError::other(format!("{} kept changing while it was being re-encrypted", key)))
+}
+
+// Old data keys stay in the keyring, since other devices may still be
+// sealing new objects with them
+async fn mark_fully_encrypted(storage: &dyn StorageBackend, keys: &LibraryKeys) -> StorageResult<LibraryKeys> {
+    let (mut keyring, e_tag) = read_keyring(storage)
+        .await?
+        .ok_or_else(|| StorageError::other("The library is not encrypted"))?;
+
+    if !keyring.fully_encrypted {
+        keyring.fully_encrypted = true;
+        keyring.updated_at = Utc::now().to_rfc3339();
+        write_keyring(storage, &keyring, e_tag).await?;
+    }
+
+    let mut keys = keys.clone();
+    keys.accepts_plaintext = false;
+
+    Ok(keys)
+}
+
+/// Encrypts objects before they leave the machine and decrypts them after
+/// download, so the provider only ever stores ciphertext.
+pub struct EncryptedBackend {
+    inner: Arc<dyn StorageBackend>,
+    keys: Arc<LibraryKeys>,
+}
+
+impl EncryptedBackend {
+    pub fn new(inner: Arc<dyn StorageBackend>, keys: Arc<LibraryKeys>) -> Self {
+        Self { inner, keys }
+    }
+}
+
+#[async_trait]
+impl StorageBackend for EncryptedBackend {
+    async fn put(&self, key: &str, body: ObjectSource<'_>, options: &PutOptions) -> StorageResult<()> {
+        // File names and plaintext checksums would leak what the library holds
+        let options = PutOptions {
+            content_disposition: None,
+            metadata: Default::default(),
+            ..options.clone()
+        };
+
+        let path = match body {
+            ObjectSource::Bytes(data) => {
+                let sealed = self.keys.seal(key, data)?;
+                return self.inner.put(key, ObjectSource::Bytes(&sealed), &options).await;
+            }
+            ObjectSource::File(path) => path,
+        };
+
+        // Files are sealed to a temporary file, so the backend can still
+        // stream them and upload large ones in parts
+        let sealed_path = std::env::temp_dir().join(format!("galleria-{}.sealed", Uuid::new_v4()));
+        let result = match self.keys.seal_file(key, path, &sealed_path).await {
+            Ok(()) => self.inner.put(key, ObjectSource::File(&sealed_path), &options).await,
+            Err(e) => Err(e),
+        };
+        let _ = tokio::fs::remove_file(&sealed_path).await;
+
+        result
+    }
+
+    async fn get(&self, key: &str) -> StorageResult<Option<StoredObject>> {
+        let Some(object) = self.inner.get(key).await? else {
+            return Ok(None);
+        };
+
+        Ok(Some(StoredObject {
+            data: self.keys.open(key, object.data)?,
+            ..object
+        }))
+    }
+
+    async fn list(
+        &self,
+        prefix: &str,
+        cursor: Option<String>,
+        max_keys: Option<usize>,
+    ) -> StorageResult<ListPage> {
+        self.inner.list(prefix, cursor, max_keys).await
+    }
+
+    async fn check_bucket(&self) -> StorageResult<()> {
+        self.inner.check_bucket().await
+    }
+
+    async fn delete(&self, key: &str) -> StorageResult<()> {
+        self.inner.delete(key).await
+    }
+
+    async fn delete_many(&self, keys: &[String]) -> StorageResult<Vec<DeleteFailure>> {
+        self.inner.delete_many(keys).await
+    }
+
+    async fn head(&self, key: &str) -> StorageResult<Option<ObjectInfo>> {
+        self.inner.head(key).await
+    }
+
+    // Provider URLs would serve ciphertext, so objects are decrypted by the app
+    async fn presign(&self, key: &str, _expires_in: Duration) -> StorageResult<String> {
+        Ok(super::library_url(key))
+    }
+
+    fn public_url(&self, _key: &str) -> Option<String> {
+        None
+    }
+
+    async fn set_storage_class(&self, key: &str, class: StorageClass) -> StorageResult<()> {
+        self.inner.set_storage_class(key, class).await
+    }
+
+    async fn restore(&self, key: &str, days: u32) -> StorageResult<()> {
+        self.inner.restore(key, days).await
+    }
+
+    // The provider's checksum covers the ciphertext, which never matches
+    // the checksums recorded for the plaintext
+    async fn stored_sha256(&self, _key: &str) -> StorageResult<Option<String>> {
+        Ok(None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::MemoryBackend;
+    use crate::upload_sessions::{MultipartSession, UploadedPart};
+
+    const KEY: &str = "photos/a/original.jpg";
+    const PART_SIZE: usize = 1024;
+
+    fn sha256(data: &[u8]) -> String {
+        use sha2::{Digest, Sha256};
+        hex::encode(Sha256::digest(data))
+    }
+
+    // A multipart upload of `body` that stopped after its first part
+    fn interrupted_upload(body: &[u8]) -> MultipartSession {
+        MultipartSession {
+            upload_id: "upload".to_string(),
+            part_size: PART_SIZE as u64,
+            total_size: body.len() as u64,
+            parts: vec![UploadedPart {
+                part_number: 1,
+                e_tag: "\"1\"".to_string(),
+                checksum_sha256: None,
+            }],
+            checksums: false,
+            content_sha256: Some(sha256(body)),
+        }
+    }
+
+    #[test]
+    fn resealed_upload_starts_over_instead_of_resuming() {
+        let keys = LibraryKeys::generate();
+        let plaintext = vec![7u8; 3 * PART_SIZE];
+
+        let first = keys.seal(KEY, &plaintext).unwrap();
+        let session = interrupted_upload(&first);
+
+        // The retry seals again with a fresh nonce: same size, other bytes
+        let retry = keys.seal(KEY, &plaintext).unwrap();
+        assert_eq!(first.len(), retry.len());
+        assert!(!session.matches(retry.len() as u64, &sha256(&retry)));
+
+        // Resuming would have stored the first part of one and the rest of the other
+        let stitched = [&first[..PART_SIZE], &retry[PART_SIZE..]].concat();
+        assert!(keys.open(KEY, stitched).is_err());
+
+        // Starting over stores the retry alone
+        assert_eq!(keys.open(KEY, retry).unwrap(), plaintext);
+    }
+
+    #[test]
+    fn upload_of_the_same_bytes_resumes() {
+        let keys = LibraryKeys::generate();
+        let plaintext = vec![7u8; 3 * PART_SIZE];
+        let sealed = keys.seal(KEY, &plaintext).unwrap();
+
+        let session = interrupted_upload(&sealed);
+        assert!(session.matches(sealed.len() as u64, &sha256(&sealed)));
+
+        let resumed = [&sealed[..PART_SIZE], &sealed[PART_SIZE..]].concat();
+        assert_eq!(keys.open(KEY, resumed).unwrap(), plaintext);
+    }
+
+    #[test]
+    fn session_without_a_hash_is_not_resumed() {
+        let body = vec![1u8; 2 * PART_SIZE];
+        let session = MultipartSession {
+            content_sha256: None,
+            ..interrupted_upload(&body)
+        };
+
+        assert!(!session.matches(body.len() as u64, &sha256(&body)));
+    }
+
+    #[test]
+    fn ciphertext_moved_to_another_key_is_rejected() {
+        let keys = LibraryKeys::generate();
+        let sealed = keys.seal(KEY, b"photo").unwrap();
+
+        assert!(keys.open("photos/b/original.jpg", sealed).is_err());
+    }
+
+    #[tokio::test]
+    async fn plaintext_is_rejected_once_the_library_is_fully_encrypted() {
+        let storage = MemoryBackend::default();
+        let options = PutOptions::content_type("image/jpeg");
+        storage.put(KEY, ObjectSource::Bytes(b"photo"), &options).await.unwrap();
+
+        let (keys, _) = enable(&storage, "correct horse battery").await.unwrap();
+        let before = EncryptedBackend::new(Arc::new(storage.clone()), Arc::new(keys.clone()));
+        assert_eq!(before.get(KEY).await.unwrap().unwrap().data, b"photo");
+
+        let (keys, report) = reencrypt_library(&storage, &keys, &|_| {}).await.unwrap();
+        assert_eq!(report.processed, 1);
+        assert!(report.archived.is_empty());
+        let after = EncryptedBackend::new(Arc::new(storage.clone()), Arc::new(keys));
+        assert_eq!(after.get(KEY).await.unwrap().unwrap().data, b"photo");
+
+        // Written behind the library's back
+        storage.put(KEY, ObjectSource::Bytes(b"forged"), &options).await.unwrap();
+        assert!(after.get(KEY).await.is_err());
+
+        // Other devices learn about it when they unlock
+        let unlocked = unlock(&storage, "correct horse battery").await.unwrap();
+        assert!(!unlocked.accepts_plaintext);
+    }
+
+    #[tokio::test]
+    async fn old_keys_stay_readable_after_reencrypting() {
+        let storage = MemoryBackend::default();
+        let (stale, _) = enable(&storage, "correct horse battery").await.unwrap();
+        let (keys, _) = rotate(&storage, "correct horse battery", None).await.unwrap();
+        reencrypt_library(&storage, &keys, &|_| {}).await.unwrap();
+
+        // Another device still holding the keys from before the rotation
+        let stale = EncryptedBackend::new(Arc::new(storage.clone()), Arc::new(stale));
+        let options = PutOptions::content_type("image/jpeg");
+        stale.put(KEY, ObjectSource::Bytes(b"photo"), &options).await.unwrap();
+
+        let unlocked = unlock(&storage, "correct horse battery").await.unwrap();
+        let current = EncryptedBackend::new(Arc::new(storage.clone()), Arc::new(unlocked));
+        assert_eq!(current.get(KEY).await.unwrap().unwrap().data, b"photo");
+    }
+
+    #[tokio::test]
+    async fn files_are_sealed_in_segments_without_loading_them_whole() {
+        let storage = MemoryBackend::default();
+        let keys = Arc::new(LibraryKeys::generate());
+        let backend = EncryptedBackend::new(Arc::new(storage.clone()), keys.clone());
+
+        let plaintext: Vec<u8> = (0..3 * SEGMENT_LEN + 100).map(|i| i as u8).collect();
+        let path = std::env::temp_dir().join(format!("galleria-test-{}", Uuid::new_v4()));
+        std::fs::write(&path, &plaintext).unwrap();
+
+        let options = PutOptions::content_type("image/jpeg");
+        backend.put(KEY, ObjectSource::File(&path), &options).await.unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let stored = storage.get(KEY).await.unwrap().unwrap().data;
+        assert_eq!(stored.len(), HEADER_LEN + plaintext.len() + 4 * TAG_LEN);
+        assert_eq!(backend.get(KEY).await.unwrap().unwrap().data, plaintext);
+
+        // Sealing in memory gives the same layout
+        assert_eq!(keys.seal(KEY, &plaintext).unwrap().len(), stored.len());
+    }
+
+    #[test]
+    fn truncated_object_is_rejected() {
+        let keys = LibraryKeys::generate();
+        let plaintext = vec![1u8; 2 * SEGMENT_LEN];
+        let sealed = keys.seal(KEY, &plaintext).unwrap();
+
+        // Cut off after the first segment, which on its own looks complete
+        let truncated = sealed[..HEADER_LEN + SEGMENT_LEN + TAG_LEN].to_vec();
+        assert!(keys.open(KEY, truncated).is_err());
+    }
+
+    #[test]
+    fn sealed_objects_round_trip() {
+        let keys = LibraryKeys::generate();
+
+        for plaintext in [Vec::new(), b"photo".to_vec(), vec![3u8; SEGMENT_LEN], vec![5u8; SEGMENT_LEN + 1]] {
+            let sealed = keys.seal(KEY, &plaintext).unwrap();
+            assert_ne!(sealed[HEADER_LEN..], plaintext[..]);
+            assert_eq!(keys.open(KEY, sealed).unwrap(), plaintext);
+        }
+    }
+
+    #[test]
+    fn tampered_objects_are_rejected() {
+        let keys = LibraryKeys::generate();
+        let sealed = keys.seal(KEY, &vec![9u8; 2 * SEGMENT_LEN]).unwrap();
+
+        // A flipped bit in the header, the first segment, the last segment and a tag
+        for position in [HEADER_LEN - 1, HEADER_LEN, sealed.len() - TAG_LEN - 1, sealed.len() - 1] {
+            let mut tampered = sealed.clone();
+            tampered[position] ^= 1;
+            assert!(keys.open(KEY, tampered).is_err(), "accepted a change at byte {}", position);
+        }
+    }
+
+    #[test]
+    fn objects_sealed_with_other_keys_are_rejected() {
+        let sealed = LibraryKeys::generate().seal(KEY, b"photo").unwrap();
+
+        assert!(LibraryKeys::generate().open(KEY, sealed).is_err());
+    }
+}
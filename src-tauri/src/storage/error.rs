@@ -0,0 +1,155 @@
+use serde::{Deserialize, Serialize};
+
+/// What went wrong talking to storage, sent to the frontend as the error `code`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorKind {
+    /// Credentials are missing, wrong, expired or lack permission
+    Auth,
+    NotFound,
+    /// The provider asked us to slow down (429 / 503 SlowDown)
+    Throttled,
+    /// Timeouts, dropped connections and transient server errors
+    Network,
+    /// The bucket, account or disk is out of space
+    Quota,
+    /// A conditional write lost against another writer
+    PreconditionFailed,
+    NotConfigured,
+    /// The library is encrypted and has not been unlocked with its passphrase
+    Locked,
+    /// The object is in an archive storage class and has to be restored first
+    Archived,
+    Other,
+}
+
+impl ErrorKind {
+    /// Errors that are worth trying again after a short wait.
+    pub fn is_retryable(self) -> bool {
+        matches!(self, ErrorKind::Throttled | ErrorKind::Network)
+    }
+
+    pub fn from_status(status: u16) -> Self {
+        match status {
+            401 | 403 => ErrorKind::Auth,
+            404 => ErrorKind::NotFound,
+            412 => ErrorKind::PreconditionFailed,
+            429 | 503 => ErrorKind::Throttled,
+            507 => ErrorKind::Quota,
+            408 | 500..=599 => ErrorKind::Network,
+            _ => ErrorKind::Other,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StorageError {
+    #[serde(rename = "code")]
+    pub kind: ErrorKind,
+    pub message: String,
+}
+
+impl StorageError {
+    pub fn new(kind: ErrorKind, message: impl Into<String>) -> Self {
+        Self {
+            kind,
+            message: message.into(),
+        }
+    }
+
+    pub fn from_status(status: u16, message: impl Into<String>) -> Self {
+        Self::new(ErrorKind::from_status(status), message)
+    }
+
+    pub fn other(error: impl std::fmt::Display) -> Self {
+        Self::new(ErrorKind::Other, error.to_string())
+    }
+
+    pub fn precondition_failed() -> Self {
+        Self::new(
+            ErrorKind::PreconditionFailed,
+            "The object was changed by another writer",
+        )
+    }
+
+    pub fn is_retryable(&self) -> bool {
+        self.kind.is_retryable()
+    }
+
+    /// Prefixes the message while keeping the kind, e.g. "Failed to upload photo: …".
+    pub fn context(self, context: &str) -> Self {
+        Self {
+            kind: self.kind,
+            message: format!("{}: {}", context, self.message),
+        }
+    }
+}
+
+impl std::fmt::Display for StorageError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl std::error::Error for StorageError {}
+
+impl From<String> for StorageError {
+    fn from(message: String) -> Self {
+        Self::new(ErrorKind::Other, message)
+    }
+}
+
+impl From<&str> for StorageError {
+    fn from(message: &str) -> Self {
+        Self::new(ErrorKind::Other, message)
+    }
+}
+
+impl From<Box<dyn std::error::Error + Send + Sync>> for StorageError {
+    fn from(error: Box<dyn std::error::Error + Send + Sync>) -> Self {
+        match error.downcast::<StorageError>() {
+            Ok(error) => *error,
+            Err(error) => Self::new(ErrorKind::Other, error.to_string()),
+        }
+    }
+}
+
+impl From<std::io::Error> for StorageError {
+    fn from(error: std::io::Error) -> Self {
+        use std::io::ErrorKind as Io;
+
+        let kind = match error.kind() {
+            Io::NotFound => ErrorKind::NotFound,
+            Io::PermissionDenied => ErrorKind::Auth,
+            Io::StorageFull | Io::QuotaExceeded => ErrorKind::Quota,
+            Io::TimedOut
+            | Io::ConnectionRefused
+            | Io::ConnectionReset
+            | Io::ConnectionAborted
+            | Io::NotConnected
+            | Io::BrokenPipe
+            | Io::UnexpectedEof => ErrorKind::Network,
+            _ => ErrorKind::Other,
+        };
+
+        Self::new(kind, error.to_string())
+    }
+}
+
+impl From<reqwest::Error> for StorageError {
+    fn from(error: reqwest::Error) -> Self {
+        let kind = match error.status() {
+            Some(status) => ErrorKind::from_status(status.as_u16()),
+            None if error.is_timeout() || error.is_connect() || error.is_request() => ErrorKind::Network,
+            None => ErrorKind::Other,
+        };
+
+        Self::new(kind, error.to_string())
+    }
+}
+
+impl From<serde_json::Error> for StorageError {
+    fn from(error: serde_json::Error) -> Self {
+        Self::new(ErrorKind::Other, format!("Invalid JSON: {}", error))
+    }
+}
@@ -0,0 +1,615 @@
+use super::{save_response, DownloadedObject, ErrorKind, ListPage, ObjectInfo, ObjectSource, PutOptions, StorageBackend, StorageError, StorageResult, StoredObject, UploadThrottle};
+use crate::models::GcsConfig;
+use crate::upload_sessions::{MultipartSession, UploadSessionStore};
+use async_trait::async_trait;
+use base64::Engine;
+use chrono::Utc;
+use reqwest::StatusCode;
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use std::path::Path;
+use std::time::{Duration, Instant};
+use tokio::sync::Mutex;
+
+const DEFAULT_ENDPOINT: &str = "https://storage.googleapis.com";
+const SCOPE: &str = "https://www.googleapis.com/auth/devstorage.read_write";
+// Refresh the access token this long before Google expires it
+const TOKEN_REFRESH_MARGIN: Duration = Duration::from_secs(60);
+// Objects at or above this size are sent with a resumable upload
+const RESUMABLE_THRESHOLD: u64 = 16 * 1024 * 1024;
+// Every chunk but the last must be a multiple of 256 KiB
+const CHUNK_SIZE: u64 = 8 * 1024 * 1024;
+
+#[derive(Deserialize)]
+struct ServiceAccount {
+    client_email: String,
+    private_key: String,
+    token_uri: String,
+}
+
+#[derive(Deserialize)]
+struct TokenResponse {
+    access_token: String,
+    expires_in: u64,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct GcsObject {
+    name: String,
+    #[serde(default)]
+    size: Option<String>,
+    #[serde(default)]
+    generation: Option<String>,
+    #[serde(default)]
+    updated: Option<String>,
+    #[serde(default)]
+    content_disposition: Option<String>,
+}
+
+impl GcsObject {
+    fn into_info(self) -> ObjectInfo {
+        ObjectInfo {
+            size: self.size.and_then(|s| s.parse().ok()).unwrap_or(0),
+            e_tag: self.generation,
+            last_modified: self.updated,
+            storage_class: None,
+            key: self.name,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct GcsListResponse {
+    #[serde(default)]
+    items: Vec<GcsObject>,
+    next_page_token: Option<String>,
+}
+
+/// Keeps the library in a Google Cloud Storage bucket through the JSON API.
+/// Without a service account the requests are unauthenticated, which is
+/// what the fake-gcs-server emulator expects.
+///
+/// The object generation stands in for the ETag, so conditional writes map
+/// onto `ifGenerationMatch`.
+pub struct GcsBackend {
+    client: reqwest::Client,
+    config: GcsConfig,
+    endpoint: String,
+    service_account: Option<ServiceAccount>,
+    token: Mutex<Option<(String, Instant)>>,
+    sessions: UploadSessionStore,
+}
+
+impl GcsBackend {
+    pub fn new(config: &GcsConfig, sessions: UploadSessionStore) -> StorageResult<Self> {
+        let service_account = match &config.service_account_json {
+            Some(json) if !json.trim().is_empty() => Some(serde_json::from_str(json)?),
+            _ => None,
+        };
+
+        let endpoint = config
+            .endpoint_url
+            .as_deref()
+            .unwrap_or(DEFAULT_ENDPOINT)
+            .trim_end_matches('/')
+            .to_string();
+
+        Ok(Self {
+            client: reqwest::Client::new(),
+            config: config.clone(),
+            endpoint,
+            service_account,
+            token: Mutex::new(None),
+            sessions,
+        })
+    }
+
+    fn object_url(&self, key: &str) -> String {
+        format!(
+            "{}/storage/v1/b/{}/o/{}",
+            self.endpoint,
+            self.config.bucket,
+            urlencoding::encode(key)
+        )
+    }
+
+    async fn access_token(&self) -> StorageResult<Option<String>> {
+        let Some(account) = &self.service_account else {
+            return Ok(None);
+        };
+
+        let mut token = self.token.lock().await;
+        if let Some((value, expires_at)) = token.as_ref() {
+            if Instant::now() + TOKEN_REFRESH_MARGIN < *expires_at {
+                return Ok(Some(value.clone()));
+            }
+        }
+
+        let now = Utc::now().timestamp();
+        let claims = serde_json::json!({
+            "iss": account.client_email,
+            "scope": SCOPE,
+            "aud": account.token_uri,
+            "iat": now,
+            "exp": now + 3600,
+        });
+        let assertion = jsonwebtoken::encode(
+            &jsonwebtoken::Header::new(jsonwebtoken::Algorithm::RS256),
+            &claims,
+            &signing_key(account)?,
+        )
+        .map_err(invalid_key)?;
+
+        let response: TokenResponse = self
+            .client
+            .post(&account.token_uri)
+            .form(&[
+                ("grant_type", "urn:ietf:params:oauth:grant-type:jwt-bearer"),
+                ("assertion", assertion.as_str()),
+            ])
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+
+        let expires_at = Instant::now() + Duration::from_secs(response.expires_in);
+        *token = Some((response.access_token.clone(), expires_at));
+
+        Ok(Some(response.access_token))
+    }
+
+    async fn request(&self, method: reqwest::Method, url: &str) -> StorageResult<reqwest::RequestBuilder> {
+        let request = self.client.request(method, url);
+
+        Ok(match self.access_token().await? {
+            Some(token) => request.bearer_auth(token),
+            None => request,
+        })
+    }
+
+    /// V4 signed URL, see https://cloud.google.com/storage/docs/access-control/signing-urls-manually
+    fn signed_url(&self, account: &ServiceAccount, key: &str, expires_in: Duration) -> StorageResult<String> {
+        let now = Utc::now();
+        let datetime = now.format("%Y%m%dT%H%M%SZ").to_string();
+        let date = now.format("%Y%m%d").to_string();
+        let scope = format!("{}/auto/storage/goog4_request", date);
+
+        let host = reqwest::Url::parse(&self.endpoint)
+            .map_err(StorageError::other)?
+            .host_str()
+            .ok_or("Invalid GCS endpoint")?
+            .to_string();
+        let path = format!("/{}/{}", self.config.bucket, encode_path(key));
+
+        let query = format!(
+            "X-Goog-Algorithm=GOOG4-RSA-SHA256&X-Goog-Credential={}&X-Goog-Date={}&X-Goog-Expires={}&X-Goog-SignedHeaders=host",
+            urlencoding::encode(&format!("{}/{}", account.client_email, scope)),
+            datetime,
+            expires_in.as_secs(),
+        );
+
+        let canonical_request = format!(
+            "GET\n{}\n{}\nhost:{}\n\nhost\nUNSIGNED-PAYLOAD",
+            path, query, host
+        );
+        let string_to_sign = format!(
+            "GOOG4-RSA-SHA256\n{}\n{}\n{}",
+            datetime,
+            scope,
+            hex::encode(Sha256::digest(canonical_request.as_bytes()))
+        );
+
+        let signature = jsonwebtoken::crypto::sign(
+            string_to_sign.as_bytes(),
+            &signing_key(account)?,
+            jsonwebtoken::Algorithm::RS256,
+        )
+        .map_err(invalid_key)?;
+        let signature = base64::engine::general_purpose::URL_SAFE_NO_PAD
+            .decode(signature)
+            .map_err(StorageError::other)?;
+
+        Ok(format!(
+            "{}{}?{}&X-Goog-Signature={}",
+            self.endpoint,
+            path,
+            query,
+            hex::encode(signature)
+        ))
+    }
+
+    /// Resumable upload, see https://cloud.google.com/storage/docs/performing-resumable-uploads.
+    /// The session URI is kept with the upload sessions, so an interrupted
+    /// upload carries on from what Google already has.
+    async fn put_resumable(
+        &self,
+        key: &str,
+        source: &ObjectSource<'_>,
+        total_size: u64,
+        options: &PutOptions,
+    ) -> StorageResult<()> {
+        let content_sha256 = source.sha256().await?;
+        let (session_url, mut offset) = self
+            .resume_or_start_upload(key, total_size, &content_sha256, options)
+            .await?;
+
+        while offset < total_size {
+            let length = CHUNK_SIZE.min(total_size - offset);
+            let chunk = source.read_range(offset, length).await?;
+
+            // The session URI authorizes the upload by itself
+            let response = self
+                .client
+                .put(&session_url)
+                .header(reqwest::header::CONTENT_LENGTH, length)
+                .header(
+                    reqwest::header::CONTENT_RANGE,
+                    format!("bytes {}-{}/{}", offset, offset + length - 1, total_size),
+                )
+                .body(UploadThrottle::shared().body(chunk).into_reqwest())
+                .send()
+                .await?;
+
+            match response.status() {
+                status if status.is_success() => break,
+                StatusCode::PERMANENT_REDIRECT => offset = persisted_size(&response),
+                // A failed precondition ends the session for good
+                StatusCode::PRECONDITION_FAILED => {
+                    self.sessions.finish_multipart(key)?;
+                    return Err(StorageError::precondition_failed());
+                }
+                status => {
+                    return Err(StorageError::from_status(
+                        status.as_u16(),
+                        format!("Failed to upload {}: {}", key, status),
+                    ))
+                }
+            }
+        }
+
+        self.sessions.finish_multipart(key)?;
+
+        Ok(())
+    }
+
+    // Session URI of the upload and how much of the body Google already has
+    async fn resume_or_start_upload(
+        &self,
+        key: &str,
+        total_size: u64,
+        content_sha256: &str,
+        options: &PutOptions,
+    ) -> StorageResult<(String, u64)> {
+        if let Some(session) = self.sessions.multipart(key) {
+            if session.matches(total_size, content_sha256) {
+                if let Some(offset) = self.upload_status(&session.upload_id, total_size).await? {
+                    return Ok((session.upload_id, offset));
+                }
+            } else {
+                let _ = self.client.delete(&session.upload_id).send().await;
+            }
+        }
+
+        let mut url = format!(
+            "{}/upload/storage/v1/b/{}/o?uploadType=resumable",
+            self.endpoint, self.config.bucket
+        );
+        push_preconditions(&mut url, options);
+
+        let response = self
+            .request(reqwest::Method::POST, &url)
+            .await?
+            .header("X-Upload-Content-Type", &options.content_type)
+            .header("X-Upload-Content-Length", total_size)
+            .json(&object_resource(key, options)?)
+            .send()
+            .await?;
+
+        match response.status() {
+            status if status.is_success() => {}
+            StatusCode::PRECONDITION_FAILED => return Err(StorageError::precondition_failed()),
+            status => {
+                return Err(StorageError::from_status(
+                    status.as_u16(),
+                    format!("Failed to start uploading {}: {}", key, status),
+                ))
+            }
+        }
+
+        let session_url = header_of(&response, "location").ok_or("GCS did not return an upload session")?;
+        self.sessions.start_multipart(
+            key,
+            MultipartSession {
+                upload_id: session_url.clone(),
+                part_size: CHUNK_SIZE,
+                total_size,
+                parts: Vec::new(),
+                checksums: false,
+                content_sha256: Some(content_sha256.to_string()),
+            },
+        )?;
+
+        Ok((session_url, 0))
+    }
+
+    // How much of an upload Google has, or `None` once the session expired
+    async fn upload_status(&self, session_url: &str, total_size: u64) -> StorageResult<Option<u64>> {
+        let response = self
+            .client
+            .put(session_url)
+            .header(reqwest::header::CONTENT_LENGTH, 0)
+            .header(reqwest::header::CONTENT_RANGE, format!("bytes */{}", total_size))
+            .send()
+            .await?;
+
+        match response.status() {
+            StatusCode::PERMANENT_REDIRECT => Ok(Some(persisted_size(&response))),
+            status if status.is_success() => Ok(Some(total_size)),
+            StatusCode::NOT_FOUND | StatusCode::GONE => Ok(None),
+            status => Err(StorageError::from_status(
+                status.as_u16(),
+                format!("Failed to resume upload: {}", status),
+            )),
+        }
+    }
+
+    // GET that leaves reading the body to the caller
+    async fn fetch(&self, key: &str) -> StorageResult<Option<reqwest::Response>> {
+        let url = format!("{}?alt=media", self.object_url(key));
+        let response = self.request(reqwest::Method::GET, &url).await?.send().await?;
+
+        match response.status() {
+            StatusCode::NOT_FOUND => Ok(None),
+            status if !status.is_success() => Err(StorageError::from_status(
+                status.as_u16(),
+                format!("Failed to download {}: {}", key, status),
+            )),
+            _ => Ok(Some(response)),
+        }
+    }
+}
+
+fn header_of(response: &reqwest::Response, name: &str) -> Option<String> {
+    response
+        .headers()
+        .get(name)
+        .and_then(|v| v.to_str().ok())
+        .map(|v| v.to_string())
+}
+
+// Bytes received so far, from the `Range: bytes=0-N` of a 308 response
+fn persisted_size(response: &reqwest::Response) -> u64 {
+    header_of(response, "range")
+        .and_then(|range| range.rsplit('-').next()?.parse::<u64>().ok())
+        .map_or(0, |last| last + 1)
+}
+
+// Generation 0 means "only if the object does not exist yet"
+fn push_preconditions(url: &mut String, options: &PutOptions) {
+    if options.if_none_match {
+        url.push_str("&ifGenerationMatch=0");
+    } else if let Some(generation) = &options.if_match {
+        url.push_str(&format!("&ifGenerationMatch={}", generation));
+    }
+}
+
+fn signing_key(account: &ServiceAccount) -> StorageResult<jsonwebtoken::EncodingKey> {
+    jsonwebtoken::EncodingKey::from_rsa_pem(account.private_key.as_bytes()).map_err(invalid_key)
+}
+
+fn invalid_key(error: jsonwebtoken::errors::Error) -> StorageError {
+    StorageError::new(ErrorKind::Auth, format!("Invalid service account key: {}", error))
+}
+
+// Object resource carrying cache control, disposition and custom metadata
+fn object_resource(key: &str, options: &PutOptions) -> StorageResult<serde_json::Value> {
+    let mut resource = serde_json::json!({
+        "name": key,
+        "contentType": options.content_type,
+    });
+    if let Some(cache_control) = &options.cache_control {
+        resource["cacheControl"] = cache_control.as_str().into();
+    }
+    if let Some(disposition) = &options.content_disposition {
+        resource["contentDisposition"] = disposition.as_str().into();
+    }
+    if !options.metadata.is_empty() {
+        resource["metadata"] = serde_json::to_value(&options.metadata)?;
+    }
+
+    Ok(resource)
+}
+
+fn multipart_body(key: &str, options: &PutOptions, data: &[u8], boundary: &str) -> StorageResult<Vec<u8>> {
+    let resource = object_resource(key, options)?;
+    let mut body = format!(
+        "--{boundary}\r\nContent-Type: application/json; charset=UTF-8\r\n\r\n{}\r\n--{boundary}\r\nContent-Type: {}\r\n\r\n",
+        resource, options.content_type,
+    )
+    .into_bytes();
+    body.extend_from_slice(data);
+    body.extend_from_slice(format!("\r\n--{}--", boundary).as_bytes());
+
+    Ok(body)
+}
+
+fn encode_path(key: &str) -> String {
+    key.split('/')
+        .map(|segment| urlencoding::encode(segment).into_owned())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+#[async_trait]
+impl StorageBackend for GcsBackend {
+    async fn put(&self, key: &str, body: ObjectSource<'_>, options: &PutOptions) -> StorageResult<()> {
+        let total_size = body.len().await?;
+
+        if total_size >= RESUMABLE_THRESHOLD {
+            return self.put_resumable(key, &body, total_size, options).await;
+        }
+
+        // Multipart uploads carry the object resource (cache control,
+        // disposition and custom metadata) along with the data
+        let mut url = format!(
+            "{}/upload/storage/v1/b/{}/o?uploadType=multipart",
+            self.endpoint, self.config.bucket
+        );
+        push_preconditions(&mut url, options);
+
+        // Below the resumable threshold, so small enough to hold in memory
+        let boundary = format!("galleria-{}", uuid::Uuid::new_v4());
+        let data = multipart_body(key, options, &body.read_all().await?, &boundary)?;
+        let response = self
+            .request(reqwest::Method::POST, &url)
+            .await?
+            .header(
+                "Content-Type",
+                format!("multipart/related; boundary={}", boundary),
+            )
+            .header(reqwest::header::CONTENT_LENGTH, data.len())
+            .body(UploadThrottle::shared().body(data).into_reqwest())
+            .send()
+            .await?;
+
+        match response.status() {
+            status if status.is_success() => Ok(()),
+            StatusCode::PRECONDITION_FAILED => Err(StorageError::precondition_failed()),
+            status => Err(StorageError::from_status(
+                status.as_u16(),
+                format!("Failed to upload {}: {}", key, status),
+            )),
+        }
+    }
+
+    async fn get(&self, key: &str) -> StorageResult<Option<StoredObject>> {
+        let Some(response) = self.fetch(key).await? else {
+            return Ok(None);
+        };
+
+        let e_tag = header_of(&response, "x-goog-generation");
+        let content_type = header_of(&response, "content-type");
+
+        Ok(Some(StoredObject {
+            data: response.bytes().await?.to_vec(),
+            e_tag,
+            content_type,
+        }))
+    }
+
+    async fn download(&self, key: &str, path: &Path) -> StorageResult<Option<DownloadedObject>> {
+        let Some(response) = self.fetch(key).await? else {
+            return Ok(None);
+        };
+
+        let content_type = header_of(&response, "content-type");
+        let size = save_response(response, path).await?;
+
+        Ok(Some(DownloadedObject { size, content_type }))
+    }
+
+    async fn list(
+        &self,
+        prefix: &str,
+        cursor: Option<String>,
+        max_keys: Option<usize>,
+    ) -> StorageResult<ListPage> {
+        let mut url = format!(
+            "{}/storage/v1/b/{}/o?prefix={}",
+            self.endpoint,
+            self.config.bucket,
+            urlencoding::encode(prefix)
+        );
+        if let Some(max_keys) = max_keys {
+            url.push_str(&format!("&maxResults={}", max_keys.min(1000)));
+        }
+        if let Some(token) = &cursor {
+            url.push_str(&format!("&pageToken={}", urlencoding::encode(token)));
+        }
+
+        let response: GcsListResponse = self
+            .request(reqwest::Method::GET, &url)
+            .await?
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+
+        Ok(ListPage {
+            objects: response.items.into_iter().map(GcsObject::into_info).collect(),
+            next_cursor: response.next_page_token,
+        })
+    }
+
+    async fn delete(&self, key: &str) -> StorageResult<()> {
+        let response = self
+            .request(reqwest::Method::DELETE, &self.object_url(key))
+            .await?
+            .send()
+            .await?;
+
+        match response.status() {
+            status if status.is_success() => Ok(()),
+            StatusCode::NOT_FOUND => Ok(()),
+            status => Err(StorageError::from_status(
+                status.as_u16(),
+                format!("Failed to delete {}: {}", key, status),
+            )),
+        }
+    }
+
+    async fn head(&self, key: &str) -> StorageResult<Option<ObjectInfo>> {
+        let response = self
+            .request(reqwest::Method::GET, &self.object_url(key))
+            .await?
+            .send()
+            .await?;
+
+        match response.status() {
+            StatusCode::NOT_FOUND => Ok(None),
+            status if !status.is_success() => Err(StorageError::from_status(
+                status.as_u16(),
+                format!("Failed to read {}: {}", key, status),
+            )),
+            _ => Ok(Some(response.json::<GcsObject>().await?.into_info())),
+        }
+    }
+
+    async fn presign(&self, key: &str, expires_in: Duration) -> StorageResult<String> {
+        match &self.service_account {
+            Some(account) => self.signed_url(account, key, expires_in),
+            // The emulator serves objects without authentication
+            None => Ok(format!("{}/{}/{}", self.endpoint, self.config.bucket, encode_path(key))),
+        }
+    }
+
+    // Private buckets only store the object keys
+    fn public_url(&self, key: &str) -> Option<String> {
+        if self.config.private_bucket {
+            None
+        } else {
+            Some(format!("{}/{}/{}", self.endpoint, self.config.bucket, encode_path(key)))
+        }
+    }
+
+    async fn content_disposition(&self, key: &str) -> StorageResult<Option<String>> {
+        let response = self
+            .request(reqwest::Method::GET, &self.object_url(key))
+            .await?
+            .send()
+            .await?;
+
+        match response.status() {
+            StatusCode::NOT_FOUND => Ok(None),
+            status if !status.is_success() => Err(StorageError::from_status(
+                status.as_u16(),
+                format!("Failed to read {}: {}", key, status),
+            )),
+            _ => Ok(response.json::<GcsObject>().await?.content_disposition),
+        }
+    }
+}
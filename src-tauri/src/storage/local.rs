@@ -0,0 +1,291 @@
+use super::{DownloadedObject, ListPage, ObjectInfo, ObjectSource, PutOptions, StorageBackend, StorageError, StorageResult, StoredObject};
+use async_trait::async_trait;
+use sha2::{Digest, Sha256};
+use std::path::{Component, Path, PathBuf};
+use std::time::Duration;
+use tokio::sync::Mutex;
+use uuid::Uuid;
+
+const DEFAULT_PAGE_SIZE: usize = 1000;
+
+/// Keeps the library in a directory on disk, e.g. an external drive for
+/// offline use. Keys map directly to relative paths under the root.
+pub struct LocalBackend {
+    root: PathBuf,
+    // Serializes conditional writes so check-and-replace is atomic
+    write_lock: Mutex<()>,
+}
+
+impl LocalBackend {
+    pub fn new(root: &str) -> StorageResult<Self> {
+        let root = PathBuf::from(root);
+        std::fs::create_dir_all(&root)?;
+
+        Ok(Self {
+            root,
+            write_lock: Mutex::new(()),
+        })
+    }
+
+    fn path_for(&self, key: &str) -> StorageResult<PathBuf> {
+        let relative = Path::new(key);
+        if !relative
+            .components()
+            .all(|c| matches!(c, Component::Normal(_)))
+        {
+            return Err(format!("Invalid object key: {}", key).into());
+        }
+
+        Ok(self.root.join(relative))
+    }
+
+    /// Up to `limit` keys starting with `prefix` after `cursor`, in order.
+    /// Only the directories that can hold such keys are read.
+    async fn collect_keys(&self, prefix: &str, cursor: Option<&str>, limit: usize) -> StorageResult<Vec<String>> {
+        let mut keys = Vec::new();
+        // Keys and directories (ending in `/`) still to visit, smallest last.
+        // A directory sorts like the keys inside it, so this walks in key order.
+        let mut pending = vec![super::dir_of_prefix(prefix).to_string()];
+
+        while let Some(item) = pending.pop() {
+            let is_dir = item.is_empty() || item.ends_with('/');
+            if !is_dir {
+                keys.push(item);
+                if keys.len() == limit {
+                    break;
+                }
+                continue;
+            }
+
+            let mut entries = match tokio::fs::read_dir(self.root.join(&item)).await {
+                Ok(entries) => entries,
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => continue,
+                Err(e) => return Err(e.into()),
+            };
+
+            let mut children = Vec::new();
+            while let Some(entry) = entries.next_entry().await? {
+                let key = format!("{}{}", item, entry.file_name().to_string_lossy());
+
+                if entry.file_type().await?.is_dir() {
+                    let dir = format!("{}/", key);
+                    if super::lists_dir(&dir, prefix, cursor) {
+                        children.push(dir);
+                    }
+                // Skip half-written files from an interrupted put
+                } else if key.starts_with(prefix)
+                    && !key.contains(".tmp-")
+                    && cursor.is_none_or(|cursor| key.as_str() > cursor)
+                {
+                    children.push(key);
+                }
+            }
+
+            children.sort_by(|a, b| b.cmp(a));
+            pending.extend(children);
+        }
+
+        Ok(keys)
+    }
+}
+
+fn e_tag_of(data: &[u8]) -> String {
+    hex::encode(Sha256::digest(data))
+}
+
+#[async_trait]
+impl StorageBackend for LocalBackend {
+    async fn put(&self, key: &str, body: ObjectSource<'_>, options: &PutOptions) -> StorageResult<()> {
+        let path = self.path_for(key)?;
+        if let Some(parent) = path.parent() {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+
+        let _guard = self.write_lock.lock().await;
+
+        if options.if_none_match || options.if_match.is_some() {
+            let current = match tokio::fs::read(&path).await {
+                Ok(data) => Some(e_tag_of(&data)),
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => None,
+                Err(e) => return Err(e.into()),
+            };
+
+            if options.if_none_match && current.is_some() {
+                return Err(StorageError::precondition_failed());
+            }
+            if options.if_match.is_some() && options.if_match != current {
+                return Err(StorageError::precondition_failed());
+            }
+        }
+
+        // Write to a temporary file first so readers never see a partial object
+        let temp_path = path.with_extension(format!("tmp-{}", Uuid::new_v4().simple()));
+        match body {
+            ObjectSource::Bytes(data) => tokio::fs::write(&temp_path, data).await?,
+            ObjectSource::File(source) => {
+                tokio::fs::copy(source, &temp_path).await?;
+            }
+        }
+        tokio::fs::rename(&temp_path, &path).await?;
+
+        Ok(())
+    }
+
+    async fn get(&self, key: &str) -> StorageResult<Option<StoredObject>> {
+        let path = self.path_for(key)?;
+
+        match tokio::fs::read(&path).await {
+            Ok(data) => Ok(Some(StoredObject {
+                e_tag: Some(e_tag_of(&data)),
+                content_type: None,
+                data,
+            })),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    async fn download(&self, key: &str, path: &Path) -> StorageResult<Option<DownloadedObject>> {
+        match tokio::fs::copy(self.path_for(key)?, path).await {
+            Ok(size) => Ok(Some(DownloadedObject {
+                size,
+                content_type: None,
+            })),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    async fn list(
+        &self,
+        prefix: &str,
+        cursor: Option<String>,
+        max_keys: Option<usize>,
+    ) -> StorageResult<ListPage> {
+        let max_keys = max_keys.unwrap_or(DEFAULT_PAGE_SIZE);
+
+        // The cursor is the last key of the previous page; one key more
+        // than the page tells whether another page follows
+        let keys = self
+            .collect_keys(prefix, cursor.as_deref(), max_keys + 1)
+            .await?;
+
+        let mut objects = Vec::new();
+        for key in keys.iter().take(max_keys) {
+            if let Some(info) = self.head(key).await? {
+                objects.push(info);
+            }
+        }
+
+        let next_cursor = if keys.len() > max_keys {
+            objects.last().map(|o| o.key.clone())
+        } else {
+            None
+        };
+
+        Ok(ListPage { objects, next_cursor })
+    }
+
+    async fn delete(&self, key: &str) -> StorageResult<()> {
+        match tokio::fs::remove_file(self.path_for(key)?).await {
+            Ok(()) => Ok(()),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    async fn head(&self, key: &str) -> StorageResult<Option<ObjectInfo>> {
+        match tokio::fs::metadata(self.path_for(key)?).await {
+            Ok(metadata) => Ok(Some(ObjectInfo {
+                key: key.to_string(),
+                size: metadata.len(),
+                e_tag: None,
+                last_modified: metadata
+                    .modified()
+                    .ok()
+                    .map(|t| chrono::DateTime::<chrono::Utc>::from(t).to_rfc3339()),
+                storage_class: None,
+            })),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    // Served by the app, since the webview can't read files outside its sandbox
+    async fn presign(&self, key: &str, _expires_in: Duration) -> StorageResult<String> {
+        Ok(super::library_url(key))
+    }
+
+    fn public_url(&self, _key: &str) -> Option<String> {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KEYS: [&str; 7] = [
+        "a-b/y.jpg",
+        "a.jpg",
+        "a/x.jpg",
+        "photos/1/original.jpg",
+        "photos/1/thumbnail.jpg",
+        "photos/10/original.jpg",
+        "photos/2/original.jpg",
+    ];
+
+    async fn backend() -> LocalBackend {
+        let root = std::env::temp_dir().join(format!("galleria-test-{}", Uuid::new_v4()));
+        let backend = LocalBackend::new(root.to_str().unwrap()).unwrap();
+
+        for key in KEYS {
+            backend
+                .put(key, ObjectSource::Bytes(b"jpg"), &PutOptions::content_type("image/jpeg"))
+                .await
+                .unwrap();
+        }
+
+        backend
+    }
+
+    async fn list_in_pages(backend: &LocalBackend, prefix: &str, page_size: usize) -> Vec<String> {
+        let mut keys = Vec::new();
+        let mut cursor = None;
+
+        loop {
+            let page = backend.list(prefix, cursor, Some(page_size)).await.unwrap();
+            assert!(page.objects.len() <= page_size);
+            keys.extend(page.objects.into_iter().map(|object| object.key));
+
+            cursor = page.next_cursor;
+            if cursor.is_none() {
+                return keys;
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn pages_list_the_tree_in_key_order() {
+        let backend = backend().await;
+
+        for page_size in [1, 2, 3, 1000] {
+            assert_eq!(list_in_pages(&backend, "", page_size).await, KEYS);
+        }
+    }
+
+    #[tokio::test]
+    async fn listing_stays_within_the_prefix() {
+        let backend = backend().await;
+
+        assert_eq!(
+            list_in_pages(&backend, "photos/1", 2).await,
+            ["photos/1/original.jpg", "photos/1/thumbnail.jpg", "photos/10/original.jpg"]
+        );
+        assert_eq!(
+            list_in_pages(&backend, "photos/1/", 1).await,
+            ["photos/1/original.jpg", "photos/1/thumbnail.jpg"]
+        );
+        assert!(list_in_pages(&backend, "videos/", 2).await.is_empty());
+    }
+}
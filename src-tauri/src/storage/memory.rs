@@ -0,0 +1,152 @@
+use super::{ListPage, ObjectInfo, ObjectSource, PutOptions, StorageBackend, StorageError, StorageResult, StoredObject};
+use async_trait::async_trait;
+use chrono::Utc;
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::Duration;
+
+const DEFAULT_PAGE_SIZE: usize = 1000;
+
+#[derive(Clone)]
+struct MemoryObject {
+    data: Vec<u8>,
+    content_type: String,
+    content_disposition: Option<String>,
+    e_tag: String,
+    last_modified: String,
+}
+
+/// Keeps the library in process memory. Useful for trying the app and for
+/// exercising the library code without any cloud account; everything is
+/// lost when the app exits.
+#[derive(Clone, Default)]
+pub struct MemoryBackend {
+    objects: Arc<Mutex<BTreeMap<String, MemoryObject>>>,
+    next_e_tag: Arc<Mutex<u64>>,
+}
+
+impl MemoryBackend {
+    /// The process-wide in-memory library, so every command sees the same objects.
+    pub fn shared() -> Self {
+        static SHARED: OnceLock<MemoryBackend> = OnceLock::new();
+        SHARED.get_or_init(MemoryBackend::default).clone()
+    }
+
+    fn info(key: &str, object: &MemoryObject) -> ObjectInfo {
+        ObjectInfo {
+            key: key.to_string(),
+            size: object.data.len() as u64,
+            e_tag: Some(object.e_tag.clone()),
+            last_modified: Some(object.last_modified.clone()),
+            storage_class: None,
+        }
+    }
+}
+
+#[async_trait]
+impl StorageBackend for MemoryBackend {
+    async fn put(&self, key: &str, body: ObjectSource<'_>, options: &PutOptions) -> StorageResult<()> {
+        let data = body.read_all().await?;
+
+        let e_tag = {
+            let mut next_e_tag = self.next_e_tag.lock().unwrap();
+            *next_e_tag += 1;
+            format!("\"{}\"", next_e_tag)
+        };
+
+        let mut objects = self.objects.lock().unwrap();
+        let current = objects.get(key).map(|o| o.e_tag.clone());
+
+        if options.if_none_match && current.is_some() {
+            return Err(StorageError::precondition_failed());
+        }
+        if options.if_match.is_some() && options.if_match != current {
+            return Err(StorageError::precondition_failed());
+        }
+
+        objects.insert(
+            key.to_string(),
+            MemoryObject {
+                data,
+                content_type: options.content_type.clone(),
+                content_disposition: options.content_disposition.clone(),
+                e_tag,
+                last_modified: Utc::now().to_rfc3339(),
+            },
+        );
+
+        Ok(())
+    }
+
+    async fn get(&self, key: &str) -> StorageResult<Option<StoredObject>> {
+        let objects = self.objects.lock().unwrap();
+
+        Ok(objects.get(key).map(|object| StoredObject {
+            data: object.data.clone(),
+            e_tag: Some(object.e_tag.clone()),
+            content_type: Some(object.content_type.clone()),
+        }))
+    }
+
+    async fn list(
+        &self,
+        prefix: &str,
+        cursor: Option<String>,
+        max_keys: Option<usize>,
+    ) -> StorageResult<ListPage> {
+        let max_keys = max_keys.unwrap_or(DEFAULT_PAGE_SIZE);
+        let objects = self.objects.lock().unwrap();
+
+        // The cursor is the last key of the previous page
+        let mut matching = objects
+            .iter()
+            .filter(|(key, _)| key.starts_with(prefix))
+            .filter(|(key, _)| cursor.as_ref().is_none_or(|c| *key > c));
+
+        let page: Vec<ObjectInfo> = matching
+            .by_ref()
+            .take(max_keys)
+            .map(|(key, object)| Self::info(key, object))
+            .collect();
+
+        let next_cursor = match matching.next() {
+            Some(_) => page.last().map(|o| o.key.clone()),
+            None => None,
+        };
+
+        Ok(ListPage {
+            objects: page,
+            next_cursor,
+        })
+    }
+
+    async fn delete(&self, key: &str) -> StorageResult<()> {
+        self.objects.lock().unwrap().remove(key);
+        Ok(())
+    }
+
+    async fn head(&self, key: &str) -> StorageResult<Option<ObjectInfo>> {
+        let objects = self.objects.lock().unwrap();
+        Ok(objects.get(key).map(|object| Self::info(key, object)))
+    }
+
+    async fn presign(&self, key: &str, _expires_in: Duration) -> StorageResult<String> {
+        Ok(super::library_url(key))
+    }
+
+    fn public_url(&self, _key: &str) -> Option<String> {
+        None
+    }
+
+    async fn stored_sha256(&self, key: &str) -> StorageResult<Option<String>> {
+        let Some(object) = self.get(key).await? else {
+            return Ok(None);
+        };
+        Ok(Some(ObjectSource::Bytes(&object.data).sha256().await?))
+    }
+
+    async fn content_disposition(&self, key: &str) -> StorageResult<Option<String>> {
+        let objects = self.objects.lock().unwrap();
+        Ok(objects.get(key).and_then(|object| object.content_disposition.clone()))
+    }
+}
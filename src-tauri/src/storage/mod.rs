@@ -0,0 +1,464 @@
+mod azure;
+pub mod encryption;
+mod error;
+mod gcs;
+mod local;
+mod memory;
+mod retry;
+mod s3;
+mod throttle;
+mod webdav;
+
+use crate::models::{StorageClass, StorageConfig};
+use crate::upload_sessions::UploadSessionStore;
+use async_trait::async_trait;
+use futures::stream::{self, StreamExt};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt};
+
+pub use azure::AzureBlobBackend;
+pub use encryption::{EncryptedBackend, LibraryKeys};
+pub use error::{ErrorKind, StorageError};
+pub use gcs::GcsBackend;
+pub use local::LocalBackend;
+pub use memory::MemoryBackend;
+pub use retry::RetryingBackend;
+pub use s3::S3Backend;
+pub use throttle::UploadThrottle;
+pub use webdav::WebDavBackend;
+
+pub type StorageResult<T> = Result<T, StorageError>;
+
+// Single-object deletes running in parallel for backends without batch deletes
+const DELETE_CONCURRENCY: usize = 8;
+
+/// Cache-Control for objects that never change once written, such as the
+/// UUID-keyed renditions
+pub const IMMUTABLE_CACHE_CONTROL: &str = "public, max-age=31536000, immutable";
+/// Cache-Control for objects that are rewritten in place, such as metadata JSON
+pub const SHORT_CACHE_CONTROL: &str = "max-age=60";
+
+/// Scheme of the protocol through which the app serves objects the webview
+/// can't load from the provider itself: encrypted, SSE-C, local and WebDAV
+/// libraries.
+pub const LIBRARY_SCHEME: &str = "library";
+
+/// URL of an object served through the `library` protocol.
+pub fn library_url(key: &str) -> String {
+    let path = key
+        .split('/')
+        .map(|segment| urlencoding::encode(segment).into_owned())
+        .collect::<Vec<_>>()
+        .join("/");
+
+    // Custom schemes are mapped onto http on Windows and Android
+    if cfg!(any(windows, target_os = "android")) {
+        format!("http://{}.localhost/{}", LIBRARY_SCHEME, path)
+    } else {
+        format!("{}://localhost/{}", LIBRARY_SCHEME, path)
+    }
+}
+
+/// Cache-Control an object of the library is written with: renditions and
+/// index shards never change, other JSON is rewritten in place.
+pub fn cache_control_for(key: &str) -> Option<String> {
+    if key.starts_with("photos/") || key.starts_with("index/shards/") {
+        Some(IMMUTABLE_CACHE_CONTROL.to_string())
+    } else if key.ends_with(".json") {
+        Some(SHORT_CACHE_CONTROL.to_string())
+    } else {
+        None
+    }
+}
+
+#[derive(Clone, Copy)]
+pub enum ObjectSource<'a> {
+    Bytes(&'a [u8]),
+    File(&'a Path),
+}
+
+impl ObjectSource<'_> {
+    pub async fn len(&self) -> StorageResult<u64> {
+        match self {
+            ObjectSource::Bytes(data) => Ok(data.len() as u64),
+            ObjectSource::File(path) => Ok(tokio::fs::metadata(path).await?.len()),
+        }
+    }
+
+    pub async fn read_range(&self, offset: u64, length: u64) -> StorageResult<Vec<u8>> {
+        match self {
+            ObjectSource::Bytes(data) => {
+                Ok(data[offset as usize..(offset + length) as usize].to_vec())
+            }
+            ObjectSource::File(path) => {
+                let mut file = tokio::fs::File::open(path).await?;
+                file.seek(std::io::SeekFrom::Start(offset)).await?;
+                let mut buffer = vec![0; length as usize];
+                file.read_exact(&mut buffer).await?;
+                Ok(buffer)
+            }
+        }
+    }
+
+    pub async fn read_all(&self) -> StorageResult<Vec<u8>> {
+        match self {
+            ObjectSource::Bytes(data) => Ok(data.to_vec()),
+            ObjectSource::File(path) => Ok(tokio::fs::read(path).await?),
+        }
+    }
+
+    /// Hex SHA-256 of the contents; files are hashed without loading them whole.
+    pub async fn sha256(&self) -> StorageResult<String> {
+        let mut hasher = Sha256::new();
+
+        match self {
+            ObjectSource::Bytes(data) => hasher.update(data),
+            ObjectSource::File(path) => {
+                let mut file = tokio::fs::File::open(path).await?;
+                let mut buffer = vec![0; 1024 * 1024];
+                loop {
+                    let read = file.read(&mut buffer).await?;
+                    if read == 0 {
+                        break;
+                    }
+                    hasher.update(&buffer[..read]);
+                }
+            }
+        }
+
+        Ok(hex::encode(hasher.finalize()))
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct PutOptions {
+    pub content_type: String,
+    /// Only write if the current object has this ETag
+    pub if_match: Option<String>,
+    /// Only write if no object exists under the key
+    pub if_none_match: bool,
+    /// Ignored by backends without storage classes
+    pub storage_class: Option<StorageClass>,
+    pub cache_control: Option<String>,
+    pub content_disposition: Option<String>,
+    /// User metadata stored with the object, e.g. `x-amz-meta-*` on S3.
+    /// Names must be valid identifiers for Azure. Ignored by WebDAV and
+    /// local storage.
+    pub metadata: BTreeMap<String, String>,
+}
+
+impl PutOptions {
+    pub fn content_type(content_type: &str) -> Self {
+        Self {
+            content_type: content_type.to_string(),
+            ..Default::default()
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct StoredObject {
+    pub data: Vec<u8>,
+    pub e_tag: Option<String>,
+    pub content_type: Option<String>,
+}
+
+/// An object written to a local file by `StorageBackend::download`.
+#[derive(Debug, Clone)]
+pub struct DownloadedObject {
+    pub size: u64,
+    pub content_type: Option<String>,
+}
+
+#[allow(dead_code)]
+#[derive(Debug, Clone)]
+pub struct ObjectInfo {
+    pub key: String,
+    pub size: u64,
+    pub e_tag: Option<String>,
+    pub last_modified: Option<String>,
+    /// `None` on backends without storage classes
+    pub storage_class: Option<StorageClass>,
+}
+
+/// An object that `delete_many` could not delete.
+#[derive(Debug, Clone, Serialize)]
+pub struct DeleteFailure {
+    pub key: String,
+    pub error: StorageError,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct ListPage {
+    pub objects: Vec<ObjectInfo>,
+    pub next_cursor: Option<String>,
+}
+
+/// Where the library's objects live. Keys use the same layout on every
+/// backend (`photos/{id}/…`, `metadata/{id}.json`, `index/…`).
+#[async_trait]
+pub trait StorageBackend: Send + Sync {
+    /// Fails with `ErrorKind::PreconditionFailed` when `if_match` / `if_none_match` did not hold.
+    async fn put(&self, key: &str, body: ObjectSource<'_>, options: &PutOptions) -> StorageResult<()>;
+
+    /// Returns `None` when the object does not exist.
+    async fn get(&self, key: &str) -> StorageResult<Option<StoredObject>>;
+
+    /// Writes the object to `path` as it arrives instead of holding it in
+    /// memory. Returns `None` when the object does not exist.
+    async fn download(&self, key: &str, path: &Path) -> StorageResult<Option<DownloadedObject>> {
+        let Some(object) = self.get(key).await? else {
+            return Ok(None);
+        };
+        tokio::fs::write(path, &object.data).await?;
+
+        Ok(Some(DownloadedObject {
+            size: object.data.len() as u64,
+            content_type: object.content_type,
+        }))
+    }
+
+    /// Lists keys under `prefix` in lexicographic order, one page at a time.
+    async fn list(
+        &self,
+        prefix: &str,
+        cursor: Option<String>,
+        max_keys: Option<usize>,
+    ) -> StorageResult<ListPage>;
+
+    /// Checks that the bucket or container exists and is reachable with the
+    /// configured credentials.
+    async fn check_bucket(&self) -> StorageResult<()> {
+        self.list("", None, Some(1)).await.map(|_| ())
+    }
+
+    async fn delete(&self, key: &str) -> StorageResult<()>;
+
+    /// Deletes many objects, returning the ones that could not be deleted.
+    /// Missing objects count as deleted. Only fails outright when the
+    /// request as a whole did.
+    async fn delete_many(&self, keys: &[String]) -> StorageResult<Vec<DeleteFailure>> {
+        let mut deletes = stream::iter(keys.to_vec())
+            .map(|key| async move {
+                let result = self.delete(&key).await;
+                (key, result)
+            })
+            .buffer_unordered(DELETE_CONCURRENCY);
+
+        let mut failures = Vec::new();
+        while let Some((key, result)) = deletes.next().await {
+            if let Err(error) = result {
+                failures.push(DeleteFailure { key, error });
+            }
+        }
+
+        Ok(failures)
+    }
+
+    async fn head(&self, key: &str) -> StorageResult<Option<ObjectInfo>>;
+
+    /// Returns a time-limited URL the webview can load the object from.
+    async fn presign(&self, key: &str, expires_in: Duration) -> StorageResult<String>;
+
+    /// Permanent URL for publicly readable objects, written into the metadata.
+    fn public_url(&self, key: &str) -> Option<String>;
+
+    /// Hex SHA-256 of the whole object as recorded by the provider at upload,
+    /// `None` where the provider keeps no such checksum.
+    async fn stored_sha256(&self, _key: &str) -> StorageResult<Option<String>> {
+        Ok(None)
+    }
+
+    /// `Content-Disposition` the object was uploaded with, `None` where the
+    /// provider doesn't keep one.
+    async fn content_disposition(&self, _key: &str) -> StorageResult<Option<String>> {
+        Ok(None)
+    }
+
+    /// Moves an existing object to another storage class in place.
+    async fn set_storage_class(&self, key: &str, _class: StorageClass) -> StorageResult<()> {
+        Err(StorageError::other(format!(
+            "Cannot change the storage class of {}: storage classes are only supported on S3",
+            key
+        )))
+    }
+
+    /// Asks for a readable copy of an archived object to be kept for `days`.
+    /// Restores take minutes to hours; until then reads fail with
+    /// `ErrorKind::Archived`.
+    async fn restore(&self, key: &str, _days: u32) -> StorageResult<()> {
+        Err(StorageError::other(format!(
+            "Cannot restore {}: archive storage classes are only supported on S3",
+            key
+        )))
+    }
+}
+
+/// The configured library together with its connected backend. The backend
+/// (and the SDK client inside it) is reused across commands and only
+/// rebuilt when the configuration changes.
+///
+/// Encrypted libraries need to be unlocked before `backend` hands out a
+/// connection; until then it fails with `ErrorKind::Locked`.
+#[derive(Default)]
+pub struct ConnectedStorage {
+    config: Option<StorageConfig>,
+    connection: Option<Arc<dyn StorageBackend>>,
+    /// Whether the library has a keyring, checked once per connection
+    encrypted: Option<bool>,
+    keys: Option<Arc<LibraryKeys>>,
+}
+
+impl ConnectedStorage {
+    /// Switches to `config`. Returns whether it points somewhere else than
+    /// before, in which case the connection and keys are dropped.
+    pub fn configure(&mut self, config: StorageConfig) -> bool {
+        let changed = self.config.as_ref() != Some(&config);
+        if changed {
+            self.connection = None;
+            self.encrypted = None;
+            self.keys = None;
+        }
+        self.config = Some(config);
+
+        changed
+    }
+
+    /// The backend without client-side encryption, for managing the keyring.
+    pub async fn connection(&mut self, sessions: &UploadSessionStore) -> StorageResult<Arc<dyn StorageBackend>> {
+        if let Some(connection) = &self.connection {
+            return Ok(connection.clone());
+        }
+
+        let config = self
+            .config
+            .as_ref()
+            .ok_or_else(|| StorageError::new(ErrorKind::NotConfigured, "Storage is not configured"))?;
+        let connection = connect(config, sessions).await?;
+        self.connection = Some(connection.clone());
+
+        Ok(connection)
+    }
+
+    pub async fn backend(&mut self, sessions: &UploadSessionStore) -> StorageResult<Arc<dyn StorageBackend>> {
+        let connection = self.connection(sessions).await?;
+
+        if let Some(keys) = &self.keys {
+            return Ok(Arc::new(EncryptedBackend::new(connection, keys.clone())));
+        }
+
+        if self.is_encrypted(sessions).await? {
+            return Err(StorageError::new(
+                ErrorKind::Locked,
+                "The library is encrypted, unlock it with your passphrase",
+            ));
+        }
+
+        Ok(connection)
+    }
+
+    pub async fn is_encrypted(&mut self, sessions: &UploadSessionStore) -> StorageResult<bool> {
+        if let Some(encrypted) = self.encrypted {
+            return Ok(encrypted);
+        }
+
+        let connection = self.connection(sessions).await?;
+        let encrypted = encryption::has_keyring(connection.as_ref()).await?;
+        self.encrypted = Some(encrypted);
+
+        Ok(encrypted)
+    }
+
+    pub fn is_unlocked(&self) -> bool {
+        self.keys.is_some()
+    }
+
+    pub fn unlock(&mut self, keys: LibraryKeys) {
+        self.encrypted = Some(true);
+        self.keys = Some(Arc::new(keys));
+    }
+
+    pub fn keys(&self) -> Option<Arc<LibraryKeys>> {
+        self.keys.clone()
+    }
+
+    pub fn config(&self) -> Option<&StorageConfig> {
+        self.config.as_ref()
+    }
+}
+
+pub async fn connect(
+    config: &StorageConfig,
+    sessions: &UploadSessionStore,
+) -> StorageResult<Arc<dyn StorageBackend>> {
+    let backend: Arc<dyn StorageBackend> = match config {
+        StorageConfig::S3(s3_config) => Arc::new(S3Backend::new(s3_config, sessions.clone()).await?),
+        StorageConfig::Gcs(gcs_config) => Arc::new(GcsBackend::new(gcs_config, sessions.clone())?),
+        StorageConfig::Azure(azure_config) => Arc::new(AzureBlobBackend::new(azure_config, sessions.clone())?),
+        StorageConfig::Local { root } => Arc::new(LocalBackend::new(root)?),
+        StorageConfig::WebDav {
+            url,
+            username,
+            password,
+        } => Arc::new(WebDavBackend::new(url, username, password)?),
+        StorageConfig::Memory => Arc::new(MemoryBackend::shared()),
+    };
+
+    Ok(Arc::new(RetryingBackend::new(backend)))
+}
+
+/// Writes an HTTP response body to `path` chunk by chunk, returning its length.
+async fn save_response(response: reqwest::Response, path: &Path) -> StorageResult<u64> {
+    let mut file = tokio::fs::File::create(path).await?;
+    let mut body = response.bytes_stream();
+    let mut size = 0;
+
+    while let Some(chunk) = body.next().await {
+        let chunk = chunk?;
+        file.write_all(&chunk).await?;
+        size += chunk.len() as u64;
+    }
+    file.flush().await?;
+
+    Ok(size)
+}
+
+/// Lists every object under `prefix`, following the cursor across pages.
+pub async fn list_all(storage: &dyn StorageBackend, prefix: &str) -> StorageResult<Vec<ObjectInfo>> {
+    let mut objects = Vec::new();
+    let mut cursor: Option<String> = None;
+
+    loop {
+        let page = storage.list(prefix, cursor, None).await?;
+        objects.extend(page.objects);
+
+        cursor = page.next_cursor;
+        if cursor.is_none() {
+            break;
+        }
+    }
+
+    Ok(objects)
+}
+
+/// The deepest directory (ending in `/`, or empty for the root) that holds
+/// every key starting with `prefix`.
+fn dir_of_prefix(prefix: &str) -> &str {
+    match prefix.rfind('/') {
+        Some(end) => &prefix[..=end],
+        None => "",
+    }
+}
+
+/// Whether the directory `dir` (ending in `/`) can hold keys for a listing
+/// of `prefix` that continues after `cursor`. Backends that walk directories
+/// use it to skip the parts of the tree a page doesn't need.
+fn lists_dir(dir: &str, prefix: &str, cursor: Option<&str>) -> bool {
+    (dir.starts_with(prefix) || prefix.starts_with(dir))
+        && cursor.is_none_or(|cursor| cursor < dir || cursor.starts_with(dir))
+}
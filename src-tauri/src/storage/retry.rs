@@ -0,0 +1,139 @@
+use super::{DeleteFailure, DownloadedObject, ListPage, ObjectInfo, ObjectSource, PutOptions, StorageBackend, StorageResult, StoredObject};
+use crate::models::StorageClass;
+use async_trait::async_trait;
+use std::future::Future;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+
+const MAX_ATTEMPTS: u32 = 5;
+const INITIAL_BACKOFF: Duration = Duration::from_millis(250);
+const MAX_BACKOFF: Duration = Duration::from_secs(8);
+
+/// Retries throttled and network failures of the wrapped backend with
+/// exponential backoff, so one transient 503 does not fail a whole batch.
+pub struct RetryingBackend {
+    inner: Arc<dyn StorageBackend>,
+}
+
+impl RetryingBackend {
+    pub fn new(inner: Arc<dyn StorageBackend>) -> Self {
+        Self { inner }
+    }
+}
+
+async fn with_retry<T, F, Fut>(mut attempt: F) -> StorageResult<T>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = StorageResult<T>>,
+{
+    let mut backoff = INITIAL_BACKOFF;
+
+    for attempt_number in 1.. {
+        match attempt().await {
+            Err(e) if e.is_retryable() && attempt_number < MAX_ATTEMPTS => {
+                tokio::time::sleep(backoff + jitter(backoff)).await;
+                backoff = (backoff * 2).min(MAX_BACKOFF);
+            }
+            result => return result,
+        }
+    }
+
+    unreachable!()
+}
+
+// Up to half the backoff again, so parallel requests do not retry in lockstep
+fn jitter(backoff: Duration) -> Duration {
+    let nanos = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.subsec_nanos())
+        .unwrap_or(0);
+
+    backoff.mul_f64((nanos % 1000) as f64 / 2000.0)
+}
+
+#[async_trait]
+impl StorageBackend for RetryingBackend {
+    async fn put(&self, key: &str, body: ObjectSource<'_>, options: &PutOptions) -> StorageResult<()> {
+        with_retry(|| self.inner.put(key, body, options)).await
+    }
+
+    async fn get(&self, key: &str) -> StorageResult<Option<StoredObject>> {
+        with_retry(|| self.inner.get(key)).await
+    }
+
+    async fn download(&self, key: &str, path: &Path) -> StorageResult<Option<DownloadedObject>> {
+        with_retry(|| self.inner.download(key, path)).await
+    }
+
+    async fn list(
+        &self,
+        prefix: &str,
+        cursor: Option<String>,
+        max_keys: Option<usize>,
+    ) -> StorageResult<ListPage> {
+        with_retry(|| self.inner.list(prefix, cursor.clone(), max_keys)).await
+    }
+
+    async fn check_bucket(&self) -> StorageResult<()> {
+        with_retry(|| self.inner.check_bucket()).await
+    }
+
+    async fn delete(&self, key: &str) -> StorageResult<()> {
+        with_retry(|| self.inner.delete(key)).await
+    }
+
+    // Retries the request as a whole, then the keys that failed transiently
+    async fn delete_many(&self, keys: &[String]) -> StorageResult<Vec<DeleteFailure>> {
+        let mut pending = keys.to_vec();
+        let mut failed = Vec::new();
+        let mut backoff = INITIAL_BACKOFF;
+
+        for attempt_number in 1..=MAX_ATTEMPTS {
+            let failures = with_retry(|| self.inner.delete_many(&pending)).await?;
+
+            let (retryable, permanent): (Vec<_>, Vec<_>) = failures
+                .into_iter()
+                .partition(|failure| failure.error.is_retryable() && attempt_number < MAX_ATTEMPTS);
+            failed.extend(permanent);
+
+            if retryable.is_empty() {
+                break;
+            }
+
+            tokio::time::sleep(backoff + jitter(backoff)).await;
+            backoff = (backoff * 2).min(MAX_BACKOFF);
+            pending = retryable.into_iter().map(|failure| failure.key).collect();
+        }
+
+        Ok(failed)
+    }
+
+    async fn head(&self, key: &str) -> StorageResult<Option<ObjectInfo>> {
+        with_retry(|| self.inner.head(key)).await
+    }
+
+    async fn presign(&self, key: &str, expires_in: Duration) -> StorageResult<String> {
+        with_retry(|| self.inner.presign(key, expires_in)).await
+    }
+
+    fn public_url(&self, key: &str) -> Option<String> {
+        self.inner.public_url(key)
+    }
+
+    async fn stored_sha256(&self, key: &str) -> StorageResult<Option<String>> {
+        with_retry(|| self.inner.stored_sha256(key)).await
+    }
+
+    async fn content_disposition(&self, key: &str) -> StorageResult<Option<String>> {
+        with_retry(|| self.inner.content_disposition(key)).await
+    }
+
+    async fn set_storage_class(&self, key: &str, class: StorageClass) -> StorageResult<()> {
+        with_retry(|| self.inner.set_storage_class(key, class)).await
+    }
+
+    async fn restore(&self, key: &str, days: u32) -> StorageResult<()> {
+        with_retry(|| self.inner.restore(key, days)).await
+    }
+}
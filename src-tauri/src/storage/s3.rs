@@ -0,0 +1,997 @@
+use super::{DeleteFailure, DownloadedObject, ErrorKind, ListPage, ObjectInfo, ObjectSource, PutOptions, StorageBackend, StorageError, StorageResult, StoredObject, UploadThrottle};
+use crate::models::{CredentialSource, S3Config, ServerSideEncryption, StorageClass};
+use crate::upload_sessions::{MultipartSession, UploadSessionStore, UploadedPart};
+use async_trait::async_trait;
+use aws_config::meta::region::RegionProviderChain;
+use aws_config::profile::ProfileFileCredentialsProvider;
+use aws_config::sts::AssumeRoleProvider;
+use aws_config::BehaviorVersion;
+use aws_sdk_s3::config::http::HttpResponse;
+use aws_sdk_s3::config::{Credentials, IdentityCache, Region, SharedCredentialsProvider};
+use aws_sdk_s3::config::retry::RetryConfig;
+use aws_sdk_s3::error::{DisplayErrorContext, ProvideErrorMetadata, SdkError};
+use aws_sdk_s3::operation::get_object::GetObjectOutput;
+use aws_sdk_s3::presigning::PresigningConfig;
+use aws_sdk_s3::types::{self, CompletedMultipartUpload, CompletedPart};
+use aws_sdk_s3::Client;
+use base64::Engine;
+use md5::{Digest, Md5};
+use std::collections::HashMap;
+use std::path::Path;
+use std::time::Duration;
+use tokio::io::AsyncWriteExt;
+
+// Objects at or above this size are sent with S3 multipart upload
+const MULTIPART_THRESHOLD: u64 = 16 * 1024 * 1024;
+const MIN_PART_SIZE: u64 = 8 * 1024 * 1024;
+const MAX_PARTS: u64 = 10_000;
+// CopyObject copies at most 5 GiB; bigger objects are copied part by part
+const MAX_COPY_SIZE: u64 = 5 * 1024 * 1024 * 1024;
+const COPY_PART_SIZE: u64 = 512 * 1024 * 1024;
+// DeleteObjects takes at most this many keys per request
+const MAX_DELETE_BATCH: usize = 1000;
+const CREDENTIALS_REFRESH_BUFFER: Duration = Duration::from_secs(5 * 60);
+
+pub struct S3Backend {
+    client: Client,
+    config: S3Config,
+    sessions: UploadSessionStore,
+    customer_key: Option<CustomerKey>,
+}
+
+/// SSE-C key, sent with every request that reads or writes object data.
+struct CustomerKey {
+    key: String,
+    key_md5: String,
+}
+
+impl CustomerKey {
+    fn new(key: &str) -> StorageResult<Self> {
+        let bytes = base64::engine::general_purpose::STANDARD
+            .decode(key.trim())
+            .map_err(|_| StorageError::other("SSE-C key must be base64 encoded"))?;
+        if bytes.len() != 32 {
+            return Err(StorageError::other("SSE-C key must be 256 bits long"));
+        }
+
+        Ok(Self {
+            key: key.trim().to_string(),
+            key_md5: base64::engine::general_purpose::STANDARD.encode(Md5::digest(&bytes)),
+        })
+    }
+}
+
+impl S3Backend {
+    pub async fn new(config: &S3Config, sessions: UploadSessionStore) -> StorageResult<Self> {
+        let customer_key = match &config.encryption {
+            Some(ServerSideEncryption::Customer { key }) => Some(CustomerKey::new(key)?),
+            _ => None,
+        };
+
+        Ok(Self {
+            client: create_s3_client(config).await?,
+            config: config.clone(),
+            sessions,
+            customer_key,
+        })
+    }
+
+    /// `x-amz-server-side-encryption` and the KMS key id for new objects.
+    fn encryption_params(&self) -> (Option<types::ServerSideEncryption>, Option<String>) {
+        match &self.config.encryption {
+            Some(ServerSideEncryption::S3) => (Some(types::ServerSideEncryption::Aes256), None),
+            Some(ServerSideEncryption::Kms { key_id }) => (
+                Some(types::ServerSideEncryption::AwsKms),
+                key_id.clone().filter(|id| !id.trim().is_empty()),
+            ),
+            _ => (None, None),
+        }
+    }
+
+    /// SSE-C algorithm, key and key MD5, all `None` without a customer key.
+    fn customer_key_params(&self) -> (Option<String>, Option<String>, Option<String>) {
+        match &self.customer_key {
+            Some(customer_key) => (
+                Some("AES256".to_string()),
+                Some(customer_key.key.clone()),
+                Some(customer_key.key_md5.clone()),
+            ),
+            None => (None, None, None),
+        }
+    }
+
+    // GET that leaves reading the body to the caller
+    async fn fetch(&self, key: &str) -> StorageResult<Option<GetObjectOutput>> {
+        let (algorithm, customer_key, customer_key_md5) = self.customer_key_params();
+
+        let response = self
+            .client
+            .get_object()
+            .bucket(&self.config.bucket)
+            .key(key)
+            .set_sse_customer_algorithm(algorithm)
+            .set_sse_customer_key(customer_key)
+            .set_sse_customer_key_md5(customer_key_md5)
+            .send()
+            .await;
+
+        match response {
+            Ok(response) => Ok(Some(response)),
+            Err(e) if e.as_service_error().map(|e| e.is_no_such_key()) == Some(true) => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    async fn put_multipart(
+        &self,
+        key: &str,
+        source: &ObjectSource<'_>,
+        total_size: u64,
+        options: &PutOptions,
+    ) -> StorageResult<()> {
+        let content_sha256 = source.sha256().await?;
+        let session = self
+            .resume_or_start_multipart(key, total_size, &content_sha256, options)
+            .await?;
+        let part_count = total_size.div_ceil(session.part_size);
+
+        // Upload only the parts that the previous attempt did not finish
+        for part_number in 1..=part_count as i32 {
+            if session.parts.iter().any(|p| p.part_number == part_number) {
+                continue;
+            }
+
+            let offset = (part_number as u64 - 1) * session.part_size;
+            let length = session.part_size.min(total_size - offset);
+            let chunk = source.read_range(offset, length).await?;
+            let checksum = match session.checksums {
+                true => Some(checksum_sha256(&ObjectSource::Bytes(&chunk).sha256().await?)?),
+                false => None,
+            };
+            let (algorithm, customer_key, customer_key_md5) = self.customer_key_params();
+
+            let response = self
+                .client
+                .upload_part()
+                .bucket(&self.config.bucket)
+                .key(key)
+                .upload_id(&session.upload_id)
+                .part_number(part_number)
+                .body(UploadThrottle::shared().body(chunk).into_byte_stream())
+                .set_checksum_sha256(checksum.clone())
+                .set_sse_customer_algorithm(algorithm)
+                .set_sse_customer_key(customer_key)
+                .set_sse_customer_key_md5(customer_key_md5)
+                .send()
+                .await?;
+
+            let e_tag = response.e_tag().ok_or("S3 did not return an ETag for the part")?;
+            self.sessions.record_part(
+                key,
+                UploadedPart {
+                    part_number,
+                    e_tag: e_tag.to_string(),
+                    checksum_sha256: checksum,
+                },
+            )?;
+        }
+
+        let mut parts: Vec<CompletedPart> = self
+            .sessions
+            .multipart(key)
+            .map(|s| s.parts)
+            .unwrap_or_default()
+            .into_iter()
+            .map(|p| {
+                CompletedPart::builder()
+                    .part_number(p.part_number)
+                    .e_tag(p.e_tag)
+                    .set_checksum_sha256(p.checksum_sha256)
+                    .build()
+            })
+            .collect();
+        parts.sort_by_key(|p| p.part_number());
+        let (algorithm, customer_key, customer_key_md5) = self.customer_key_params();
+
+        self.client
+            .complete_multipart_upload()
+            .bucket(&self.config.bucket)
+            .key(key)
+            .upload_id(&session.upload_id)
+            .multipart_upload(
+                CompletedMultipartUpload::builder()
+                    .set_parts(Some(parts))
+                    .build(),
+            )
+            .set_sse_customer_algorithm(algorithm)
+            .set_sse_customer_key(customer_key)
+            .set_sse_customer_key_md5(customer_key_md5)
+            .send()
+            .await?;
+
+        self.sessions.finish_multipart(key)?;
+
+        Ok(())
+    }
+
+    /// Rewrites an object over 5 GiB in place with UploadPartCopy, carrying
+    /// its headers and metadata over since a multipart upload can't copy them.
+    async fn copy_in_parts(&self, key: &str, class: StorageClass) -> StorageResult<()> {
+        let (encryption, kms_key_id) = self.encryption_params();
+        let (algorithm, customer_key, customer_key_md5) = self.customer_key_params();
+
+        let head = self
+            .client
+            .head_object()
+            .bucket(&self.config.bucket)
+            .key(key)
+            .set_sse_customer_algorithm(algorithm.clone())
+            .set_sse_customer_key(customer_key.clone())
+            .set_sse_customer_key_md5(customer_key_md5.clone())
+            .send()
+            .await?;
+        let total_size = head.content_length().unwrap_or(0) as u64;
+
+        let upload = self
+            .client
+            .create_multipart_upload()
+            .bucket(&self.config.bucket)
+            .key(key)
+            .set_content_type(head.content_type().map(String::from))
+            .set_cache_control(head.cache_control().map(String::from))
+            .set_content_disposition(head.content_disposition().map(String::from))
+            .set_metadata(head.metadata().cloned())
+            .storage_class(s3_storage_class(class))
+            .checksum_algorithm(types::ChecksumAlgorithm::Sha256)
+            .set_server_side_encryption(encryption)
+            .set_ssekms_key_id(kms_key_id)
+            .set_sse_customer_algorithm(algorithm.clone())
+            .set_sse_customer_key(customer_key.clone())
+            .set_sse_customer_key_md5(customer_key_md5.clone())
+            .send()
+            .await?;
+        let upload_id = upload
+            .upload_id()
+            .ok_or("S3 did not return a multipart upload id")?
+            .to_string();
+
+        let mut parts = Vec::new();
+        let part_size = COPY_PART_SIZE.max(total_size.div_ceil(MAX_PARTS));
+        for (index, offset) in (0..total_size).step_by(part_size as usize).enumerate() {
+            let last = (offset + part_size).min(total_size) - 1;
+            let response = self
+                .client
+                .upload_part_copy()
+                .bucket(&self.config.bucket)
+                .key(key)
+                .upload_id(&upload_id)
+                .part_number(index as i32 + 1)
+                .copy_source(format!("{}/{}", self.config.bucket, encode_key(key)))
+                .copy_source_range(format!("bytes={}-{}", offset, last))
+                .set_copy_source_sse_customer_algorithm(algorithm.clone())
+                .set_copy_source_sse_customer_key(customer_key.clone())
+                .set_copy_source_sse_customer_key_md5(customer_key_md5.clone())
+                .set_sse_customer_algorithm(algorithm.clone())
+                .set_sse_customer_key(customer_key.clone())
+                .set_sse_customer_key_md5(customer_key_md5.clone())
+                .send()
+                .await;
+
+            let result = match response {
+                Ok(response) => response.copy_part_result,
+                Err(e) => {
+                    let _ = self
+                        .client
+                        .abort_multipart_upload()
+                        .bucket(&self.config.bucket)
+                        .key(key)
+                        .upload_id(&upload_id)
+                        .send()
+                        .await;
+                    return Err(e.into());
+                }
+            };
+            let result = result.ok_or("S3 did not return the copied part")?;
+
+            parts.push(
+                CompletedPart::builder()
+                    .part_number(index as i32 + 1)
+                    .set_e_tag(result.e_tag)
+                    .set_checksum_sha256(result.checksum_sha256)
+                    .build(),
+            );
+        }
+
+        self.client
+            .complete_multipart_upload()
+            .bucket(&self.config.bucket)
+            .key(key)
+            .upload_id(&upload_id)
+            .multipart_upload(CompletedMultipartUpload::builder().set_parts(Some(parts)).build())
+            .set_sse_customer_algorithm(algorithm)
+            .set_sse_customer_key(customer_key)
+            .set_sse_customer_key_md5(customer_key_md5)
+            .send()
+            .await?;
+
+        Ok(())
+    }
+
+    async fn resume_or_start_multipart(
+        &self,
+        key: &str,
+        total_size: u64,
+        content_sha256: &str,
+        options: &PutOptions,
+    ) -> StorageResult<MultipartSession> {
+        if let Some(session) = self.sessions.multipart(key) {
+            if session.matches(total_size, content_sha256) {
+                // Make sure S3 still knows the upload (it may have been aborted by a lifecycle rule)
+                let (algorithm, customer_key, customer_key_md5) = self.customer_key_params();
+                let existing = self
+                    .client
+                    .list_parts()
+                    .bucket(&self.config.bucket)
+                    .key(key)
+                    .upload_id(&session.upload_id)
+                    .set_sse_customer_algorithm(algorithm)
+                    .set_sse_customer_key(customer_key)
+                    .set_sse_customer_key_md5(customer_key_md5)
+                    .send()
+                    .await;
+
+                match existing {
+                    Ok(_) => return Ok(session),
+                    Err(e) if e.code() == Some("NoSuchUpload") => {}
+                    Err(e) => return Err(e.into()),
+                }
+            } else {
+                let _ = self
+                    .client
+                    .abort_multipart_upload()
+                    .bucket(&self.config.bucket)
+                    .key(key)
+                    .upload_id(&session.upload_id)
+                    .send()
+                    .await;
+            }
+        }
+
+        let (encryption, kms_key_id) = self.encryption_params();
+        let (algorithm, customer_key, customer_key_md5) = self.customer_key_params();
+
+        let response = self
+            .client
+            .create_multipart_upload()
+            .bucket(&self.config.bucket)
+            .key(key)
+            .content_type(&options.content_type)
+            .set_storage_class(options.storage_class.map(s3_storage_class))
+            .set_cache_control(options.cache_control.clone())
+            .set_content_disposition(options.content_disposition.clone())
+            .set_metadata(user_metadata(options))
+            .checksum_algorithm(types::ChecksumAlgorithm::Sha256)
+            .set_server_side_encryption(encryption)
+            .set_ssekms_key_id(kms_key_id)
+            .set_sse_customer_algorithm(algorithm)
+            .set_sse_customer_key(customer_key)
+            .set_sse_customer_key_md5(customer_key_md5)
+            .send()
+            .await?;
+
+        let upload_id = response
+            .upload_id()
+            .ok_or("S3 did not return a multipart upload id")?;
+
+        let session = MultipartSession {
+            upload_id: upload_id.to_string(),
+            part_size: MIN_PART_SIZE.max(total_size.div_ceil(MAX_PARTS)),
+            total_size,
+            parts: Vec::new(),
+            checksums: true,
+            content_sha256: Some(content_sha256.to_string()),
+        };
+        self.sessions.start_multipart(key, session.clone())?;
+
+        Ok(session)
+    }
+}
+
+#[async_trait]
+impl StorageBackend for S3Backend {
+    async fn put(&self, key: &str, body: ObjectSource<'_>, options: &PutOptions) -> StorageResult<()> {
+        let total_size = body.len().await?;
+
+        if total_size >= MULTIPART_THRESHOLD {
+            return self.put_multipart(key, &body, total_size, options).await;
+        }
+
+        // S3 rejects the upload if what arrives does not match
+        let checksum = checksum_sha256(&body.sha256().await?)?;
+        // Below the multipart threshold, so small enough to hold in memory
+        let stream = UploadThrottle::shared().body(body.read_all().await?).into_byte_stream();
+
+        let (encryption, kms_key_id) = self.encryption_params();
+        let (algorithm, customer_key, customer_key_md5) = self.customer_key_params();
+
+        let mut request = self
+            .client
+            .put_object()
+            .bucket(&self.config.bucket)
+            .key(key)
+            .body(stream)
+            .checksum_sha256(checksum)
+            .content_type(&options.content_type)
+            .set_storage_class(options.storage_class.map(s3_storage_class))
+            .set_cache_control(options.cache_control.clone())
+            .set_content_disposition(options.content_disposition.clone())
+            .set_metadata(user_metadata(options))
+            .set_server_side_encryption(encryption)
+            .set_ssekms_key_id(kms_key_id)
+            .set_sse_customer_algorithm(algorithm)
+            .set_sse_customer_key(customer_key)
+            .set_sse_customer_key_md5(customer_key_md5);
+
+        if let Some(e_tag) = &options.if_match {
+            request = request.if_match(e_tag);
+        }
+        if options.if_none_match {
+            request = request.if_none_match("*");
+        }
+
+        request.send().await?;
+
+        Ok(())
+    }
+
+    async fn get(&self, key: &str) -> StorageResult<Option<StoredObject>> {
+        let Some(response) = self.fetch(key).await? else {
+            return Ok(None);
+        };
+
+        let e_tag = response.e_tag().map(|t| t.to_string());
+        let content_type = response.content_type().map(|t| t.to_string());
+        let body = response
+            .body
+            .collect()
+            .await
+            .map_err(|e| StorageError::new(ErrorKind::Network, e.to_string()))?;
+
+        Ok(Some(StoredObject {
+            data: body.into_bytes().to_vec(),
+            e_tag,
+            content_type,
+        }))
+    }
+
+    async fn download(&self, key: &str, path: &Path) -> StorageResult<Option<DownloadedObject>> {
+        let Some(mut response) = self.fetch(key).await? else {
+            return Ok(None);
+        };
+
+        let content_type = response.content_type().map(|t| t.to_string());
+        let mut file = tokio::fs::File::create(path).await?;
+        let mut size = 0;
+
+        while let Some(chunk) = response.body.next().await {
+            let chunk = chunk.map_err(|e| StorageError::new(ErrorKind::Network, e.to_string()))?;
+            file.write_all(&chunk).await?;
+            size += chunk.len() as u64;
+        }
+        file.flush().await?;
+
+        Ok(Some(DownloadedObject { size, content_type }))
+    }
+
+    async fn list(
+        &self,
+        prefix: &str,
+        cursor: Option<String>,
+        max_keys: Option<usize>,
+    ) -> StorageResult<ListPage> {
+        let response = self
+            .client
+            .list_objects_v2()
+            .bucket(&self.config.bucket)
+            .prefix(prefix)
+            .set_max_keys(max_keys.map(|n| n.min(1000) as i32))
+            .set_continuation_token(cursor)
+            .send()
+            .await?;
+
+        let objects = response
+            .contents()
+            .iter()
+            .filter_map(|object| {
+                Some(ObjectInfo {
+                    key: object.key()?.to_string(),
+                    size: object.size().unwrap_or(0) as u64,
+                    e_tag: object.e_tag().map(|t| t.to_string()),
+                    last_modified: object.last_modified().map(|t| t.to_string()),
+                    storage_class: parse_storage_class(object.storage_class().map(|c| c.as_str())),
+                })
+            })
+            .collect();
+
+        let next_cursor = match response.is_truncated() {
+            Some(true) => response.next_continuation_token().map(|t| t.to_string()),
+            _ => None,
+        };
+
+        Ok(ListPage { objects, next_cursor })
+    }
+
+    async fn check_bucket(&self) -> StorageResult<()> {
+        // HEAD responses carry no error body, so a missing bucket is told
+        // apart from missing permissions by status code alone
+        self.client
+            .head_bucket()
+            .bucket(&self.config.bucket)
+            .send()
+            .await
+            .map_err(|e| StorageError::from(e).context(&format!("Bucket {}", self.config.bucket)))?;
+
+        Ok(())
+    }
+
+    async fn delete(&self, key: &str) -> StorageResult<()> {
+        self.client
+            .delete_object()
+            .bucket(&self.config.bucket)
+            .key(key)
+            .send()
+            .await?;
+
+        Ok(())
+    }
+
+    async fn delete_many(&self, keys: &[String]) -> StorageResult<Vec<DeleteFailure>> {
+        let mut failures = Vec::new();
+
+        for batch in keys.chunks(MAX_DELETE_BATCH) {
+            let objects = batch
+                .iter()
+                .map(|key| types::ObjectIdentifier::builder().key(key).build())
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(StorageError::other)?;
+            let delete = types::Delete::builder()
+                .set_objects(Some(objects))
+                .quiet(true)
+                .build()
+                .map_err(StorageError::other)?;
+
+            let response = self
+                .client
+                .delete_objects()
+                .bucket(&self.config.bucket)
+                .delete(delete)
+                .send()
+                .await?;
+
+            // In quiet mode only the keys that failed are reported
+            for error in response.errors() {
+                let code = error.code().unwrap_or("Unknown");
+                failures.push(DeleteFailure {
+                    key: error.key().unwrap_or_default().to_string(),
+                    error: StorageError::new(
+                        kind_for_code(code).unwrap_or(ErrorKind::Other),
+                        format!("{}: {}", code, error.message().unwrap_or_default()),
+                    ),
+                });
+            }
+        }
+
+        Ok(failures)
+    }
+
+    async fn head(&self, key: &str) -> StorageResult<Option<ObjectInfo>> {
+        let (algorithm, customer_key, customer_key_md5) = self.customer_key_params();
+
+        let response = self
+            .client
+            .head_object()
+            .bucket(&self.config.bucket)
+            .key(key)
+            .set_sse_customer_algorithm(algorithm)
+            .set_sse_customer_key(customer_key)
+            .set_sse_customer_key_md5(customer_key_md5)
+            .send()
+            .await;
+
+        match response {
+            Ok(response) => Ok(Some(ObjectInfo {
+                key: key.to_string(),
+                size: response.content_length().unwrap_or(0) as u64,
+                e_tag: response.e_tag().map(|t| t.to_string()),
+                last_modified: response.last_modified().map(|t| t.to_string()),
+                storage_class: parse_storage_class(response.storage_class().map(|c| c.as_str())),
+            })),
+            Err(e) if e.as_service_error().map(|e| e.is_not_found()) == Some(true) => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    async fn presign(&self, key: &str, expires_in: Duration) -> StorageResult<String> {
+        // The webview cannot send the SSE-C headers a signed URL would need
+        if self.customer_key.is_some() {
+            return Ok(super::library_url(key));
+        }
+
+        let request = self
+            .client
+            .get_object()
+            .bucket(&self.config.bucket)
+            .key(key)
+            .presigned(PresigningConfig::expires_in(expires_in).map_err(StorageError::other)?)
+            .await?;
+
+        Ok(request.uri().to_string())
+    }
+
+    // Private buckets only store the object keys, and SSE-C objects cannot
+    // be read without the key
+    fn public_url(&self, key: &str) -> Option<String> {
+        if self.config.private_bucket || self.customer_key.is_some() {
+            None
+        } else {
+            Some(object_url(&self.config, key))
+        }
+    }
+
+    async fn stored_sha256(&self, key: &str) -> StorageResult<Option<String>> {
+        let (algorithm, customer_key, customer_key_md5) = self.customer_key_params();
+
+        let response = self
+            .client
+            .head_object()
+            .bucket(&self.config.bucket)
+            .key(key)
+            .checksum_mode(types::ChecksumMode::Enabled)
+            .set_sse_customer_algorithm(algorithm)
+            .set_sse_customer_key(customer_key)
+            .set_sse_customer_key_md5(customer_key_md5)
+            .send()
+            .await?;
+
+        // Multipart uploads only have a checksum of their part checksums
+        // (`…-<parts>`), which can't be compared with a whole-file digest
+        let Some(checksum) = response.checksum_sha256().filter(|c| !c.contains('-')) else {
+            return Ok(None);
+        };
+        let digest = base64::engine::general_purpose::STANDARD
+            .decode(checksum)
+            .map_err(StorageError::other)?;
+
+        Ok(Some(hex::encode(digest)))
+    }
+
+    async fn content_disposition(&self, key: &str) -> StorageResult<Option<String>> {
+        let (algorithm, customer_key, customer_key_md5) = self.customer_key_params();
+
+        let response = self
+            .client
+            .head_object()
+            .bucket(&self.config.bucket)
+            .key(key)
+            .set_sse_customer_algorithm(algorithm)
+            .set_sse_customer_key(customer_key)
+            .set_sse_customer_key_md5(customer_key_md5)
+            .send()
+            .await;
+
+        match response {
+            Ok(response) => Ok(response.content_disposition().map(String::from)),
+            Err(e) if e.as_service_error().map(|e| e.is_not_found()) == Some(true) => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    // Copying an object onto itself is how S3 changes the class of stored data
+    async fn set_storage_class(&self, key: &str, class: StorageClass) -> StorageResult<()> {
+        let size = self.head(key).await?.map_or(0, |info| info.size);
+        if size > MAX_COPY_SIZE {
+            return self.copy_in_parts(key, class).await;
+        }
+
+        let (encryption, kms_key_id) = self.encryption_params();
+        let (algorithm, customer_key, customer_key_md5) = self.customer_key_params();
+
+        self.client
+            .copy_object()
+            .bucket(&self.config.bucket)
+            .key(key)
+            .copy_source(format!("{}/{}", self.config.bucket, encode_key(key)))
+            .metadata_directive(types::MetadataDirective::Copy)
+            .storage_class(s3_storage_class(class))
+            .set_server_side_encryption(encryption)
+            .set_ssekms_key_id(kms_key_id)
+            .set_copy_source_sse_customer_algorithm(algorithm.clone())
+            .set_copy_source_sse_customer_key(customer_key.clone())
+            .set_copy_source_sse_customer_key_md5(customer_key_md5.clone())
+            .set_sse_customer_algorithm(algorithm)
+            .set_sse_customer_key(customer_key)
+            .set_sse_customer_key_md5(customer_key_md5)
+            .send()
+            .await?;
+
+        Ok(())
+    }
+
+    async fn restore(&self, key: &str, days: u32) -> StorageResult<()> {
+        let job = types::GlacierJobParameters::builder()
+            .tier(types::Tier::Standard)
+            .build()
+            .map_err(StorageError::other)?;
+
+        let response = self
+            .client
+            .restore_object()
+            .bucket(&self.config.bucket)
+            .key(key)
+            .restore_request(
+                types::RestoreRequest::builder()
+                    .days(days as i32)
+                    .glacier_job_parameters(job)
+                    .build(),
+            )
+            .send()
+            .await;
+
+        match response {
+            Ok(_) => Ok(()),
+            Err(e) if e.code() == Some("RestoreAlreadyInProgress") => Ok(()),
+            Err(e) => Err(e.into()),
+        }
+    }
+}
+
+impl<E> From<SdkError<E, HttpResponse>> for StorageError
+where
+    E: ProvideErrorMetadata + std::error::Error + Send + Sync + 'static,
+{
+    fn from(error: SdkError<E, HttpResponse>) -> Self {
+        let kind = match &error {
+            SdkError::TimeoutError(_) | SdkError::DispatchFailure(_) | SdkError::ResponseError(_) => {
+                ErrorKind::Network
+            }
+            _ => error
+                .code()
+                .and_then(kind_for_code)
+                .or_else(|| {
+                    error
+                        .raw_response()
+                        .map(|r| ErrorKind::from_status(r.status().as_u16()))
+                })
+                .unwrap_or(ErrorKind::Other),
+        };
+
+        let message = match (error.code(), error.message()) {
+            (Some(code), Some(message)) => format!("{}: {}", code, message),
+            _ => DisplayErrorContext(&error).to_string(),
+        };
+
+        StorageError::new(kind, message)
+    }
+}
+
+// S3 error codes, including the ones S3-compatible providers return
+fn kind_for_code(code: &str) -> Option<ErrorKind> {
+    let kind = match code {
+        "AccessDenied" | "AllAccessDisabled" | "InvalidAccessKeyId" | "SignatureDoesNotMatch"
+        | "ExpiredToken" | "InvalidToken" | "TokenRefreshRequired" | "AccountProblem" => ErrorKind::Auth,
+        "NoSuchKey" | "NoSuchBucket" | "NoSuchUpload" | "NotFound" => ErrorKind::NotFound,
+        "SlowDown" | "Throttling" | "ThrottlingException" | "RequestLimitExceeded"
+        | "TooManyRequests" | "ServiceUnavailable" => ErrorKind::Throttled,
+        "InternalError" | "RequestTimeout" => ErrorKind::Network,
+        "QuotaExceeded" | "XMinioStorageFull" | "TooManyBuckets" => ErrorKind::Quota,
+        "PreconditionFailed" | "ConditionalRequestConflict" => ErrorKind::PreconditionFailed,
+        "InvalidObjectState" => ErrorKind::Archived,
+        _ => return None,
+    };
+
+    Some(kind)
+}
+
+// S3 wants the raw digest base64 encoded, `ObjectSource::sha256` gives hex
+fn checksum_sha256(hex_digest: &str) -> StorageResult<String> {
+    let digest = hex::decode(hex_digest).map_err(StorageError::other)?;
+    Ok(base64::engine::general_purpose::STANDARD.encode(digest))
+}
+
+fn user_metadata(options: &PutOptions) -> Option<HashMap<String, String>> {
+    if options.metadata.is_empty() {
+        None
+    } else {
+        Some(options.metadata.clone().into_iter().collect())
+    }
+}
+
+fn s3_storage_class(class: StorageClass) -> types::StorageClass {
+    types::StorageClass::from(class.as_str())
+}
+
+// S3 leaves the class out for Standard objects
+fn parse_storage_class(name: Option<&str>) -> Option<StorageClass> {
+    name.map_or(Some(StorageClass::Standard), StorageClass::parse)
+}
+
+fn encode_key(key: &str) -> String {
+    key.split('/')
+        .map(|segment| urlencoding::encode(segment).into_owned())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+fn object_url(config: &S3Config, key: &str) -> String {
+    if let Some(base) = &config.public_base_url {
+        return format!("{}/{}", base.trim_end_matches('/'), key);
+    }
+
+    match &config.endpoint_url {
+        Some(endpoint) => {
+            let endpoint = endpoint.trim_end_matches('/');
+            if config.force_path_style {
+                return format!("{}/{}/{}", endpoint, config.bucket, key);
+            }
+
+            let (scheme, host) = endpoint.split_once("://").unwrap_or(("https", endpoint));
+            format!("{}://{}.{}/{}", scheme, config.bucket, host, key)
+        }
+        None if config.force_path_style => format!(
+            "https://s3.{}.amazonaws.com/{}/{}",
+            config.region, config.bucket, key
+        ),
+        None => format!(
+            "https://{}.s3.{}.amazonaws.com/{}",
+            config.bucket, config.region, key
+        ),
+    }
+}
+
+/// Credentials from the configured source, through the configured role if
+/// there is one.
+async fn credentials_provider(config: &S3Config) -> SharedCredentialsProvider {
+    let source = match &config.credentials {
+        CredentialSource::Keys => SharedCredentialsProvider::new(Credentials::new(
+            &config.access_key,
+            &config.secret_key,
+            config.session_token.clone().filter(|token| !token.trim().is_empty()),
+            None,
+            "galleria",
+        )),
+        CredentialSource::Profile { name } => {
+            let mut builder = ProfileFileCredentialsProvider::builder();
+            if let Some(name) = name.as_deref().filter(|name| !name.trim().is_empty()) {
+                builder = builder.profile_name(name);
+            }
+            SharedCredentialsProvider::new(builder.build())
+        }
+    };
+
+    let Some(role) = &config.assume_role else {
+        return source;
+    };
+
+    let mut builder = AssumeRoleProvider::builder(&role.role_arn)
+        .region(Region::new(config.region.clone()))
+        .session_name(
+            role.session_name
+                .clone()
+                .filter(|name| !name.trim().is_empty())
+                .unwrap_or_else(|| "galleria".to_string()),
+        );
+    if let Some(external_id) = role.external_id.as_deref().filter(|id| !id.trim().is_empty()) {
+        builder = builder.external_id(external_id);
+    }
+
+    SharedCredentialsProvider::new(builder.build_from_provider(source).await)
+}
+
+async fn create_s3_client(config: &S3Config) -> StorageResult<Client> {
+    let region = Region::new(config.region.clone());
+    let region_provider = RegionProviderChain::default_provider()
+        .or_else(region);
+
+    let sdk_config = aws_config::defaults(BehaviorVersion::latest())
+        .region(region_provider)
+        .credentials_provider(credentials_provider(config).await)
+        // Temporary credentials are fetched again this long before they
+        // expire, so a long upload never signs with expired ones
+        .identity_cache(
+            IdentityCache::lazy()
+                .buffer_time(CREDENTIALS_REFRESH_BUFFER)
+                .build(),
+        )
+        .load()
+        .await;
+
+    // Retries are handled by `RetryingBackend` for every provider
+    let mut s3_config = aws_sdk_s3::config::Builder::from(&sdk_config)
+        .force_path_style(config.force_path_style)
+        .retry_config(RetryConfig::disabled());
+
+    if let Some(endpoint) = &config.endpoint_url {
+        s3_config = s3_config.endpoint_url(endpoint);
+    }
+
+    Ok(Client::from_conf(s3_config.build()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(endpoint_url: Option<&str>, force_path_style: bool) -> S3Config {
+        S3Config {
+            bucket: "photos".to_string(),
+            region: "eu-west-1".to_string(),
+            access_key: String::new(),
+            secret_key: String::new(),
+            session_token: None,
+            credentials: CredentialSource::Keys,
+            assume_role: None,
+            endpoint_url: endpoint_url.map(String::from),
+            force_path_style,
+            public_base_url: None,
+            private_bucket: false,
+            encryption: None,
+            storage_classes: Default::default(),
+        }
+    }
+
+    #[test]
+    fn error_codes_map_to_kinds() {
+        let cases = [
+            ("AccessDenied", ErrorKind::Auth),
+            ("SignatureDoesNotMatch", ErrorKind::Auth),
+            ("ExpiredToken", ErrorKind::Auth),
+            ("NoSuchKey", ErrorKind::NotFound),
+            ("NoSuchBucket", ErrorKind::NotFound),
+            ("SlowDown", ErrorKind::Throttled),
+            ("ServiceUnavailable", ErrorKind::Throttled),
+            ("InternalError", ErrorKind::Network),
+            ("XMinioStorageFull", ErrorKind::Quota),
+            ("PreconditionFailed", ErrorKind::PreconditionFailed),
+            ("InvalidObjectState", ErrorKind::Archived),
+        ];
+
+        for (code, kind) in cases {
+            assert_eq!(kind_for_code(code), Some(kind), "{}", code);
+        }
+        assert_eq!(kind_for_code("MalformedXML"), None);
+    }
+
+    #[test]
+    fn aws_urls_are_virtual_hosted_unless_path_style_is_forced() {
+        assert_eq!(
+            object_url(&config(None, false), "photos/a/small.jpg"),
+            "https://photos.s3.eu-west-1.amazonaws.com/photos/a/small.jpg"
+        );
+        assert_eq!(
+            object_url(&config(None, true), "photos/a/small.jpg"),
+            "https://s3.eu-west-1.amazonaws.com/photos/photos/a/small.jpg"
+        );
+    }
+
+    #[test]
+    fn custom_endpoint_urls_keep_their_scheme() {
+        assert_eq!(
+            object_url(&config(Some("http://localhost:9000/"), true), "photos/a/small.jpg"),
+            "http://localhost:9000/photos/photos/a/small.jpg"
+        );
+        assert_eq!(
+            object_url(&config(Some("https://r2.example.com"), false), "photos/a/small.jpg"),
+            "https://photos.r2.example.com/photos/a/small.jpg"
+        );
+    }
+
+    #[test]
+    fn public_base_url_wins_over_the_endpoint() {
+        let config = S3Config {
+            public_base_url: Some("https://cdn.example.com/".to_string()),
+            ..config(Some("https://r2.example.com"), true)
+        };
+
+        assert_eq!(object_url(&config, "photos/a/small.jpg"), "https://cdn.example.com/photos/a/small.jpg");
+    }
+}
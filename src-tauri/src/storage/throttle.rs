@@ -0,0 +1,178 @@
+use aws_sdk_s3::primitives::{ByteStream, SdkBody};
+use bytes::Bytes;
+use futures::Stream;
+use http_body::{Body, Frame, SizeHint};
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex, OnceLock};
+use std::task::{Context, Poll};
+use std::time::Duration;
+use tokio::time::{Instant, Sleep};
+
+// Request bodies are released in chunks of this size, so the limit holds
+// while a large body is being sent and not only between requests
+const CHUNK_LEN: usize = 64 * 1024;
+
+/// Caps upload bandwidth across every concurrent upload in the process.
+/// Backends send request bodies through `body`, which reserves bandwidth a
+/// chunk at a time; reservations are spaced out so uploads stay under the
+/// limit.
+#[derive(Clone, Default)]
+pub struct UploadThrottle {
+    state: Arc<Mutex<ThrottleState>>,
+}
+
+#[derive(Default)]
+struct ThrottleState {
+    /// `None` uploads at full speed
+    bytes_per_second: Option<u64>,
+    /// When the bandwidth reserved so far has been used up
+    next_free: Option<Instant>,
+}
+
+impl UploadThrottle {
+    /// The process-wide throttle shared by every backend.
+    pub fn shared() -> Self {
+        static SHARED: OnceLock<UploadThrottle> = OnceLock::new();
+        SHARED.get_or_init(UploadThrottle::default).clone()
+    }
+
+    pub fn set_limit(&self, bytes_per_second: Option<u64>) {
+        let mut state = self.state.lock().unwrap();
+        state.bytes_per_second = bytes_per_second.filter(|limit| *limit > 0);
+        state.next_free = None;
+    }
+
+    /// A request body that sends `data` no faster than the limit allows.
+    pub fn body(&self, data: impl Into<Bytes>) -> ThrottledBody {
+        ThrottledBody {
+            data: data.into(),
+            sent: 0,
+            throttle: self.clone(),
+            wait: None,
+        }
+    }
+
+    // Reserves `bytes` and returns when they may be sent, `None` for right away
+    fn reserve(&self, bytes: u64) -> Option<Instant> {
+        let mut state = self.state.lock().unwrap();
+        let bytes_per_second = state.bytes_per_second?;
+
+        let now = Instant::now();
+        let start = state.next_free.map_or(now, |next_free| next_free.max(now));
+        state.next_free = Some(start + Duration::from_secs_f64(bytes as f64 / bytes_per_second as f64));
+        Some(start)
+    }
+}
+
+/// Request body paced by an `UploadThrottle`. Usable as a stream for
+/// reqwest and as an HTTP body for the AWS SDK, with its exact length known
+/// up front for signing and checksums.
+pub struct ThrottledBody {
+    data: Bytes,
+    sent: usize,
+    throttle: UploadThrottle,
+    /// Until the reserved bandwidth for the next chunk is free
+    wait: Option<Pin<Box<Sleep>>>,
+}
+
+impl ThrottledBody {
+    /// Without a `Content-Length` header reqwest sends streams chunked,
+    /// which not every server accepts.
+    pub fn into_reqwest(self) -> reqwest::Body {
+        reqwest::Body::wrap_stream(self)
+    }
+
+    /// The SDK may resend a request; every attempt is throttled again.
+    pub fn into_byte_stream(self) -> ByteStream {
+        let ThrottledBody { data, throttle, .. } = self;
+        ByteStream::new(SdkBody::retryable(move || {
+            SdkBody::from_body_1_x(throttle.body(data.clone()))
+        }))
+    }
+}
+
+impl Stream for ThrottledBody {
+    type Item = Result<Bytes, std::io::Error>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        if this.sent >= this.data.len() {
+            return Poll::Ready(None);
+        }
+        let end = (this.sent + CHUNK_LEN).min(this.data.len());
+
+        if this.wait.is_none() {
+            if let Some(start) = this.throttle.reserve((end - this.sent) as u64) {
+                this.wait = Some(Box::pin(tokio::time::sleep_until(start)));
+            }
+        }
+        if let Some(wait) = &mut this.wait {
+            if wait.as_mut().poll(cx).is_pending() {
+                return Poll::Pending;
+            }
+            this.wait = None;
+        }
+
+        let chunk = this.data.slice(this.sent..end);
+        this.sent = end;
+        Poll::Ready(Some(Ok(chunk)))
+    }
+}
+
+impl Body for ThrottledBody {
+    type Data = Bytes;
+    type Error = std::io::Error;
+
+    fn poll_frame(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Result<Frame<Bytes>, Self::Error>>> {
+        self.poll_next(cx)
+            .map(|chunk| chunk.map(|chunk| chunk.map(Frame::data)))
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.sent >= self.data.len()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        SizeHint::with_exact((self.data.len() - self.sent) as u64)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::StreamExt;
+
+    #[tokio::test]
+    async fn body_is_paced_chunk_by_chunk() {
+        let throttle = UploadThrottle::default();
+        throttle.set_limit(Some(10 * CHUNK_LEN as u64));
+
+        let started = std::time::Instant::now();
+        let chunks: Vec<Bytes> = throttle
+            .body(vec![0u8; 3 * CHUNK_LEN])
+            .map(|chunk| chunk.unwrap())
+            .collect()
+            .await;
+
+        // The first chunk goes right away, the other two a tenth of a second apart
+        assert_eq!(chunks.len(), 3);
+        assert!(started.elapsed() >= Duration::from_millis(200));
+    }
+
+    #[tokio::test]
+    async fn unlimited_body_is_sent_whole() {
+        let throttle = UploadThrottle::default();
+        let data: Vec<u8> = (0..2 * CHUNK_LEN + 10).map(|i| i as u8).collect();
+
+        let body = throttle.body(data.clone());
+        assert_eq!(Body::size_hint(&body).exact(), Some(data.len() as u64));
+
+        let sent: Vec<u8> = body
+            .map(|chunk| chunk.unwrap())
+            .collect::<Vec<_>>()
+            .await
+            .concat();
+        assert_eq!(sent, data);
+    }
+}
@@ -0,0 +1,433 @@
+use super::{save_response, DownloadedObject, ListPage, ObjectInfo, ObjectSource, PutOptions, StorageBackend, StorageError, StorageResult, StoredObject, UploadThrottle};
+use async_trait::async_trait;
+use reqwest::{Method, StatusCode};
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+use std::time::Duration;
+use tokio::sync::Mutex;
+
+const DEFAULT_PAGE_SIZE: usize = 1000;
+// Listings that are never finished leave their walk behind; beyond this many
+// they are all dropped, and their next pages skip ahead from the root instead
+const MAX_PAUSED_WALKS: usize = 16;
+
+const PROPFIND_BODY: &str = r#"<?xml version="1.0" encoding="utf-8"?>
+<d:propfind xmlns:d="DAV:">
+  <d:prop>
+    <d:resourcetype/>
+    <d:getcontentlength/>
+    <d:getetag/>
+    <d:getlastmodified/>
+  </d:prop>
+</d:propfind>"#;
+
+/// Keeps the library on a WebDAV server such as Nextcloud or a NAS. Keys
+/// map to paths below the configured collection URL.
+pub struct WebDavBackend {
+    client: reqwest::Client,
+    base_url: String,
+    // Path part of `base_url`, used to turn PROPFIND hrefs back into keys
+    base_path: String,
+    username: String,
+    password: String,
+    // Collections already known to exist, so MKCOL is sent once per directory
+    collections: Mutex<HashSet<String>>,
+    // Listings paused at the end of a page, by prefix and cursor, so the next
+    // page carries on without listing the collections before it again
+    walks: Mutex<HashMap<(String, String), Vec<Pending>>>,
+}
+
+struct Entry {
+    key: String,
+    is_collection: bool,
+    info: ObjectInfo,
+}
+
+/// Part of the tree a listing has yet to visit.
+enum Pending {
+    Object(ObjectInfo),
+    /// Path ending in `/`, or empty for the root
+    Collection(String),
+}
+
+impl Pending {
+    // A collection sorts like the keys inside it, so popping the smallest
+    // item first walks the tree in key order
+    fn sort_key(&self) -> &str {
+        match self {
+            Pending::Object(info) => &info.key,
+            Pending::Collection(dir) => dir,
+        }
+    }
+}
+
+impl WebDavBackend {
+    pub fn new(url: &str, username: &str, password: &str) -> StorageResult<Self> {
+        let base_url = format!("{}/", url.trim_end_matches('/'));
+        let base_path = reqwest::Url::parse(&base_url)
+            .map_err(StorageError::other)?
+            .path()
+            .to_string();
+
+        Ok(Self {
+            client: reqwest::Client::new(),
+            base_url,
+            base_path,
+            username: username.to_string(),
+            password: password.to_string(),
+            collections: Mutex::new(HashSet::new()),
+            walks: Mutex::new(HashMap::new()),
+        })
+    }
+
+    fn url_for(&self, key: &str) -> String {
+        let encoded: Vec<String> = key
+            .split('/')
+            .map(|segment| urlencoding::encode(segment).into_owned())
+            .collect();
+
+        format!("{}{}", self.base_url, encoded.join("/"))
+    }
+
+    fn request(&self, method: Method, key: &str) -> reqwest::RequestBuilder {
+        self.client
+            .request(method, self.url_for(key))
+            .basic_auth(&self.username, Some(&self.password))
+    }
+
+    /// Creates the parent collections of `key`, since WebDAV does not
+    /// create intermediate directories on PUT.
+    async fn ensure_parents(&self, key: &str) -> StorageResult<()> {
+        let Some((parent, _)) = key.rsplit_once('/') else {
+            return Ok(());
+        };
+
+        let mut collections = self.collections.lock().await;
+        let mut path = String::new();
+
+        for segment in parent.split('/') {
+            if !path.is_empty() {
+                path.push('/');
+            }
+            path.push_str(segment);
+
+            if collections.contains(&path) {
+                continue;
+            }
+
+            let mkcol = Method::from_bytes(b"MKCOL").map_err(StorageError::other)?;
+            let response = self.request(mkcol, &format!("{}/", path)).send().await?;
+
+            // 405 means the collection already exists
+            match response.status() {
+                status if status.is_success() => {}
+                StatusCode::METHOD_NOT_ALLOWED => {}
+                status => return Err(StorageError::from_status(
+                    status.as_u16(),
+                    format!("Failed to create collection {}: {}", path, status),
+                )),
+            }
+
+            collections.insert(path.clone());
+        }
+
+        Ok(())
+    }
+
+    async fn propfind(&self, collection: &str) -> StorageResult<Vec<Entry>> {
+        let propfind = Method::from_bytes(b"PROPFIND").map_err(StorageError::other)?;
+        let response = self
+            .request(propfind, collection)
+            .header("Depth", "1")
+            .header("Content-Type", "application/xml")
+            .body(PROPFIND_BODY)
+            .send()
+            .await?;
+
+        if response.status() == StatusCode::NOT_FOUND {
+            return Ok(Vec::new());
+        }
+        if !response.status().is_success() {
+            return Err(StorageError::from_status(
+                response.status().as_u16(),
+                format!("PROPFIND {} failed: {}", collection, response.status()),
+            ));
+        }
+
+        let body = response.text().await?;
+        self.parse_multistatus(&body)
+    }
+
+    fn parse_multistatus(&self, body: &str) -> StorageResult<Vec<Entry>> {
+        let document = roxmltree::Document::parse(body).map_err(StorageError::other)?;
+        let mut entries = Vec::new();
+
+        for response in document
+            .descendants()
+            .filter(|n| n.has_tag_name(("DAV:", "response")))
+        {
+            let text_of = |name: &str| {
+                response
+                    .descendants()
+                    .find(|n| n.has_tag_name(("DAV:", name)))
+                    .and_then(|n| n.text())
+                    .map(|t| t.trim().to_string())
+            };
+
+            let Some(href) = text_of("href") else {
+                continue;
+            };
+
+            // Servers return either absolute paths or full URLs
+            let path = match reqwest::Url::parse(&href) {
+                Ok(url) => url.path().to_string(),
+                Err(_) => href,
+            };
+            let path = urlencoding::decode(&path).map_err(StorageError::other)?.into_owned();
+            let base_path = urlencoding::decode(&self.base_path)
+                .map_err(StorageError::other)?
+                .into_owned();
+
+            let Some(key) = path.strip_prefix(&base_path) else {
+                continue;
+            };
+
+            let is_collection = response
+                .descendants()
+                .any(|n| n.has_tag_name(("DAV:", "collection")));
+
+            let key = key.trim_end_matches('/').to_string();
+            entries.push(Entry {
+                info: ObjectInfo {
+                    key: key.clone(),
+                    size: text_of("getcontentlength")
+                        .and_then(|s| s.parse().ok())
+                        .unwrap_or(0),
+                    e_tag: text_of("getetag"),
+                    last_modified: text_of("getlastmodified"),
+                    storage_class: None,
+                },
+                key,
+                is_collection,
+            });
+        }
+
+        Ok(entries)
+    }
+
+    /// The objects and collections in `collection` that a listing of
+    /// `prefix` after `cursor` has to visit, largest key first.
+    async fn children(
+        &self,
+        collection: &str,
+        prefix: &str,
+        cursor: Option<&str>,
+    ) -> StorageResult<Vec<Pending>> {
+        let current = collection.trim_end_matches('/');
+
+        let mut children: Vec<Pending> = self
+            .propfind(collection)
+            .await?
+            .into_iter()
+            // The collection itself is part of its own listing
+            .filter(|entry| entry.key != current)
+            .filter_map(|entry| {
+                if entry.is_collection {
+                    let dir = format!("{}/", entry.key);
+                    super::lists_dir(&dir, prefix, cursor).then_some(Pending::Collection(dir))
+                } else {
+                    let listed = entry.key.starts_with(prefix)
+                        && cursor.is_none_or(|cursor| entry.key.as_str() > cursor);
+                    listed.then_some(Pending::Object(entry.info))
+                }
+            })
+            .collect();
+
+        children.sort_by(|a, b| b.sort_key().cmp(a.sort_key()));
+        Ok(children)
+    }
+
+    // GET that leaves reading the body to the caller
+    async fn fetch(&self, key: &str) -> StorageResult<Option<reqwest::Response>> {
+        let response = self.request(Method::GET, key).send().await?;
+
+        match response.status() {
+            StatusCode::NOT_FOUND => Ok(None),
+            status if !status.is_success() => Err(StorageError::from_status(
+                status.as_u16(),
+                format!("Failed to download {}: {}", key, status),
+            )),
+            _ => Ok(Some(response)),
+        }
+    }
+}
+
+fn header_of(response: &reqwest::Response, name: reqwest::header::HeaderName) -> Option<String> {
+    response
+        .headers()
+        .get(name)
+        .and_then(|v| v.to_str().ok())
+        .map(|v| v.to_string())
+}
+
+#[async_trait]
+impl StorageBackend for WebDavBackend {
+    async fn put(&self, key: &str, body: ObjectSource<'_>, options: &PutOptions) -> StorageResult<()> {
+        self.ensure_parents(key).await?;
+
+        let data = body.read_all().await?;
+        let mut request = self
+            .request(Method::PUT, key)
+            .header("Content-Type", &options.content_type)
+            .header(reqwest::header::CONTENT_LENGTH, data.len())
+            .body(UploadThrottle::shared().body(data).into_reqwest());
+
+        if let Some(e_tag) = &options.if_match {
+            request = request.header("If-Match", e_tag);
+        }
+        if options.if_none_match {
+            request = request.header("If-None-Match", "*");
+        }
+
+        let response = request.send().await?;
+        match response.status() {
+            status if status.is_success() => Ok(()),
+            StatusCode::PRECONDITION_FAILED => Err(StorageError::precondition_failed()),
+            status => Err(StorageError::from_status(
+                status.as_u16(),
+                format!("Failed to upload {}: {}", key, status),
+            )),
+        }
+    }
+
+    async fn get(&self, key: &str) -> StorageResult<Option<StoredObject>> {
+        let Some(response) = self.fetch(key).await? else {
+            return Ok(None);
+        };
+
+        let e_tag = header_of(&response, reqwest::header::ETAG);
+        let content_type = header_of(&response, reqwest::header::CONTENT_TYPE);
+
+        Ok(Some(StoredObject {
+            data: response.bytes().await?.to_vec(),
+            e_tag,
+            content_type,
+        }))
+    }
+
+    async fn download(&self, key: &str, path: &Path) -> StorageResult<Option<DownloadedObject>> {
+        let Some(response) = self.fetch(key).await? else {
+            return Ok(None);
+        };
+
+        let content_type = header_of(&response, reqwest::header::CONTENT_TYPE);
+        let size = save_response(response, path).await?;
+
+        Ok(Some(DownloadedObject { size, content_type }))
+    }
+
+    async fn list(
+        &self,
+        prefix: &str,
+        cursor: Option<String>,
+        max_keys: Option<usize>,
+    ) -> StorageResult<ListPage> {
+        let max_keys = max_keys.unwrap_or(DEFAULT_PAGE_SIZE);
+
+        // The cursor is the last key of the previous page. Without the walk
+        // that produced it, collections before it are skipped instead.
+        let paused = match &cursor {
+            Some(cursor) => self.walks.lock().await.remove(&(prefix.to_string(), cursor.clone())),
+            None => None,
+        };
+        let mut pending = paused
+            .unwrap_or_else(|| vec![Pending::Collection(super::dir_of_prefix(prefix).to_string())]);
+
+        let mut objects = Vec::new();
+        let mut more = false;
+
+        while let Some(item) = pending.pop() {
+            match item {
+                Pending::Object(info) if objects.len() == max_keys => {
+                    pending.push(Pending::Object(info));
+                    more = true;
+                    break;
+                }
+                Pending::Object(info) => objects.push(info),
+                Pending::Collection(collection) => {
+                    pending.extend(self.children(&collection, prefix, cursor.as_deref()).await?);
+                }
+            }
+        }
+
+        let next_cursor = match more {
+            true => objects.last().map(|o| o.key.clone()),
+            false => None,
+        };
+
+        if let Some(next_cursor) = &next_cursor {
+            let mut walks = self.walks.lock().await;
+            if walks.len() >= MAX_PAUSED_WALKS {
+                walks.clear();
+            }
+            walks.insert((prefix.to_string(), next_cursor.clone()), pending);
+        }
+
+        Ok(ListPage { objects, next_cursor })
+    }
+
+    async fn delete(&self, key: &str) -> StorageResult<()> {
+        let response = self.request(Method::DELETE, key).send().await?;
+
+        match response.status() {
+            status if status.is_success() => Ok(()),
+            StatusCode::NOT_FOUND => Ok(()),
+            status => Err(StorageError::from_status(
+                status.as_u16(),
+                format!("Failed to delete {}: {}", key, status),
+            )),
+        }
+    }
+
+    async fn head(&self, key: &str) -> StorageResult<Option<ObjectInfo>> {
+        let response = self.request(Method::HEAD, key).send().await?;
+
+        match response.status() {
+            StatusCode::NOT_FOUND => return Ok(None),
+            status if !status.is_success() => {
+                return Err(StorageError::from_status(
+                    status.as_u16(),
+                    format!("Failed to read {}: {}", key, status),
+                ))
+            }
+            _ => {}
+        }
+
+        let header = |name| {
+            response
+                .headers()
+                .get(name)
+                .and_then(|v| v.to_str().ok())
+                .map(|v| v.to_string())
+        };
+
+        Ok(Some(ObjectInfo {
+            key: key.to_string(),
+            size: header(reqwest::header::CONTENT_LENGTH)
+                .and_then(|s| s.parse().ok())
+                .unwrap_or(0),
+            e_tag: header(reqwest::header::ETAG),
+            last_modified: header(reqwest::header::LAST_MODIFIED),
+            storage_class: None,
+        }))
+    }
+
+    // WebDAV has no signed URLs, and the webview cannot send the credentials
+    async fn presign(&self, key: &str, _expires_in: Duration) -> StorageResult<String> {
+        Ok(super::library_url(key))
+    }
+
+    fn public_url(&self, _key: &str) -> Option<String> {
+        None
+    }
+}
@@ -0,0 +1,104 @@
+use crate::models::{StorageClass, StorageConfig};
+use crate::storage::{self, ObjectInfo, StorageBackend, StorageError, StorageResult};
+use futures::stream::{self, StreamExt};
+use serde::Serialize;
+
+/// How long a restored copy of an archived rendition stays readable
+pub const DEFAULT_RESTORE_DAYS: u32 = 7;
+
+// Storage class changes running in parallel
+const RETIER_CONCURRENCY: usize = 8;
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct RetierSummary {
+    pub checked: usize,
+    pub moved: usize,
+    /// Archived renditions that have to be restored before they can move
+    pub needs_restore: usize,
+}
+
+// `photos/{id}/original.jpg` -> `original`, `photos/{id}/secondary.mov` -> `secondary`
+pub fn size_name_of(key: &str) -> &str {
+    let file_name = key.rsplit('/').next().unwrap_or(key);
+    file_name.split('.').next().unwrap_or(file_name)
+}
+
+/// Moves every rendition in the library to the storage class configured for
+/// its size. Listing reports the current class, so photos already in the
+/// right class cost nothing beyond the listing.
+pub async fn retier_library(
+    storage: &dyn StorageBackend,
+    config: &StorageConfig,
+    on_progress: &(dyn Fn(usize) + Send + Sync),
+) -> StorageResult<RetierSummary> {
+    if config.storage_class_for("original").is_none() {
+        return Err(StorageError::other("Storage classes are only supported on S3"));
+    }
+
+    let mut summary = RetierSummary::default();
+    let objects = storage::list_all(storage, "photos/").await?;
+
+    let mut changes = stream::iter(objects)
+        .map(|object| async move { retier_object(storage, config, &object).await })
+        .buffer_unordered(RETIER_CONCURRENCY);
+
+    while let Some(outcome) = changes.next().await {
+        match outcome? {
+            Retiered::Moved => summary.moved += 1,
+            Retiered::NeedsRestore => summary.needs_restore += 1,
+            Retiered::Unchanged => {}
+        }
+        summary.checked += 1;
+        on_progress(summary.checked);
+    }
+
+    Ok(summary)
+}
+
+enum Retiered {
+    Moved,
+    NeedsRestore,
+    Unchanged,
+}
+
+async fn retier_object(
+    storage: &dyn StorageBackend,
+    config: &StorageConfig,
+    object: &ObjectInfo,
+) -> StorageResult<Retiered> {
+    let Some(target) = config.storage_class_for(size_name_of(&object.key)) else {
+        return Ok(Retiered::Unchanged);
+    };
+    let current = object.storage_class.unwrap_or(StorageClass::Standard);
+
+    if current == target {
+        return Ok(Retiered::Unchanged);
+    }
+    // S3 cannot copy an archived object until a restored copy is available
+    if current.is_archived() {
+        return Ok(Retiered::NeedsRestore);
+    }
+
+    storage.set_storage_class(&object.key, target).await?;
+    Ok(Retiered::Moved)
+}
+
+/// Requests a restore of every archived rendition of a photo and returns
+/// how many were requested.
+pub async fn restore_photo(
+    storage: &dyn StorageBackend,
+    photo_id: &str,
+    days: u32,
+) -> StorageResult<usize> {
+    let objects = storage::list_all(storage, &format!("photos/{}/", photo_id)).await?;
+
+    let mut requested = 0;
+    for object in objects {
+        if object.storage_class.is_some_and(StorageClass::is_archived) {
+            storage.restore(&object.key, days).await?;
+            requested += 1;
+        }
+    }
+
+    Ok(requested)
+}
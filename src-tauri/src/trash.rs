@@ -0,0 +1,365 @@
+use crate::library_index;
+use crate::models::PhotoMetadata;
+use crate::s3_uploader::{self, DeleteReport};
+use crate::storage::{
+    self, DeleteFailure, ErrorKind, ObjectSource, PutOptions, StorageBackend, StorageError, StorageResult,
+    SHORT_CACHE_CONTROL,
+};
+use chrono::{DateTime, Duration, Utc};
+use futures::stream::{self, StreamExt};
+use serde::{Deserialize, Serialize};
+
+/// Trashed photos are purged after this many days unless configured otherwise
+pub const DEFAULT_RETENTION_DAYS: u32 = 30;
+
+const TRASH_PREFIX: &str = "trash/";
+// Kept in the library so every device purges on the same schedule
+const SETTINGS_KEY: &str = "settings/trash.json";
+
+// Trash entries fetched in parallel while listing
+const TRASH_FETCH_CONCURRENCY: usize = 16;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TrashSettings {
+    pub retention_days: u32,
+}
+
+impl Default for TrashSettings {
+    fn default() -> Self {
+        Self {
+            retention_days: DEFAULT_RETENTION_DAYS,
+        }
+    }
+}
+
+/// A photo in the trash. Its renditions stay where they are; only the
+/// metadata moves from `metadata/{id}.json` to `trash/{id}.json`, so
+/// trashing and restoring never copy image data.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TrashedPhoto {
+    pub trashed_at: String,
+    pub photo: PhotoMetadata,
+}
+
+impl TrashedPhoto {
+    fn is_expired(&self, retention_days: u32, now: DateTime<Utc>) -> bool {
+        DateTime::parse_from_rfc3339(&self.trashed_at)
+            .map(|trashed_at| trashed_at.with_timezone(&Utc) + Duration::days(retention_days as i64) <= now)
+            .unwrap_or(false)
+    }
+}
+
+fn trash_key(photo_id: &str) -> String {
+    format!("{}{}.json", TRASH_PREFIX, photo_id)
+}
+
+fn metadata_key(photo_id: &str) -> String {
+    format!("metadata/{}.json", photo_id)
+}
+
+fn json_options() -> PutOptions {
+    PutOptions {
+        cache_control: Some(SHORT_CACHE_CONTROL.to_string()),
+        ..PutOptions::content_type("application/json")
+    }
+}
+
+pub async fn load_settings(storage: &dyn StorageBackend) -> StorageResult<TrashSettings> {
+    match storage.get(SETTINGS_KEY).await? {
+        Some(object) => Ok(serde_json::from_slice(&object.data)?),
+        None => Ok(TrashSettings::default()),
+    }
+}
+
+pub async fn save_settings(storage: &dyn StorageBackend, settings: &TrashSettings) -> StorageResult<()> {
+    storage
+        .put(
+            SETTINGS_KEY,
+            ObjectSource::Bytes(&serde_json::to_vec(settings)?),
+            &json_options(),
+        )
+        .await
+}
+
+async fn write_entry(storage: &dyn StorageBackend, photo_id: &str) -> StorageResult<()> {
+    let photo = s3_uploader::fetch_metadata(storage, &metadata_key(photo_id)).await?;
+    let entry = TrashedPhoto {
+        trashed_at: Utc::now().to_rfc3339(),
+        photo,
+    };
+
+    storage
+        .put(
+            &trash_key(photo_id),
+            ObjectSource::Bytes(&serde_json::to_vec(&entry)?),
+            &json_options(),
+        )
+        .await
+}
+
+/// Moves a photo to the trash, hiding it from the library.
+pub async fn trash_photo(storage: &dyn StorageBackend, photo_id: &str) -> StorageResult<()> {
+    // Write the trash entry first, so an interruption leaves the photo in
+    // both places rather than in neither
+    write_entry(storage, photo_id).await?;
+    storage.delete(&metadata_key(photo_id)).await?;
+
+    // The next listing drops the photo from the index if this fails
+    if let Err(e) = library_index::remove(storage, photo_id).await {
+        log::warn!("Failed to remove trashed photo {} from the library index: {}", photo_id, e);
+    }
+
+    Ok(())
+}
+
+/// Moves many photos to the trash, reporting the ones that could not be moved.
+pub async fn trash_photos(storage: &dyn StorageBackend, photo_ids: &[String]) -> StorageResult<DeleteReport> {
+    let mut writes = stream::iter(photo_ids.to_vec())
+        .map(|photo_id| async move {
+            let result = write_entry(storage, &photo_id).await;
+            (photo_id, result)
+        })
+        .buffer_unordered(TRASH_FETCH_CONCURRENCY);
+
+    let mut written = Vec::new();
+    let mut failed = Vec::new();
+    while let Some((photo_id, result)) = writes.next().await {
+        match result {
+            Ok(()) => written.push(photo_id),
+            Err(error) => failed.push(DeleteFailure {
+                key: metadata_key(&photo_id),
+                error,
+            }),
+        }
+    }
+
+    let metadata_keys: Vec<String> = written.iter().map(|photo_id| metadata_key(photo_id)).collect();
+    let metadata_failures = storage.delete_many(&metadata_keys).await?;
+    let deleted: Vec<String> = written
+        .into_iter()
+        .filter(|photo_id| {
+            let key = metadata_key(photo_id);
+            !metadata_failures.iter().any(|failure| failure.key == key)
+        })
+        .collect();
+    failed.extend(metadata_failures);
+
+    if !deleted.is_empty() {
+        if let Err(e) = library_index::remove_many(storage, &deleted).await {
+            log::warn!("Failed to remove trashed photos from the library index: {}", e);
+        }
+    }
+
+    Ok(DeleteReport {
+        deleted,
+        failed,
+        skipped: Vec::new(),
+    })
+}
+
+/// Puts a trashed photo back into the library.
+pub async fn restore(storage: &dyn StorageBackend, photo_id: &str) -> StorageResult<PhotoMetadata> {
+    let entry = fetch_entry(storage, &trash_key(photo_id)).await?;
+
+    storage
+        .put(
+            &metadata_key(photo_id),
+            ObjectSource::Bytes(&serde_json::to_vec(&entry.photo)?),
+            &json_options(),
+        )
+        .await?;
+    storage.delete(&trash_key(photo_id)).await?;
+
+    if let Err(e) = library_index::upsert(storage, &entry.photo).await {
+        log::warn!("Failed to add restored photo {} to the library index: {}", photo_id, e);
+    }
+
+    Ok(entry.photo)
+}
+
+async fn fetch_entry(storage: &dyn StorageBackend, key: &str) -> StorageResult<TrashedPhoto> {
+    let object = storage.get(key).await?.ok_or_else(|| {
+        StorageError::new(ErrorKind::NotFound, format!("{} is not in the trash", key))
+    })?;
+
+    Ok(serde_json::from_slice(&object.data)?)
+}
+
+/// Lists the trash, most recently trashed first.
+pub async fn list_trash(storage: &dyn StorageBackend) -> StorageResult<Vec<TrashedPhoto>> {
+    let objects = storage::list_all(storage, TRASH_PREFIX).await?;
+
+    let mut fetches = stream::iter(objects)
+        .map(|object| async move { fetch_entry(storage, &object.key).await })
+        .buffered(TRASH_FETCH_CONCURRENCY);
+
+    let mut entries = Vec::new();
+    while let Some(entry) = fetches.next().await {
+        entries.push(entry?);
+    }
+
+    entries.sort_by(|a, b| b.trashed_at.cmp(&a.trashed_at));
+    Ok(entries)
+}
+
+/// Permanently deletes the given trashed photos, or the whole trash. Photos
+/// that are not in the trash (anymore) are reported as skipped.
+pub async fn empty_trash(
+    storage: &dyn StorageBackend,
+    photo_ids: Option<Vec<String>>,
+) -> StorageResult<DeleteReport> {
+    let photo_ids = match photo_ids {
+        Some(photo_ids) => photo_ids,
+        None => list_trash(storage)
+            .await?
+            .into_iter()
+            .map(|entry| entry.photo.id)
+            .collect(),
+    };
+
+    // Ids may be stale: the UI's list is old, or another device restored
+    // the photo since. Only photos that are still in the trash, and not
+    // back in the library, are deleted.
+    let mut checks = stream::iter(photo_ids)
+        .map(|photo_id| async move {
+            let trashed = is_trashed(storage, &photo_id).await;
+            (photo_id, trashed)
+        })
+        .buffered(TRASH_FETCH_CONCURRENCY);
+
+    let mut trashed = Vec::new();
+    let mut skipped = Vec::new();
+    while let Some((photo_id, result)) = checks.next().await {
+        match result? {
+            true => trashed.push(photo_id),
+            false => skipped.push(photo_id),
+        }
+    }
+
+    let mut report = s3_uploader::delete_photos(storage, &trashed).await?;
+    report.skipped = skipped;
+
+    // Entries of photos that could not be fully deleted stay in the trash,
+    // so the purge can be retried from there
+    let trash_keys: Vec<String> = report.deleted.iter().map(|photo_id| trash_key(photo_id)).collect();
+    let trash_failures = storage.delete_many(&trash_keys).await?;
+    report.failed.extend(trash_failures);
+
+    Ok(report)
+}
+
+// Whether the photo has a trash entry and no metadata in the library
+async fn is_trashed(storage: &dyn StorageBackend, photo_id: &str) -> StorageResult<bool> {
+    Ok(storage.head(&trash_key(photo_id)).await?.is_some()
+        && storage.head(&metadata_key(photo_id)).await?.is_none())
+}
+
+/// Deletes photos that have been in the trash longer than the retention period.
+pub async fn purge_expired(storage: &dyn StorageBackend) -> StorageResult<DeleteReport> {
+    let settings = load_settings(storage).await?;
+    let now = Utc::now();
+
+    let expired: Vec<String> = list_trash(storage)
+        .await?
+        .into_iter()
+        .filter(|entry| entry.is_expired(settings.retention_days, now))
+        .map(|entry| entry.photo.id)
+        .collect();
+
+    if expired.is_empty() {
+        return Ok(DeleteReport::default());
+    }
+
+    empty_trash(storage, Some(expired)).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::fixtures;
+    use crate::storage::MemoryBackend;
+
+    async fn add_photo(storage: &MemoryBackend, photo_id: &str) {
+        let options = PutOptions::content_type("image/jpeg");
+
+        for size_name in ["thumbnail", "original"] {
+            let key = format!("photos/{}/{}.jpg", photo_id, size_name);
+            storage.put(&key, ObjectSource::Bytes(b"jpg"), &options).await.unwrap();
+        }
+        fixtures::write_metadata(storage, &fixtures::photo(photo_id)).await;
+    }
+
+    async fn keys(storage: &MemoryBackend) -> Vec<String> {
+        storage
+            .list("", None, None)
+            .await
+            .unwrap()
+            .objects
+            .into_iter()
+            .map(|object| object.key)
+            .filter(|key| !key.starts_with("index/"))
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn trash_moves_only_the_metadata() {
+        let storage = MemoryBackend::default();
+        add_photo(&storage, "a").await;
+
+        trash_photo(&storage, "a").await.unwrap();
+
+        assert_eq!(
+            keys(&storage).await,
+            ["photos/a/original.jpg", "photos/a/thumbnail.jpg", "trash/a.json"]
+        );
+        assert_eq!(list_trash(&storage).await.unwrap()[0].photo.id, "a");
+    }
+
+    #[tokio::test]
+    async fn restore_puts_the_photo_back() {
+        let storage = MemoryBackend::default();
+        add_photo(&storage, "a").await;
+        trash_photo(&storage, "a").await.unwrap();
+
+        let photo = restore(&storage, "a").await.unwrap();
+
+        assert_eq!(photo.id, "a");
+        assert!(list_trash(&storage).await.unwrap().is_empty());
+        assert!(storage.head(&metadata_key("a")).await.unwrap().is_some());
+    }
+
+    #[tokio::test]
+    async fn empty_trash_deletes_everything_of_trashed_photos() {
+        let storage = MemoryBackend::default();
+        add_photo(&storage, "a").await;
+        add_photo(&storage, "b").await;
+        trash_photo(&storage, "a").await.unwrap();
+
+        let report = empty_trash(&storage, None).await.unwrap();
+
+        assert_eq!(report.deleted, ["a"]);
+        assert!(report.failed.is_empty());
+        assert_eq!(
+            keys(&storage).await,
+            ["metadata/b.json", "photos/b/original.jpg", "photos/b/thumbnail.jpg"]
+        );
+    }
+
+    #[tokio::test]
+    async fn empty_trash_skips_photos_that_are_not_trashed() {
+        let storage = MemoryBackend::default();
+        add_photo(&storage, "live").await;
+        add_photo(&storage, "restored").await;
+        trash_photo(&storage, "restored").await.unwrap();
+        // Restored on another device after this one listed the trash
+        restore(&storage, "restored").await.unwrap();
+
+        let report = empty_trash(&storage, Some(vec!["live".into(), "restored".into()]))
+            .await
+            .unwrap();
+
+        assert!(report.deleted.is_empty());
+        assert_eq!(report.skipped, ["live", "restored"]);
+        assert_eq!(keys(&storage).await.len(), 6);
+    }
+}
@@ -0,0 +1,189 @@
+use crate::storage::UploadThrottle;
+use chrono::{Local, NaiveTime, Timelike};
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tauri::Manager;
+
+// Waiting uploads look at the windows again this often, so edits apply
+// without restarting the import
+const WINDOW_POLL_INTERVAL: Duration = Duration::from_secs(60);
+
+/// A daily period in local time during which uploads may run. A window
+/// whose end is before its start runs past midnight, e.g. 22:00 to 06:00.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct UploadWindow {
+    pub start: NaiveTime,
+    pub end: NaiveTime,
+}
+
+impl UploadWindow {
+    fn contains(&self, time: NaiveTime) -> bool {
+        if self.start <= self.end {
+            self.start <= time && time < self.end
+        } else {
+            time >= self.start || time < self.end
+        }
+    }
+}
+
+/// Bandwidth and schedule for uploads from this device. Kept on the device
+/// rather than in the library, since they depend on the network it is on.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct UploadLimits {
+    /// Shared by all uploads at once; `None` uploads at full speed
+    #[serde(default)]
+    pub max_bytes_per_second: Option<u64>,
+    /// Queued uploads only start inside one of these; empty means any time
+    #[serde(default)]
+    pub windows: Vec<UploadWindow>,
+}
+
+impl UploadLimits {
+    fn allows(&self, time: NaiveTime) -> bool {
+        self.windows.is_empty() || self.windows.iter().any(|window| window.contains(time))
+    }
+
+    /// Time from `time` until the next window opens.
+    fn until_open(&self, time: NaiveTime) -> Duration {
+        let now = time.num_seconds_from_midnight() as i64;
+
+        self.windows
+            .iter()
+            .map(|window| (window.start.num_seconds_from_midnight() as i64 - now).rem_euclid(24 * 60 * 60))
+            .min()
+            .map_or(Duration::ZERO, |seconds| Duration::from_secs(seconds as u64))
+    }
+}
+
+/// The device's upload limits, persisted next to the upload sessions.
+/// Clones share the same state.
+#[derive(Clone)]
+pub struct UploadLimitsStore {
+    path: PathBuf,
+    limits: Arc<Mutex<UploadLimits>>,
+}
+
+impl UploadLimitsStore {
+    pub fn load(path: PathBuf) -> Self {
+        let limits: UploadLimits = std::fs::read(&path)
+            .ok()
+            .and_then(|bytes| serde_json::from_slice(&bytes).ok())
+            .unwrap_or_default();
+        UploadThrottle::shared().set_limit(limits.max_bytes_per_second);
+
+        Self {
+            path,
+            limits: Arc::new(Mutex::new(limits)),
+        }
+    }
+
+    pub fn limits(&self) -> UploadLimits {
+        self.limits.lock().unwrap().clone()
+    }
+
+    pub fn set(&self, limits: UploadLimits) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        std::fs::write(&self.path, serde_json::to_vec_pretty(&limits)?)?;
+        UploadThrottle::shared().set_limit(limits.max_bytes_per_second);
+        *self.limits.lock().unwrap() = limits;
+        Ok(())
+    }
+
+    /// Waits until uploads are allowed, calling `on_wait` once if they
+    /// aren't yet.
+    pub async fn wait_for_window(&self, on_wait: impl FnOnce()) {
+        let mut on_wait = Some(on_wait);
+
+        loop {
+            let limits = self.limits();
+            let now = Local::now().time();
+            if limits.allows(now) {
+                return;
+            }
+
+            if let Some(on_wait) = on_wait.take() {
+                on_wait();
+            }
+            tokio::time::sleep(limits.until_open(now).clamp(Duration::from_secs(1), WINDOW_POLL_INTERVAL)).await;
+        }
+    }
+}
+
+pub fn get_limits_path(app_handle: &tauri::AppHandle) -> Result<PathBuf, String> {
+    let app_data_dir = app_handle
+        .path()
+        .app_data_dir()
+        .map_err(|e| format!("Failed to get app data dir: {}", e))?;
+
+    std::fs::create_dir_all(&app_data_dir)
+        .map_err(|e| format!("Failed to create app data dir: {}", e))?;
+
+    Ok(app_data_dir.join("upload_limits.json"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(time: &str) -> NaiveTime {
+        NaiveTime::parse_from_str(time, "%H:%M").unwrap()
+    }
+
+    fn window(start: &str, end: &str) -> UploadWindow {
+        UploadWindow {
+            start: at(start),
+            end: at(end),
+        }
+    }
+
+    fn limits(windows: Vec<UploadWindow>) -> UploadLimits {
+        UploadLimits {
+            max_bytes_per_second: None,
+            windows,
+        }
+    }
+
+    #[test]
+    fn window_within_a_day() {
+        let office_hours = window("09:00", "17:00");
+
+        assert!(office_hours.contains(at("09:00")));
+        assert!(office_hours.contains(at("16:59")));
+        assert!(!office_hours.contains(at("17:00")));
+        assert!(!office_hours.contains(at("08:59")));
+    }
+
+    #[test]
+    fn window_past_midnight() {
+        let night = window("22:00", "06:00");
+
+        assert!(night.contains(at("22:00")));
+        assert!(night.contains(at("23:59")));
+        assert!(night.contains(at("00:00")));
+        assert!(night.contains(at("05:59")));
+        assert!(!night.contains(at("06:00")));
+        assert!(!night.contains(at("12:00")));
+    }
+
+    #[test]
+    fn no_windows_allow_any_time() {
+        assert!(limits(Vec::new()).allows(at("03:00")));
+        assert!(!limits(vec![window("22:00", "06:00")]).allows(at("12:00")));
+    }
+
+    #[test]
+    fn until_open_waits_for_the_nearest_window() {
+        let limits = limits(vec![window("22:00", "06:00"), window("12:00", "13:00")]);
+
+        assert_eq!(limits.until_open(at("10:00")), Duration::from_secs(2 * 60 * 60));
+        assert_eq!(limits.until_open(at("14:30")), Duration::from_secs(7 * 60 * 60 + 30 * 60));
+    }
+
+    #[test]
+    fn until_open_wraps_past_midnight() {
+        let limits = limits(vec![window("01:00", "05:00")]);
+
+        assert_eq!(limits.until_open(at("23:00")), Duration::from_secs(2 * 60 * 60));
+    }
+}
@@ -0,0 +1,200 @@
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::{HashMap, HashSet};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use tauri::Manager;
+use uuid::Uuid;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UploadedPart {
+    pub part_number: i32,
+    pub e_tag: String,
+    /// Base64 SHA-256 sent with the part, repeated when completing the upload
+    #[serde(default)]
+    pub checksum_sha256: Option<String>,
+}
+
+/// An S3 multipart upload that has been started but not completed yet.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MultipartSession {
+    pub upload_id: String,
+    pub part_size: u64,
+    pub total_size: u64,
+    pub parts: Vec<UploadedPart>,
+    /// Whether the upload was started with SHA-256 part checksums; uploads
+    /// started by older versions finish without them
+    #[serde(default)]
+    pub checksums: bool,
+    /// Hex SHA-256 of the whole body. Parts are only reused for the very
+    /// same bytes, since a re-encrypted or edited file of the same size
+    /// would otherwise be stitched together from two versions.
+    #[serde(default)]
+    pub content_sha256: Option<String>,
+}
+
+impl MultipartSession {
+    /// Whether the parts uploaded so far belong to this body.
+    pub fn matches(&self, total_size: u64, content_sha256: &str) -> bool {
+        self.total_size == total_size && self.content_sha256.as_deref() == Some(content_sha256)
+    }
+}
+
+/// A photo whose objects are partly uploaded. Keeping the photo id lets a
+/// retried import of the same file land on the same keys.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PendingImport {
+    pub photo_id: String,
+    pub uploaded_keys: Vec<String>,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct SessionData {
+    imports: HashMap<String, PendingImport>,
+    multipart: HashMap<String, MultipartSession>,
+}
+
+type SessionResult<T> = Result<T, Box<dyn std::error::Error + Send + Sync>>;
+
+/// Upload progress persisted to disk so interrupted uploads resume after an
+/// app restart instead of starting from scratch. Clones share the same state.
+#[derive(Clone)]
+pub struct UploadSessionStore {
+    path: PathBuf,
+    data: Arc<Mutex<SessionData>>,
+}
+
+impl UploadSessionStore {
+    pub fn load(path: PathBuf) -> Self {
+        let data = std::fs::read(&path)
+            .ok()
+            .and_then(|bytes| serde_json::from_slice(&bytes).ok())
+            .unwrap_or_default();
+
+        Self {
+            path,
+            data: Arc::new(Mutex::new(data)),
+        }
+    }
+
+    fn save(&self, data: &SessionData) -> SessionResult<()> {
+        let json = serde_json::to_vec_pretty(data)?;
+        std::fs::write(&self.path, json)?;
+        Ok(())
+    }
+
+    pub fn photo_id_for(&self, fingerprint: &str) -> SessionResult<String> {
+        let mut data = self.data.lock().unwrap();
+        if let Some(pending) = data.imports.get(fingerprint) {
+            return Ok(pending.photo_id.clone());
+        }
+
+        let photo_id = Uuid::new_v4().to_string();
+        data.imports.insert(
+            fingerprint.to_string(),
+            PendingImport {
+                photo_id: photo_id.clone(),
+                uploaded_keys: Vec::new(),
+            },
+        );
+        self.save(&data)?;
+
+        Ok(photo_id)
+    }
+
+    pub fn is_uploaded(&self, fingerprint: &str, key: &str) -> bool {
+        self.data
+            .lock()
+            .unwrap()
+            .imports
+            .get(fingerprint)
+            .map(|pending| pending.uploaded_keys.iter().any(|k| k == key))
+            .unwrap_or(false)
+    }
+
+    pub fn mark_uploaded(&self, fingerprint: &str, key: &str) -> SessionResult<()> {
+        let mut data = self.data.lock().unwrap();
+        if let Some(pending) = data.imports.get_mut(fingerprint) {
+            pending.uploaded_keys.push(key.to_string());
+        }
+        self.save(&data)
+    }
+
+    pub fn finish_import(&self, fingerprint: &str) -> SessionResult<()> {
+        let mut data = self.data.lock().unwrap();
+        data.imports.remove(fingerprint);
+        self.save(&data)
+    }
+
+    /// Photo ids of imports that stopped before their metadata was written.
+    pub fn pending_photo_ids(&self) -> HashSet<String> {
+        self.data
+            .lock()
+            .unwrap()
+            .imports
+            .values()
+            .map(|pending| pending.photo_id.clone())
+            .collect()
+    }
+
+    /// Forgets an interrupted import whose objects were cleaned up or
+    /// adopted, so retrying the file starts a new photo.
+    pub fn abandon_import(&self, photo_id: &str) -> SessionResult<()> {
+        let mut data = self.data.lock().unwrap();
+        data.imports.retain(|_, pending| pending.photo_id != photo_id);
+        self.save(&data)
+    }
+
+    pub fn multipart(&self, key: &str) -> Option<MultipartSession> {
+        self.data.lock().unwrap().multipart.get(key).cloned()
+    }
+
+    pub fn start_multipart(&self, key: &str, session: MultipartSession) -> SessionResult<()> {
+        let mut data = self.data.lock().unwrap();
+        data.multipart.insert(key.to_string(), session);
+        self.save(&data)
+    }
+
+    pub fn record_part(&self, key: &str, part: UploadedPart) -> SessionResult<()> {
+        let mut data = self.data.lock().unwrap();
+        if let Some(session) = data.multipart.get_mut(key) {
+            session.parts.retain(|p| p.part_number != part.part_number);
+            session.parts.push(part);
+        }
+        self.save(&data)
+    }
+
+    pub fn finish_multipart(&self, key: &str) -> SessionResult<()> {
+        let mut data = self.data.lock().unwrap();
+        data.multipart.remove(key);
+        self.save(&data)
+    }
+}
+
+/// Identifies the import of a source file into a library across restarts,
+/// by the library's identity and the file's path, size and modification
+/// time. The same file imported into another library starts afresh.
+pub fn fingerprint(library: &str, file_path: &str) -> SessionResult<String> {
+    let metadata = std::fs::metadata(file_path)?;
+    let modified = metadata
+        .modified()?
+        .duration_since(std::time::UNIX_EPOCH)?
+        .as_secs();
+
+    let mut hasher = Sha256::new();
+    hasher.update(format!("{}|{}|{}|{}", library, file_path, metadata.len(), modified));
+
+    Ok(hex::encode(hasher.finalize()))
+}
+
+pub fn get_sessions_path(app_handle: &tauri::AppHandle) -> Result<PathBuf, String> {
+    let app_data_dir = app_handle
+        .path()
+        .app_data_dir()
+        .map_err(|e| format!("Failed to get app data dir: {}", e))?;
+
+    std::fs::create_dir_all(&app_data_dir)
+        .map_err(|e| format!("Failed to create app data dir: {}", e))?;
+
+    Ok(app_data_dir.join("upload_sessions.json"))
+}
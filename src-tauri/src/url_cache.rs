@@ -0,0 +1,93 @@
+use crate::models::PhotoMetadata;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+/// How long pre-signed GET URLs stay valid
+pub const PRESIGN_TTL: Duration = Duration::from_secs(60 * 60);
+
+// Hand out a fresh URL once a cached one gets this close to expiry
+const REFRESH_MARGIN: Duration = Duration::from_secs(5 * 60);
+
+struct CachedUrl {
+    url: String,
+    expires_at: Instant,
+}
+
+/// Pre-signed rendition URLs for private buckets, keyed by photo id and size.
+#[derive(Default)]
+pub struct UrlCache {
+    keys: HashMap<(String, String), String>,
+    urls: HashMap<(String, String), CachedUrl>,
+}
+
+impl UrlCache {
+    /// Records the object keys of a photo so renditions with non-standard
+    /// keys (such as a stack's secondary asset) can be signed later.
+    pub fn remember(&mut self, photo: &PhotoMetadata) {
+        for (size_type, key) in &photo.object_keys {
+            self.keys
+                .insert((photo.id.clone(), size_type.clone()), key.clone());
+        }
+    }
+
+    pub fn key_for(&self, photo_id: &str, size_type: &str) -> String {
+        self.keys
+            .get(&(photo_id.to_string(), size_type.to_string()))
+            .cloned()
+            .unwrap_or_else(|| format!("photos/{}/{}.jpg", photo_id, size_type))
+    }
+
+    pub fn get(&self, photo_id: &str, size_type: &str) -> Option<String> {
+        self.urls
+            .get(&(photo_id.to_string(), size_type.to_string()))
+            .filter(|cached| cached.expires_at > Instant::now() + REFRESH_MARGIN)
+            .map(|cached| cached.url.clone())
+    }
+
+    pub fn insert(&mut self, photo_id: &str, size_type: &str, url: String) {
+        // Expired URLs are never handed out again
+        let now = Instant::now();
+        self.urls.retain(|_, cached| cached.expires_at > now);
+
+        self.urls.insert(
+            (photo_id.to_string(), size_type.to_string()),
+            CachedUrl {
+                url,
+                expires_at: Instant::now() + PRESIGN_TTL,
+            },
+        );
+    }
+
+    /// Drops everything, for when the library moves to another storage.
+    pub fn clear(&mut self) {
+        self.keys.clear();
+        self.urls.clear();
+    }
+
+    pub fn forget(&mut self, photo_id: &str) {
+        self.keys.retain(|(id, _), _| id != photo_id);
+        self.urls.retain(|(id, _), _| id != photo_id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn inserting_evicts_expired_urls() {
+        let mut cache = UrlCache::default();
+        cache.urls.insert(
+            ("a".to_string(), "thumbnail".to_string()),
+            CachedUrl {
+                url: "https://old".to_string(),
+                expires_at: Instant::now(),
+            },
+        );
+
+        cache.insert("b", "thumbnail", "https://new".to_string());
+
+        assert_eq!(cache.urls.len(), 1);
+        assert_eq!(cache.get("b", "thumbnail").as_deref(), Some("https://new"));
+    }
+}
@@ -0,0 +1,194 @@
+use crate::models::StorageClass;
+use crate::s3_uploader;
+use crate::storage::{self, ObjectInfo, ObjectSource, PutOptions, StorageBackend, StorageResult, SHORT_CACHE_CONTROL};
+use crate::tiering;
+use chrono::DateTime;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+
+// Kept in the library so devices share the prices
+const PRICING_KEY: &str = "settings/pricing.json";
+
+const BYTES_PER_GB: f64 = 1024.0 * 1024.0 * 1024.0;
+
+/// Prices the cost estimate is based on. Defaults are S3 list prices in
+/// us-east-1; other regions and providers need their own.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Pricing {
+    pub currency: String,
+    /// Per GB-month; classes not listed cost nothing
+    pub storage_per_gb_month: BTreeMap<StorageClass, f64>,
+    /// Data transfer out to the internet
+    pub egress_per_gb: f64,
+    pub get_per_1000: f64,
+    pub put_per_1000: f64,
+    /// Expected monthly downloads, which the library listing can't tell
+    pub monthly_egress_gb: f64,
+    pub monthly_get_requests: u64,
+}
+
+impl Default for Pricing {
+    fn default() -> Self {
+        Self {
+            currency: "USD".to_string(),
+            storage_per_gb_month: BTreeMap::from([
+                (StorageClass::Standard, 0.023),
+                (StorageClass::StandardIa, 0.0125),
+                (StorageClass::OnezoneIa, 0.01),
+                (StorageClass::IntelligentTiering, 0.023),
+                (StorageClass::GlacierIr, 0.004),
+                (StorageClass::Glacier, 0.0036),
+                (StorageClass::DeepArchive, 0.00099),
+            ]),
+            egress_per_gb: 0.09,
+            get_per_1000: 0.0004,
+            put_per_1000: 0.005,
+            monthly_egress_gb: 0.0,
+            monthly_get_requests: 0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, Serialize)]
+pub struct Usage {
+    pub objects: u64,
+    pub bytes: u64,
+}
+
+impl Usage {
+    fn add(&mut self, object: &ObjectInfo) {
+        self.objects += 1;
+        self.bytes += object.size;
+    }
+
+    fn gigabytes(&self) -> f64 {
+        self.bytes as f64 / BYTES_PER_GB
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct CostEstimate {
+    pub storage: f64,
+    pub egress: f64,
+    /// Expected GETs plus the PUTs of an average month of uploads
+    pub requests: f64,
+    pub total: f64,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct UsageReport {
+    pub total: Usage,
+    /// Renditions by size name
+    pub by_tier: BTreeMap<String, Usage>,
+    /// Renditions by month of upload, as `YYYY-MM`
+    pub by_month: BTreeMap<String, Usage>,
+    /// Everything by storage class; backends without storage classes
+    /// count as Standard
+    pub by_class: BTreeMap<StorageClass, Usage>,
+    /// Metadata, trash entries, the index and settings
+    pub other: Usage,
+    pub pricing: Pricing,
+    pub estimate: CostEstimate,
+}
+
+pub async fn load_pricing(storage: &dyn StorageBackend) -> StorageResult<Pricing> {
+    match storage.get(PRICING_KEY).await? {
+        Some(object) => Ok(serde_json::from_slice(&object.data)?),
+        None => Ok(Pricing::default()),
+    }
+}
+
+pub async fn save_pricing(storage: &dyn StorageBackend, pricing: &Pricing) -> StorageResult<()> {
+    storage
+        .put(
+            PRICING_KEY,
+            ObjectSource::Bytes(&serde_json::to_vec(pricing)?),
+            &PutOptions {
+                cache_control: Some(SHORT_CACHE_CONTROL.to_string()),
+                ..PutOptions::content_type("application/json")
+            },
+        )
+        .await
+}
+
+/// Adds up every object in the library from the listing and estimates what
+/// it costs per month at the saved prices.
+pub async fn usage_report(storage: &dyn StorageBackend) -> StorageResult<UsageReport> {
+    // Photos are counted in the month they were uploaded, not the month
+    // their objects were last rewritten, e.g. by a storage class change
+    let upload_months: HashMap<String, String> = s3_uploader::list_photos(storage, &|_| {})
+        .await?
+        .into_iter()
+        .map(|photo| (photo.id, month_of(&photo.upload_date)))
+        .collect();
+
+    let mut report = UsageReport {
+        pricing: load_pricing(storage).await?,
+        ..Default::default()
+    };
+
+    for object in &storage::list_all(storage, "").await? {
+        report.total.add(object);
+        report
+            .by_class
+            .entry(object.storage_class.unwrap_or(StorageClass::Standard))
+            .or_default()
+            .add(object);
+
+        let Some(photo_id) = s3_uploader::photo_id_of(&object.key) else {
+            report.other.add(object);
+            continue;
+        };
+
+        report
+            .by_tier
+            .entry(tiering::size_name_of(&object.key).to_string())
+            .or_default()
+            .add(object);
+
+        // Orphaned and trashed renditions fall back to their own date
+        let month = upload_months
+            .get(photo_id)
+            .cloned()
+            .or_else(|| object.last_modified.as_deref().map(month_of))
+            .unwrap_or_else(|| "unknown".to_string());
+        report.by_month.entry(month).or_default().add(object);
+    }
+
+    report.estimate = estimate(&report);
+    Ok(report)
+}
+
+fn estimate(report: &UsageReport) -> CostEstimate {
+    let pricing = &report.pricing;
+
+    let storage = report
+        .by_class
+        .iter()
+        .map(|(class, usage)| {
+            usage.gigabytes() * pricing.storage_per_gb_month.get(class).copied().unwrap_or_default()
+        })
+        .sum();
+    let egress = pricing.monthly_egress_gb * pricing.egress_per_gb;
+
+    let months = report.by_month.len().max(1) as f64;
+    let uploads_per_month = report.by_month.values().map(|usage| usage.objects).sum::<u64>() as f64 / months;
+    let requests = pricing.monthly_get_requests as f64 / 1000.0 * pricing.get_per_1000
+        + uploads_per_month / 1000.0 * pricing.put_per_1000;
+
+    CostEstimate {
+        storage,
+        egress,
+        requests,
+        total: storage + egress + requests,
+    }
+}
+
+// `2024-03-15T10:00:00Z` -> `2024-03`; Azure reports HTTP dates instead
+fn month_of(date: &str) -> String {
+    DateTime::parse_from_rfc3339(date)
+        .or_else(|_| DateTime::parse_from_rfc2822(date))
+        .map(|date| date.format("%Y-%m").to_string())
+        .unwrap_or_else(|_| date.chars().take(7).collect())
+}
@@ -11,9 +11,11 @@ import Gallery from './components/Gallery'
 import UploadView from './components/UploadView'
 import EmptyState from './components/EmptyState'
 import SettingsView from './components/SettingsView'
+import TrashView from './components/TrashView'
 import UploadProgress from './components/UploadProgress'
 import ReloadToast from './components/ReloadToast'
-import { useGalleryStore } from './store/galleryStore'
+import { UploadReport, useGalleryStore } from './store/galleryStore'
+import { describeError } from './lib/errors'
 
 function App() {
   const { isAuthenticated, user } = useAuth()
@@ -80,12 +82,16 @@ function App() {
   const handleUpload = async (files: File[]) => {
     const filePaths = files.map((f) => (f as any).path || f.name)
     try {
-      await invoke('upload_photos', { files: filePaths })
+      const report = await invoke<UploadReport>('upload_photos', { files: filePaths })
+      if (report.failed.length > 0) {
+        const files = report.failed.map((f) => `${f.file}: ${describeError(f.error)}`).join('\n')
+        alert(`${report.failed.length} files could not be uploaded:\n${files}`)
+      }
       await fetchPhotos(false) // Refresh from S3
       setCurrentView('photos') // Return to photos view after upload
     } catch (error) {
       console.error('Upload failed:', error)
-      alert(`Upload failed: ${error}`)
+      alert(`Upload failed: ${describeError(error)}`)
     }
   }
 
@@ -139,6 +145,10 @@ function App() {
             <EmptyState view="people" onUpload={() => setCurrentView('upload')} />
           )}
           
+          {currentView === 'trash' && user?.hasS3Config && (
+            <TrashView />
+          )}
+          
           {currentView === 'settings' && (
             <SettingsView />
           )}
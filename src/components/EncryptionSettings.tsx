@@ -0,0 +1,229 @@
+import { useEffect, useState } from 'react'
+import { invoke } from '@tauri-apps/api/core'
+import { listen } from '@tauri-apps/api/event'
+import { Lock, AlertCircle } from 'lucide-react'
+import { Card, CardContent, CardDescription, CardHeader, CardTitle } from './ui/card'
+import { Button } from './ui/button'
+import { Badge } from './ui/badge'
+import { describeError } from '../lib/errors'
+
+interface EncryptionStatus {
+  enabled: boolean
+  unlocked: boolean
+}
+
+type Action = 'enable' | 'unlock' | 'rotate' | 'recover'
+
+const inputClass =
+  'w-full px-3 py-2 border border-gray-300 rounded-lg focus:ring-2 focus:ring-primary-500 focus:border-transparent'
+
+export default function EncryptionSettings() {
+  const [status, setStatus] = useState<EncryptionStatus | null>(null)
+  const [action, setAction] = useState<Action | null>(null)
+  const [passphrase, setPassphrase] = useState('')
+  const [newPassphrase, setNewPassphrase] = useState('')
+  const [recoveryKey, setRecoveryKey] = useState('')
+  const [shownRecoveryKey, setShownRecoveryKey] = useState('')
+  const [progress, setProgress] = useState<number | null>(null)
+  const [loading, setLoading] = useState(false)
+  const [error, setError] = useState('')
+
+  const refresh = () =>
+    invoke<EncryptionStatus>('encryption_status')
+      .then(setStatus)
+      .catch(() => setStatus(null))
+
+  useEffect(() => {
+    refresh()
+
+    const unlisten = listen<number>('encryption-progress', (event) => {
+      setProgress(event.payload)
+    })
+
+    return () => {
+      unlisten.then((fn) => fn())
+    }
+  }, [])
+
+  // Encrypts existing photos, or moves them onto the new key after a rotation
+  const reencrypt = async () => {
+    setProgress(0)
+    try {
+      const report = await invoke<{ processed: number; archived: string[] }>('reencrypt_library')
+      if (report.archived.length > 0) {
+        setError(
+          `${report.archived.length} archived object(s) could not be re-encrypted. Restore them from archive storage and re-encrypt again.`
+        )
+      }
+    } finally {
+      setProgress(null)
+    }
+  }
+
+  const handleSubmit = async (e: React.FormEvent) => {
+    e.preventDefault()
+    setLoading(true)
+    setError('')
+
+    try {
+      switch (action) {
+        case 'enable':
+          setShownRecoveryKey(await invoke<string>('enable_encryption', { passphrase }))
+          break
+        case 'unlock':
+          await invoke('unlock_library', { passphrase })
+          break
+        case 'rotate':
+          setShownRecoveryKey(
+            await invoke<string>('rotate_encryption_key', {
+              passphrase,
+              newPassphrase: newPassphrase || null,
+            })
+          )
+          break
+        case 'recover':
+          await invoke('recover_library', { recoveryKey, newPassphrase })
+          break
+      }
+
+      setAction(null)
+      setPassphrase('')
+      setNewPassphrase('')
+      setRecoveryKey('')
+      await refresh()
+
+      if (action === 'enable' || action === 'rotate') {
+        await reencrypt()
+      }
+    } catch (err) {
+      setError(describeError(err))
+    } finally {
+      setLoading(false)
+    }
+  }
+
+  return (
+    <Card className="glass-card">
+      <CardHeader>
+        <div className="flex items-center justify-between">
+          <div className="flex items-center space-x-3">
+            <div className="p-2 bg-emerald-100 dark:bg-emerald-900/30 rounded-lg">
+              <Lock className="w-6 h-6 text-emerald-600" />
+            </div>
+            <div>
+              <CardTitle>End-to-End Encryption</CardTitle>
+              <CardDescription>Encrypt photos on this device before they are uploaded</CardDescription>
+            </div>
+          </div>
+          {status?.enabled && (
+            <Badge variant={status.unlocked ? 'secondary' : 'outline'}>
+              {status.unlocked ? 'Unlocked' : 'Locked'}
+            </Badge>
+          )}
+        </div>
+      </CardHeader>
+      <CardContent className="space-y-4">
+        {shownRecoveryKey && (
+          <div className="bg-amber-50 dark:bg-amber-900/20 border border-amber-200 dark:border-amber-800 rounded-lg p-4">
+            <div className="flex items-start space-x-3">
+              <AlertCircle className="w-5 h-5 text-amber-600 dark:text-amber-400 mt-0.5" />
+              <div className="flex-1">
+                <h4 className="font-medium text-amber-900 dark:text-amber-300 mb-1">
+                  Save your recovery key
+                </h4>
+                <p className="text-sm text-amber-700 dark:text-amber-400 mb-2">
+                  This is the only way back into your library if you forget the passphrase. It will not be shown again.
+                </p>
+                <code className="block text-xs break-all bg-white dark:bg-gray-900 rounded p-2 select-all">
+                  {shownRecoveryKey}
+                </code>
+                <Button variant="outline" className="mt-3" onClick={() => setShownRecoveryKey('')}>
+                  I have saved it
+                </Button>
+              </div>
+            </div>
+          </div>
+        )}
+
+        {progress !== null && (
+          <p className="text-sm text-muted-foreground">Encrypting library… {progress} objects checked</p>
+        )}
+
+        {error && <p className="text-sm text-red-600">{error}</p>}
+
+        {action ? (
+          <form onSubmit={handleSubmit} className="space-y-3">
+            {action !== 'recover' && (
+              <input
+                type="password"
+                value={passphrase}
+                onChange={(e) => setPassphrase(e.target.value)}
+                className={inputClass}
+                placeholder={action === 'enable' ? 'Choose a passphrase' : 'Passphrase'}
+                required
+              />
+            )}
+            {action === 'recover' && (
+              <input
+                type="text"
+                value={recoveryKey}
+                onChange={(e) => setRecoveryKey(e.target.value)}
+                className={inputClass}
+                placeholder="Recovery key"
+                required
+              />
+            )}
+            {(action === 'rotate' || action === 'recover') && (
+              <input
+                type="password"
+                value={newPassphrase}
+                onChange={(e) => setNewPassphrase(e.target.value)}
+                className={inputClass}
+                placeholder={action === 'rotate' ? 'New passphrase (optional)' : 'New passphrase'}
+                required={action === 'recover'}
+              />
+            )}
+
+            <div className="flex space-x-2">
+              <Button type="button" variant="outline" className="flex-1" onClick={() => setAction(null)}>
+                Cancel
+              </Button>
+              <Button type="submit" className="flex-1" disabled={loading}>
+                {loading ? 'Working…' : 'Continue'}
+              </Button>
+            </div>
+          </form>
+        ) : !status ? (
+          <p className="text-sm text-muted-foreground">Configure storage to set up encryption.</p>
+        ) : !status.enabled ? (
+          <Button variant="outline" className="w-full" onClick={() => setAction('enable')}>
+            Enable Encryption
+          </Button>
+        ) : !status.unlocked ? (
+          <div className="flex space-x-2">
+            <Button variant="outline" className="flex-1" onClick={() => setAction('unlock')}>
+              Unlock Library
+            </Button>
+            <Button variant="outline" className="flex-1" onClick={() => setAction('recover')}>
+              Use Recovery Key
+            </Button>
+          </div>
+        ) : (
+          <div className="flex space-x-2">
+            <Button variant="outline" className="flex-1" onClick={() => setAction('rotate')}>
+              Rotate Keys
+            </Button>
+            <Button
+              variant="outline"
+              className="flex-1"
+              disabled={progress !== null}
+              onClick={() => reencrypt().catch((err) => setError(describeError(err)))}
+            >
+              Encrypt Existing Photos
+            </Button>
+          </div>
+        )}
+      </CardContent>
+    </Card>
+  )
+}
@@ -1,23 +1,69 @@
 import { useState } from 'react'
-import { Eye, Calendar } from 'lucide-react'
+import { Eye, Calendar, CheckCircle2, Circle, Trash2, X } from 'lucide-react'
 import { Photo, useGalleryStore } from '../store/galleryStore'
 import PhotoModal from './PhotoModal'
 import LazyImage from './LazyImage'
 import { Badge } from './ui/badge'
+import { Button } from './ui/button'
+import { describeError } from '../lib/errors'
 
 interface GalleryProps {
   photos: Photo[]
 }
 
 export default function Gallery({ photos }: GalleryProps) {
-  const { setSelectedPhoto } = useGalleryStore()
+  const { setSelectedPhoto, deletePhotos } = useGalleryStore()
   const [selectedPhoto, setLocalSelectedPhoto] = useState<Photo | null>(null)
+  const [isSelecting, setIsSelecting] = useState(false)
+  const [checkedIds, setCheckedIds] = useState<Set<string>>(new Set())
+  const [isDeleting, setIsDeleting] = useState(false)
 
   const handlePhotoClick = (photo: Photo) => {
+    if (isSelecting) {
+      const next = new Set(checkedIds)
+      if (next.has(photo.id)) {
+        next.delete(photo.id)
+      } else {
+        next.add(photo.id)
+      }
+      setCheckedIds(next)
+      return
+    }
+
     setLocalSelectedPhoto(photo)
     setSelectedPhoto(photo)
   }
 
+  const stopSelecting = () => {
+    setIsSelecting(false)
+    setCheckedIds(new Set())
+  }
+
+  const handleDeleteSelected = async () => {
+    const count = checkedIds.size
+    if (!confirm(`Move ${count} ${count === 1 ? 'photo' : 'photos'} to the trash?`)) {
+      return
+    }
+
+    setIsDeleting(true)
+    try {
+      const report = await deletePhotos([...checkedIds])
+      if (report.failed.length > 0) {
+        const keys = report.failed.map((f) => `${f.key}: ${describeError(f.error)}`).join('\n')
+        alert(`${report.failed.length} objects could not be deleted:\n${keys}`)
+        // Keep the photos that failed selected so they can be retried
+        const deleted = new Set(report.deleted)
+        setCheckedIds(new Set([...checkedIds].filter((id) => !deleted.has(id))))
+      } else {
+        stopSelecting()
+      }
+    } catch (error) {
+      alert(`Failed to delete photos: ${describeError(error)}`)
+    } finally {
+      setIsDeleting(false)
+    }
+  }
+
   const handleCloseModal = () => {
     setLocalSelectedPhoto(null)
     setSelectedPhoto(null)
@@ -53,10 +99,34 @@ export default function Gallery({ photos }: GalleryProps) {
         </div>
         
         <div className="flex items-center space-x-2">
-          <Badge variant="secondary" className="glass-card">
-            <Calendar className="w-3 h-3 mr-1" />
-            Grouped by date
-          </Badge>
+          {isSelecting ? (
+            <>
+              <Button
+                variant="outline"
+                onClick={handleDeleteSelected}
+                disabled={checkedIds.size === 0 || isDeleting}
+                className="text-destructive hover:text-destructive"
+              >
+                <Trash2 className="w-4 h-4 mr-2" />
+                {isDeleting ? 'Deleting…' : `Delete ${checkedIds.size} Selected`}
+              </Button>
+              <Button variant="ghost" onClick={stopSelecting} disabled={isDeleting}>
+                <X className="w-4 h-4 mr-2" />
+                Cancel
+              </Button>
+            </>
+          ) : (
+            <>
+              <Button variant="outline" onClick={() => setIsSelecting(true)} disabled={photos.length === 0}>
+                <CheckCircle2 className="w-4 h-4 mr-2" />
+                Select
+              </Button>
+              <Badge variant="secondary" className="glass-card">
+                <Calendar className="w-3 h-3 mr-1" />
+                Grouped by date
+              </Badge>
+            </>
+          )}
         </div>
       </div>
 
@@ -99,12 +169,25 @@ export default function Gallery({ photos }: GalleryProps) {
                     </div>
                   </div>
                   
+                  {/* Selection checkbox */}
+                  {isSelecting && (
+                    <div className="absolute top-2 right-2 p-0.5 bg-white/90 dark:bg-gray-900/90 rounded-full">
+                      {checkedIds.has(photo.id) ? (
+                        <CheckCircle2 className="w-5 h-5 text-primary-600" />
+                      ) : (
+                        <Circle className="w-5 h-5 text-gray-400" />
+                      )}
+                    </div>
+                  )}
+
                   {/* View icon */}
-                  <div className="absolute top-1/2 left-1/2 -translate-x-1/2 -translate-y-1/2 opacity-0 group-hover:opacity-100 transition-all duration-300 scale-75 group-hover:scale-100">
-                    <div className="p-3 bg-white/90 dark:bg-gray-900/90 backdrop-blur-sm rounded-full shadow-xl">
-                      <Eye className="w-6 h-6 text-primary-600" />
+                  {!isSelecting && (
+                    <div className="absolute top-1/2 left-1/2 -translate-x-1/2 -translate-y-1/2 opacity-0 group-hover:opacity-100 transition-all duration-300 scale-75 group-hover:scale-100">
+                      <div className="p-3 bg-white/90 dark:bg-gray-900/90 backdrop-blur-sm rounded-full shadow-xl">
+                        <Eye className="w-6 h-6 text-primary-600" />
+                      </div>
                     </div>
-                  </div>
+                  )}
                 </div>
               ))}
             </div>
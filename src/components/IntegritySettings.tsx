@@ -0,0 +1,185 @@
+import { useEffect, useState } from 'react'
+import { invoke } from '@tauri-apps/api/core'
+import { listen } from '@tauri-apps/api/event'
+import { ShieldCheck, AlertCircle } from 'lucide-react'
+import { Card, CardContent, CardDescription, CardHeader, CardTitle } from './ui/card'
+import { Button } from './ui/button'
+import { Badge } from './ui/badge'
+import { StorageError, describeError } from '../lib/errors'
+
+type ScrubMode = 'checksums' | 'download'
+
+type ScrubProblem =
+  | { kind: 'mismatch'; expected: string; actual: string }
+  | { kind: 'missing' }
+  | { kind: 'unreadable'; error: StorageError }
+
+interface ScrubReport {
+  mode: ScrubMode
+  started_at: string
+  checked: number
+  verified: number
+  unverified: number
+  issues: { photo_id: string; key: string; problem: ScrubProblem }[]
+}
+
+interface ScrubSchedule {
+  interval_days: number | null
+  mode: ScrubMode
+  last_started_at: string | null
+  last_report: ScrubReport | null
+}
+
+const selectClass =
+  'px-2 py-1 text-sm border border-gray-300 rounded-lg focus:ring-2 focus:ring-primary-500 focus:border-transparent'
+
+const describeProblem = (problem: ScrubProblem) => {
+  switch (problem.kind) {
+    case 'mismatch':
+      return 'contents do not match the checksum recorded at upload'
+    case 'missing':
+      return 'missing from storage'
+    case 'unreadable':
+      return describeError(problem.error)
+  }
+}
+
+export default function IntegritySettings() {
+  const [schedule, setSchedule] = useState<ScrubSchedule | null>(null)
+  const [progress, setProgress] = useState<number | null>(null)
+  const [error, setError] = useState('')
+
+  useEffect(() => {
+    invoke<ScrubSchedule>('get_scrub_schedule')
+      .then(setSchedule)
+      .catch(() => setSchedule(null))
+
+    const unlisten = listen<number>('scrub-progress', (event) => {
+      setProgress(event.payload)
+    })
+
+    return () => {
+      unlisten.then((fn) => fn())
+    }
+  }, [])
+
+  const saveSchedule = async (intervalDays: number | null, mode: ScrubMode) => {
+    if (!schedule) {
+      return
+    }
+
+    setSchedule({ ...schedule, interval_days: intervalDays, mode })
+    try {
+      await invoke('set_scrub_schedule', { intervalDays, mode })
+    } catch (err) {
+      setError(describeError(err))
+    }
+  }
+
+  const handleScrub = async (mode: ScrubMode) => {
+    setError('')
+    setProgress(0)
+    try {
+      const report = await invoke<ScrubReport>('scrub_library', { mode })
+      setSchedule(schedule && { ...schedule, last_report: report })
+    } catch (err) {
+      setError(describeError(err))
+    } finally {
+      setProgress(null)
+    }
+  }
+
+  const report = schedule?.last_report
+
+  return (
+    <Card className="glass-card">
+      <CardHeader>
+        <div className="flex items-center justify-between">
+          <div className="flex items-center space-x-3">
+            <div className="p-2 bg-sky-100 dark:bg-sky-900/30 rounded-lg">
+              <ShieldCheck className="w-6 h-6 text-sky-600" />
+            </div>
+            <div>
+              <CardTitle>Integrity Checks</CardTitle>
+              <CardDescription>Verify stored photos against the checksums recorded at upload</CardDescription>
+            </div>
+          </div>
+          {report && (
+            <Badge variant={report.issues.length === 0 ? 'secondary' : 'outline'}>
+              {report.issues.length === 0 ? 'No problems found' : `${report.issues.length} problems`}
+            </Badge>
+          )}
+        </div>
+      </CardHeader>
+      <CardContent className="space-y-4">
+        {schedule && (
+          <div className="flex items-center justify-between text-sm">
+            <span className="text-muted-foreground">Check automatically</span>
+            <div className="flex space-x-2">
+              <select
+                value={schedule.interval_days ?? ''}
+                onChange={(e) => saveSchedule(e.target.value ? Number(e.target.value) : null, schedule.mode)}
+                className={selectClass}
+              >
+                <option value="">Never</option>
+                <option value="7">Weekly</option>
+                <option value="30">Monthly</option>
+                <option value="90">Every 3 months</option>
+              </select>
+              <select
+                value={schedule.mode}
+                onChange={(e) => saveSchedule(schedule.interval_days, e.target.value as ScrubMode)}
+                className={selectClass}
+              >
+                <option value="checksums">Compare checksums</option>
+                <option value="download">Download and hash</option>
+              </select>
+            </div>
+          </div>
+        )}
+
+        {report && (
+          <div className="text-sm text-muted-foreground">
+            Last check {new Date(report.started_at).toLocaleString()}: {report.verified} of {report.checked} files
+            verified, {report.unverified} could not be verified.
+            {report.issues.length > 0 && (
+              <ul className="mt-2 space-y-1 text-red-700 dark:text-red-400">
+                {report.issues.map((issue) => (
+                  <li key={issue.key} className="break-all">
+                    {issue.key}: {describeProblem(issue.problem)}
+                  </li>
+                ))}
+              </ul>
+            )}
+          </div>
+        )}
+
+        {error && (
+          <div className="flex items-start space-x-2 text-sm text-red-700">
+            <AlertCircle className="w-4 h-4 mt-0.5" />
+            <span>{error}</span>
+          </div>
+        )}
+
+        <div className="flex space-x-2">
+          <Button
+            variant="outline"
+            className="flex-1"
+            onClick={() => handleScrub('checksums')}
+            disabled={progress !== null}
+          >
+            {progress !== null ? `Checking… ${progress} files` : 'Compare Checksums'}
+          </Button>
+          <Button
+            variant="outline"
+            className="flex-1"
+            onClick={() => handleScrub('download')}
+            disabled={progress !== null}
+          >
+            Download and Verify All
+          </Button>
+        </div>
+      </CardContent>
+    </Card>
+  )
+}
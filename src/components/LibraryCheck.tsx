@@ -0,0 +1,171 @@
+import { useState } from 'react'
+import { invoke } from '@tauri-apps/api/core'
+import { Stethoscope, AlertCircle } from 'lucide-react'
+import { Card, CardContent, CardDescription, CardHeader, CardTitle } from './ui/card'
+import { Button } from './ui/button'
+import { Badge } from './ui/badge'
+import { StorageError, describeError } from '../lib/errors'
+
+type RepairAction = 'regenerate' | 'relink' | 'delete'
+
+type FsckIssue =
+  | { kind: 'orphaned'; photo_id: string; keys: string[]; pending_upload: boolean; can_relink: boolean }
+  | { kind: 'incomplete'; photo_id: string; missing: string[]; can_regenerate: boolean }
+
+interface FsckReport {
+  photos_checked: number
+  issues: FsckIssue[]
+}
+
+interface RepairReport {
+  repaired: string[]
+  failed: { photo_id: string; error: StorageError }[]
+}
+
+const describeIssue = (issue: FsckIssue) => {
+  switch (issue.kind) {
+    case 'orphaned':
+      return issue.pending_upload
+        ? `${issue.keys.length} files from an unfinished upload; importing the same file again completes it`
+        : `${issue.keys.length} files that belong to no photo`
+    case 'incomplete':
+      return `missing ${issue.missing.join(', ')}`
+  }
+}
+
+// Repairs that make sense for an issue, the first one being the suggestion
+const repairsFor = (issue: FsckIssue): RepairAction[] => {
+  switch (issue.kind) {
+    case 'orphaned':
+      return issue.can_relink ? ['relink', 'delete'] : ['delete']
+    case 'incomplete':
+      return issue.can_regenerate ? ['regenerate', 'delete'] : ['delete']
+  }
+}
+
+const repairLabels: Record<RepairAction, string> = {
+  regenerate: 'Regenerate',
+  relink: 'Relink',
+  delete: 'Delete',
+}
+
+export default function LibraryCheck() {
+  const [report, setReport] = useState<FsckReport | null>(null)
+  const [failures, setFailures] = useState<Record<string, string>>({})
+  const [busy, setBusy] = useState(false)
+  const [error, setError] = useState('')
+
+  const handleCheck = async () => {
+    setError('')
+    setFailures({})
+    setBusy(true)
+    try {
+      setReport(await invoke<FsckReport>('check_library'))
+    } catch (err) {
+      setError(describeError(err))
+    } finally {
+      setBusy(false)
+    }
+  }
+
+  const handleRepair = async (repairs: { photo_id: string; action: RepairAction }[]) => {
+    if (repairs.some((repair) => repair.action === 'delete')) {
+      if (!confirm('Files of deleted photos cannot be recovered. Continue?')) {
+        return
+      }
+    }
+
+    setError('')
+    setBusy(true)
+    try {
+      const result = await invoke<RepairReport>('repair_library', { repairs })
+      const repaired = new Set(result.repaired)
+      setReport(report && { ...report, issues: report.issues.filter((issue) => !repaired.has(issue.photo_id)) })
+      setFailures(Object.fromEntries(result.failed.map((failure) => [failure.photo_id, describeError(failure.error)])))
+    } catch (err) {
+      setError(describeError(err))
+    } finally {
+      setBusy(false)
+    }
+  }
+
+  // Unfinished uploads are left alone; they may still be resumed
+  const suggested = (report?.issues ?? [])
+    .filter((issue) => issue.kind !== 'orphaned' || !issue.pending_upload)
+    .map((issue) => ({ photo_id: issue.photo_id, action: repairsFor(issue)[0] }))
+
+  return (
+    <Card className="glass-card">
+      <CardHeader>
+        <div className="flex items-center justify-between">
+          <div className="flex items-center space-x-3">
+            <div className="p-2 bg-amber-100 dark:bg-amber-900/30 rounded-lg">
+              <Stethoscope className="w-6 h-6 text-amber-600" />
+            </div>
+            <div>
+              <CardTitle>Library Check</CardTitle>
+              <CardDescription>Find leftovers of interrupted uploads and photos with missing files</CardDescription>
+            </div>
+          </div>
+          {report && (
+            <Badge variant={report.issues.length === 0 ? 'secondary' : 'outline'}>
+              {report.issues.length === 0 ? 'No problems found' : `${report.issues.length} problems`}
+            </Badge>
+          )}
+        </div>
+      </CardHeader>
+      <CardContent className="space-y-4">
+        {report && (
+          <div className="text-sm text-muted-foreground">
+            Checked {report.photos_checked} photos.
+            {report.issues.length > 0 && (
+              <ul className="mt-2 space-y-2">
+                {report.issues.map((issue) => (
+                  <li key={issue.photo_id} className="flex items-start justify-between space-x-2">
+                    <div className="break-all">
+                      <span className="font-mono">{issue.photo_id}</span>: {describeIssue(issue)}
+                      {failures[issue.photo_id] && (
+                        <div className="text-red-700 dark:text-red-400">{failures[issue.photo_id]}</div>
+                      )}
+                    </div>
+                    <div className="flex space-x-1 shrink-0">
+                      {repairsFor(issue).map((action) => (
+                        <Button
+                          key={action}
+                          size="sm"
+                          variant="outline"
+                          disabled={busy}
+                          onClick={() => handleRepair([{ photo_id: issue.photo_id, action }])}
+                        >
+                          {repairLabels[action]}
+                        </Button>
+                      ))}
+                    </div>
+                  </li>
+                ))}
+              </ul>
+            )}
+          </div>
+        )}
+
+        {error && (
+          <div className="flex items-start space-x-2 text-sm text-red-700">
+            <AlertCircle className="w-4 h-4 mt-0.5" />
+            <span>{error}</span>
+          </div>
+        )}
+
+        <div className="flex space-x-2">
+          <Button variant="outline" className="flex-1" onClick={handleCheck} disabled={busy}>
+            {busy ? 'Working…' : 'Check Library'}
+          </Button>
+          {suggested.length > 0 && (
+            <Button variant="outline" className="flex-1" onClick={() => handleRepair(suggested)} disabled={busy}>
+              Repair All
+            </Button>
+          )}
+        </div>
+      </CardContent>
+    </Card>
+  )
+}
@@ -1,8 +1,11 @@
 import { useState } from 'react'
-import { X, Trash2, Download, Share2, Info } from 'lucide-react'
+import { invoke } from '@tauri-apps/api/core'
+import { X, Trash2, Download, Share2, Info, ArchiveRestore } from 'lucide-react'
 import { Photo, useGalleryStore } from '../store/galleryStore'
 import { Button } from './ui/button'
 import { Badge } from './ui/badge'
+import LazyImage from './LazyImage'
+import { describeError } from '../lib/errors'
 
 interface PhotoModalProps {
   photo: Photo
@@ -22,11 +25,25 @@ export default function PhotoModal({ photo, onClose }: PhotoModalProps) {
       onClose()
     } catch (error) {
       console.error('Failed to delete photo:', error)
-      alert('Failed to delete photo')
+      alert(`Failed to delete photo: ${describeError(error)}`)
     }
     setIsDeleting(false)
   }
 
+  // Archived originals (Glacier Flexible / Deep Archive) take hours to come back
+  const handleRestore = async () => {
+    try {
+      const requested = await invoke<number>('restore_photo', { photoId: photo.id })
+      alert(
+        requested > 0
+          ? 'Restore requested. The original will be available in a few hours.'
+          : 'This photo is not archived.'
+      )
+    } catch (error) {
+      alert(`Failed to request restore: ${describeError(error)}`)
+    }
+  }
+
   const formatFileSize = (bytes: number): string => {
     if (bytes < 1024) return bytes + ' B'
     if (bytes < 1024 * 1024) return (bytes / 1024).toFixed(1) + ' KB'
@@ -84,6 +101,16 @@ export default function PhotoModal({ photo, onClose }: PhotoModalProps) {
               <Download className="w-5 h-5" />
             </Button>
             
+            <Button
+              variant="ghost"
+              size="icon"
+              onClick={handleRestore}
+              className="rounded-full"
+              title="Restore original from archive"
+            >
+              <ArchiveRestore className="w-5 h-5" />
+            </Button>
+            
             <Button
               variant="ghost"
               size="icon"
@@ -108,10 +135,13 @@ export default function PhotoModal({ photo, onClose }: PhotoModalProps) {
         <div className="flex-1 flex overflow-hidden">
           {/* Image */}
           <div className="flex-1 bg-black/20 backdrop-blur-sm flex items-center justify-center p-6">
-            <img
+            <LazyImage
+              photoId={photo.id}
               src={photo.large_url || photo.original_url}
               alt={photo.original_name}
               className="max-w-full max-h-full object-contain rounded-lg shadow-2xl"
+              sizeType={photo.large_url || photo.object_keys?.large ? 'large' : 'original'}
+              loading="eager"
             />
           </div>
 
@@ -167,9 +197,9 @@ export default function PhotoModal({ photo, onClose }: PhotoModalProps) {
             className="bg-white rounded-lg p-6 max-w-md"
             onClick={(e) => e.stopPropagation()}
           >
-            <h3 className="text-lg font-semibold text-gray-800 mb-2">Delete Photo?</h3>
+            <h3 className="text-lg font-semibold text-gray-800 mb-2">Move to Trash?</h3>
             <p className="text-gray-600 mb-6">
-              This will permanently delete "{photo.original_name}" from your gallery. This action cannot be undone.
+              "{photo.original_name}" will be moved to the trash. You can restore it from there until it is deleted permanently.
             </p>
             <div className="flex justify-end space-x-3">
               <button
@@ -184,7 +214,7 @@ export default function PhotoModal({ photo, onClose }: PhotoModalProps) {
                 className="px-4 py-2 bg-red-600 hover:bg-red-700 text-white rounded-lg transition-colors disabled:opacity-50"
                 disabled={isDeleting}
               >
-                {isDeleting ? 'Deleting...' : 'Delete'}
+                {isDeleting ? 'Moving...' : 'Move to Trash'}
               </button>
             </div>
           </div>
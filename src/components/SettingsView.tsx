@@ -1,16 +1,48 @@
 import { useState } from 'react'
+import { invoke } from '@tauri-apps/api/core'
 import { Cloud, HardDrive, Shield, AlertCircle } from 'lucide-react'
 import { Card, CardContent, CardDescription, CardHeader, CardTitle } from './ui/card'
 import { Button } from './ui/button'
 import { Badge } from './ui/badge'
 import ConfigModal from './ConfigModal'
 import SyncWarning from './SyncWarning'
+import EncryptionSettings from './EncryptionSettings'
+import IntegritySettings from './IntegritySettings'
+import LibraryCheck from './LibraryCheck'
+import StorageUsage from './StorageUsage'
+import UploadLimitsSettings from './UploadLimitsSettings'
 import { useAuth } from '../hooks/useAuth'
+import { describeError } from '../lib/errors'
+
+interface RetierSummary {
+  checked: number
+  moved: number
+  needs_restore: number
+}
 
 export default function SettingsView() {
   const [showConfigModal, setShowConfigModal] = useState(false)
+  const [showMigrateModal, setShowMigrateModal] = useState(false)
+  const [isRetiering, setIsRetiering] = useState(false)
   const { user } = useAuth()
 
+  // Moves photos uploaded earlier to the storage classes configured now
+  const handleRetier = async () => {
+    setIsRetiering(true)
+    try {
+      const summary = await invoke<RetierSummary>('retier_photos')
+      let message = `Moved ${summary.moved} of ${summary.checked} files to their configured storage class.`
+      if (summary.needs_restore > 0) {
+        message += ` ${summary.needs_restore} archived files have to be restored before they can move.`
+      }
+      alert(message)
+    } catch (error) {
+      alert(`Failed to apply storage classes: ${describeError(error)}`)
+    } finally {
+      setIsRetiering(false)
+    }
+  }
+
   return (
     <div className="max-w-4xl mx-auto space-y-6">
       {/* Page Header */}
@@ -90,9 +122,45 @@ export default function SettingsView() {
           >
             {user?.hasS3Config ? 'Update S3 Configuration' : 'Configure S3 Storage'}
           </Button>
+
+          {user?.hasS3Config && (
+            <Button
+              onClick={handleRetier}
+              variant="outline"
+              className="w-full mt-2"
+              disabled={isRetiering}
+            >
+              {isRetiering ? 'Applying Storage Classes…' : 'Apply Storage Classes'}
+            </Button>
+          )}
+
+          {user?.hasS3Config && (
+            <Button
+              onClick={() => setShowMigrateModal(true)}
+              variant="outline"
+              className="w-full mt-2"
+            >
+              Migrate to Another Storage
+            </Button>
+          )}
         </CardContent>
       </Card>
 
+      {/* Client-side Encryption */}
+      {user?.hasS3Config && <EncryptionSettings />}
+
+      {/* Integrity Checks */}
+      {user?.hasS3Config && <IntegritySettings />}
+
+      {/* Library Check */}
+      {user?.hasS3Config && <LibraryCheck />}
+
+      {/* Storage Usage */}
+      {user?.hasS3Config && <StorageUsage />}
+
+      {/* Upload Limits */}
+      <UploadLimitsSettings />
+
       {/* Account Information */}
       <Card className="glass-card">
         <CardHeader>
@@ -170,6 +238,10 @@ export default function SettingsView() {
       {showConfigModal && (
         <ConfigModal onClose={() => setShowConfigModal(false)} />
       )}
+
+      {showMigrateModal && (
+        <ConfigModal migrate onClose={() => setShowMigrateModal(false)} />
+      )}
     </div>
   )
 }
@@ -1,4 +1,4 @@
-import { Image, Folder, Users, Upload, Settings } from 'lucide-react'
+import { Image, Folder, Users, Upload, Settings, Trash2 } from 'lucide-react'
 import { cn } from '@/lib/utils'
 import { Button } from './ui/button'
 
@@ -12,6 +12,7 @@ export default function Sidebar({ currentView, onViewChange }: SidebarProps) {
     { id: 'photos', label: 'All Images', icon: Image },
     { id: 'albums', label: 'Albums', icon: Folder },
     { id: 'people', label: 'People', icon: Users },
+    { id: 'trash', label: 'Trash', icon: Trash2 },
   ]
 
   return (
@@ -0,0 +1,203 @@
+import { useState } from 'react'
+import { invoke } from '@tauri-apps/api/core'
+import { PieChart, AlertCircle } from 'lucide-react'
+import { Card, CardContent, CardDescription, CardHeader, CardTitle } from './ui/card'
+import { Button } from './ui/button'
+import { describeError } from '../lib/errors'
+
+interface Usage {
+  objects: number
+  bytes: number
+}
+
+interface Pricing {
+  currency: string
+  storage_per_gb_month: Record<string, number>
+  egress_per_gb: number
+  get_per_1000: number
+  put_per_1000: number
+  monthly_egress_gb: number
+  monthly_get_requests: number
+}
+
+interface UsageReport {
+  total: Usage
+  by_tier: Record<string, Usage>
+  by_month: Record<string, Usage>
+  by_class: Record<string, Usage>
+  other: Usage
+  pricing: Pricing
+  estimate: { storage: number; egress: number; requests: number; total: number }
+}
+
+const formatBytes = (bytes: number) => {
+  const units = ['B', 'KB', 'MB', 'GB', 'TB']
+  let value = bytes
+  let unit = 0
+  while (value >= 1024 && unit < units.length - 1) {
+    value /= 1024
+    unit++
+  }
+  return `${value.toFixed(unit === 0 ? 0 : 1)} ${units[unit]}`
+}
+
+function UsageTable({ title, rows }: { title: string; rows: [string, Usage][] }) {
+  return (
+    <div>
+      <h4 className="font-medium mb-1">{title}</h4>
+      <table className="w-full text-sm">
+        <tbody>
+          {rows.map(([name, usage]) => (
+            <tr key={name} className="border-t border-gray-200 dark:border-gray-700">
+              <td className="py-1">{name}</td>
+              <td className="py-1 text-right text-muted-foreground">{usage.objects} files</td>
+              <td className="py-1 text-right">{formatBytes(usage.bytes)}</td>
+            </tr>
+          ))}
+        </tbody>
+      </table>
+    </div>
+  )
+}
+
+export default function StorageUsage() {
+  const [report, setReport] = useState<UsageReport | null>(null)
+  const [pricing, setPricing] = useState<Pricing | null>(null)
+  const [loading, setLoading] = useState(false)
+  const [error, setError] = useState('')
+
+  const loadReport = async () => {
+    setError('')
+    setLoading(true)
+    try {
+      const result = await invoke<UsageReport>('storage_usage')
+      setReport(result)
+      setPricing(result.pricing)
+    } catch (err) {
+      setError(describeError(err))
+    } finally {
+      setLoading(false)
+    }
+  }
+
+  const savePricing = async () => {
+    if (!pricing) {
+      return
+    }
+
+    setError('')
+    try {
+      await invoke('set_pricing', { pricing })
+      await loadReport()
+    } catch (err) {
+      setError(describeError(err))
+    }
+  }
+
+  const priceInput = (label: string, value: number, onChange: (value: number) => void) => (
+    <label key={label} className="flex items-center justify-between space-x-2">
+      <span className="text-muted-foreground">{label}</span>
+      <input
+        type="number"
+        min="0"
+        step="any"
+        value={value}
+        onChange={(e) => onChange(Number(e.target.value))}
+        className="w-28 px-2 py-1 text-sm border border-gray-300 rounded-lg focus:ring-2 focus:ring-primary-500 focus:border-transparent"
+      />
+    </label>
+  )
+
+  const money = (amount: number) =>
+    new Intl.NumberFormat(undefined, { style: 'currency', currency: report?.pricing.currency || 'USD' }).format(amount)
+
+  return (
+    <Card className="glass-card">
+      <CardHeader>
+        <div className="flex items-center space-x-3">
+          <div className="p-2 bg-emerald-100 dark:bg-emerald-900/30 rounded-lg">
+            <PieChart className="w-6 h-6 text-emerald-600" />
+          </div>
+          <div>
+            <CardTitle>Storage Usage</CardTitle>
+            <CardDescription>What the library stores and roughly what it costs per month</CardDescription>
+          </div>
+        </div>
+      </CardHeader>
+      <CardContent className="space-y-4">
+        {report && (
+          <>
+            <div className="grid grid-cols-2 gap-4 text-sm">
+              <div>
+                <div className="text-muted-foreground">Total</div>
+                <div className="text-lg font-semibold">
+                  {formatBytes(report.total.bytes)} in {report.total.objects} files
+                </div>
+              </div>
+              <div>
+                <div className="text-muted-foreground">Estimated per month</div>
+                <div className="text-lg font-semibold">{money(report.estimate.total)}</div>
+                <div className="text-xs text-muted-foreground">
+                  {money(report.estimate.storage)} storage, {money(report.estimate.egress)} egress,{' '}
+                  {money(report.estimate.requests)} requests
+                </div>
+              </div>
+            </div>
+
+            <div className="grid grid-cols-1 md:grid-cols-2 gap-4">
+              <UsageTable
+                title="By size"
+                rows={[...Object.entries(report.by_tier), ['metadata and settings', report.other]]}
+              />
+              <UsageTable title="By storage class" rows={Object.entries(report.by_class)} />
+              <UsageTable title="By month of upload" rows={Object.entries(report.by_month).reverse()} />
+            </div>
+          </>
+        )}
+
+        {pricing && (
+          <div className="space-y-2 text-sm">
+            <h4 className="font-medium">Prices ({pricing.currency})</h4>
+            {Object.entries(pricing.storage_per_gb_month).map(([storageClass, price]) =>
+              priceInput(`${storageClass} per GB-month`, price, (value) =>
+                setPricing({
+                  ...pricing,
+                  storage_per_gb_month: { ...pricing.storage_per_gb_month, [storageClass]: value },
+                })
+              )
+            )}
+            {priceInput('Egress per GB', pricing.egress_per_gb, (value) =>
+              setPricing({ ...pricing, egress_per_gb: value })
+            )}
+            {priceInput('Per 1,000 GET requests', pricing.get_per_1000, (value) =>
+              setPricing({ ...pricing, get_per_1000: value })
+            )}
+            {priceInput('Per 1,000 PUT requests', pricing.put_per_1000, (value) =>
+              setPricing({ ...pricing, put_per_1000: value })
+            )}
+            {priceInput('Expected egress GB per month', pricing.monthly_egress_gb, (value) =>
+              setPricing({ ...pricing, monthly_egress_gb: value })
+            )}
+            {priceInput('Expected GET requests per month', pricing.monthly_get_requests, (value) =>
+              setPricing({ ...pricing, monthly_get_requests: Math.round(value) })
+            )}
+            <Button variant="outline" size="sm" onClick={savePricing}>
+              Save Prices
+            </Button>
+          </div>
+        )}
+
+        {error && (
+          <div className="flex items-start space-x-2 text-sm text-red-700">
+            <AlertCircle className="w-4 h-4 mt-0.5" />
+            <span>{error}</span>
+          </div>
+        )}
+
+        <Button variant="outline" className="w-full" onClick={loadReport} disabled={loading}>
+          {loading ? 'Counting…' : report ? 'Refresh' : 'Show Usage'}
+        </Button>
+      </CardContent>
+    </Card>
+  )
+}
@@ -0,0 +1,168 @@
+import { useEffect, useState } from 'react'
+import { invoke } from '@tauri-apps/api/core'
+import { RotateCcw, Trash2 } from 'lucide-react'
+import { DeleteReport, Photo, useGalleryStore } from '../store/galleryStore'
+import LazyImage from './LazyImage'
+import { Button } from './ui/button'
+import { describeError } from '../lib/errors'
+
+interface TrashedPhoto {
+  trashed_at: string
+  photo: Photo
+}
+
+interface TrashSettings {
+  retention_days: number
+}
+
+export default function TrashView() {
+  const { fetchPhotos } = useGalleryStore()
+  const [entries, setEntries] = useState<TrashedPhoto[]>([])
+  const [retentionDays, setRetentionDays] = useState(30)
+  const [isLoading, setIsLoading] = useState(true)
+
+  const loadTrash = async () => {
+    setIsLoading(true)
+    try {
+      const [trash, settings] = await Promise.all([
+        invoke<TrashedPhoto[]>('list_trash'),
+        invoke<TrashSettings>('get_trash_settings'),
+      ])
+      setEntries(trash)
+      setRetentionDays(settings.retention_days)
+    } catch (error) {
+      alert(`Failed to load trash: ${describeError(error)}`)
+    } finally {
+      setIsLoading(false)
+    }
+  }
+
+  useEffect(() => {
+    loadTrash()
+  }, [])
+
+  const handleRestore = async (photoId: string) => {
+    try {
+      await invoke('restore_from_trash', { photoId })
+      setEntries(entries.filter((entry) => entry.photo.id !== photoId))
+      fetchPhotos(false)
+    } catch (error) {
+      alert(`Failed to restore photo: ${describeError(error)}`)
+    }
+  }
+
+  const handleDelete = async (photoIds?: string[]) => {
+    const what = photoIds ? 'this photo' : `all ${entries.length} photos in the trash`
+    if (!confirm(`Permanently delete ${what}? This cannot be undone.`)) {
+      return
+    }
+
+    try {
+      const report = await invoke<DeleteReport>('empty_trash', { photoIds: photoIds ?? null })
+      if (report.skipped.length > 0) {
+        alert(`${report.skipped.length} photos were no longer in the trash and were not deleted.`)
+      }
+      await loadTrash()
+    } catch (error) {
+      alert(`Failed to delete: ${describeError(error)}`)
+      await loadTrash()
+    }
+  }
+
+  const handleRetentionChange = async (days: number) => {
+    setRetentionDays(days)
+    try {
+      await invoke('set_trash_retention', { retentionDays: days })
+    } catch (error) {
+      alert(`Failed to save retention: ${describeError(error)}`)
+    }
+  }
+
+  const daysLeft = (trashedAt: string) => {
+    const elapsed = (Date.now() - new Date(trashedAt).getTime()) / (24 * 60 * 60 * 1000)
+    return Math.max(0, Math.ceil(retentionDays - elapsed))
+  }
+
+  return (
+    <>
+      {/* Trash Header */}
+      <div className="mb-6 flex items-center justify-between">
+        <div>
+          <h2 className="text-2xl font-bold text-gray-800 dark:text-white">Trash</h2>
+          <p className="text-sm text-muted-foreground mt-1">
+            {entries.length} {entries.length === 1 ? 'photo' : 'photos'} · deleted permanently after{' '}
+            <select
+              value={retentionDays}
+              onChange={(e) => handleRetentionChange(Number(e.target.value))}
+              className="bg-transparent border-b border-gray-300 focus:outline-none"
+            >
+              {[7, 14, 30, 60, 90].map((days) => (
+                <option key={days} value={days}>
+                  {days} days
+                </option>
+              ))}
+            </select>
+          </p>
+        </div>
+
+        <Button
+          variant="outline"
+          onClick={() => handleDelete()}
+          disabled={entries.length === 0}
+          className="text-destructive hover:text-destructive"
+        >
+          <Trash2 className="w-4 h-4 mr-2" />
+          Empty Trash
+        </Button>
+      </div>
+
+      {isLoading ? (
+        <p className="text-sm text-muted-foreground">Loading trash…</p>
+      ) : entries.length === 0 ? (
+        <p className="text-sm text-muted-foreground">The trash is empty.</p>
+      ) : (
+        <div className="grid grid-cols-2 sm:grid-cols-3 md:grid-cols-4 lg:grid-cols-5 xl:grid-cols-6 gap-3">
+          {entries.map(({ photo, trashed_at }) => (
+            <div
+              key={photo.id}
+              className="relative group overflow-hidden rounded-xl bg-gray-200 dark:bg-gray-800 aspect-square glass-card"
+            >
+              <LazyImage
+                photoId={photo.id}
+                src={photo.thumbnail_url}
+                alt={photo.original_name}
+                className="w-full h-full object-cover opacity-70"
+                sizeType="thumbnail"
+              />
+
+              <div className="absolute inset-0 bg-gradient-to-t from-black/70 via-black/0 to-black/0">
+                <div className="absolute bottom-0 left-0 right-0 p-3 flex items-end justify-between">
+                  <div className="min-w-0">
+                    <p className="text-white text-xs font-medium truncate">{photo.original_name}</p>
+                    <p className="text-white/70 text-xs">{daysLeft(trashed_at)} days left</p>
+                  </div>
+                  <div className="flex space-x-1 opacity-0 group-hover:opacity-100 transition-opacity">
+                    <button
+                      onClick={() => handleRestore(photo.id)}
+                      className="p-1.5 bg-white/90 rounded-full"
+                      title="Restore"
+                    >
+                      <RotateCcw className="w-4 h-4 text-primary-600" />
+                    </button>
+                    <button
+                      onClick={() => handleDelete([photo.id])}
+                      className="p-1.5 bg-white/90 rounded-full"
+                      title="Delete permanently"
+                    >
+                      <Trash2 className="w-4 h-4 text-red-600" />
+                    </button>
+                  </div>
+                </div>
+              </div>
+            </div>
+          ))}
+        </div>
+      )}
+    </>
+  )
+}
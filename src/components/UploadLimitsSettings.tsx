@@ -0,0 +1,139 @@
+import { useEffect, useState } from 'react'
+import { invoke } from '@tauri-apps/api/core'
+import { Gauge, AlertCircle, X } from 'lucide-react'
+import { Card, CardContent, CardDescription, CardHeader, CardTitle } from './ui/card'
+import { Button } from './ui/button'
+import { describeError } from '../lib/errors'
+
+interface UploadWindow {
+  start: string
+  end: string
+}
+
+interface UploadLimits {
+  max_bytes_per_second: number | null
+  windows: UploadWindow[]
+}
+
+const inputClass =
+  'px-2 py-1 text-sm border border-gray-300 rounded-lg focus:ring-2 focus:ring-primary-500 focus:border-transparent'
+
+const BYTES_PER_MBIT = 1_000_000 / 8
+
+export default function UploadLimitsSettings() {
+  const [limits, setLimits] = useState<UploadLimits | null>(null)
+  const [saved, setSaved] = useState(false)
+  const [error, setError] = useState('')
+
+  useEffect(() => {
+    invoke<UploadLimits>('get_upload_limits')
+      .then(setLimits)
+      .catch((err) => setError(describeError(err)))
+  }, [])
+
+  if (!limits) {
+    return null
+  }
+
+  const update = (changes: Partial<UploadLimits>) => {
+    setLimits({ ...limits, ...changes })
+    setSaved(false)
+  }
+
+  const updateWindow = (index: number, changes: Partial<UploadWindow>) => {
+    update({ windows: limits.windows.map((window, i) => (i === index ? { ...window, ...changes } : window)) })
+  }
+
+  const handleSave = async () => {
+    setError('')
+    try {
+      await invoke('set_upload_limits', { limits })
+      setSaved(true)
+    } catch (err) {
+      setError(describeError(err))
+    }
+  }
+
+  const megabits = limits.max_bytes_per_second === null ? '' : limits.max_bytes_per_second / BYTES_PER_MBIT
+
+  return (
+    <Card className="glass-card">
+      <CardHeader>
+        <div className="flex items-center space-x-3">
+          <div className="p-2 bg-violet-100 dark:bg-violet-900/30 rounded-lg">
+            <Gauge className="w-6 h-6 text-violet-600" />
+          </div>
+          <div>
+            <CardTitle>Upload Limits</CardTitle>
+            <CardDescription>Keep large imports from saturating this device's connection</CardDescription>
+          </div>
+        </div>
+      </CardHeader>
+      <CardContent className="space-y-4 text-sm">
+        <label className="flex items-center justify-between">
+          <span className="text-muted-foreground">Maximum upload speed (Mbit/s, empty for no limit)</span>
+          <input
+            type="number"
+            min="0"
+            step="any"
+            value={megabits}
+            onChange={(e) =>
+              update({
+                max_bytes_per_second: e.target.value ? Math.round(Number(e.target.value) * BYTES_PER_MBIT) : null,
+              })
+            }
+            className={`w-28 ${inputClass}`}
+          />
+        </label>
+
+        <div className="space-y-2">
+          <div className="text-muted-foreground">
+            Upload only during these hours{limits.windows.length === 0 && ' (any time when none are set)'}
+          </div>
+          {limits.windows.map((window, index) => (
+            <div key={index} className="flex items-center space-x-2">
+              <input
+                type="time"
+                value={window.start}
+                onChange={(e) => updateWindow(index, { start: e.target.value })}
+                className={inputClass}
+              />
+              <span>to</span>
+              <input
+                type="time"
+                value={window.end}
+                onChange={(e) => updateWindow(index, { end: e.target.value })}
+                className={inputClass}
+              />
+              <Button
+                variant="ghost"
+                size="sm"
+                onClick={() => update({ windows: limits.windows.filter((_, i) => i !== index) })}
+              >
+                <X className="w-4 h-4" />
+              </Button>
+            </div>
+          ))}
+          <Button
+            variant="outline"
+            size="sm"
+            onClick={() => update({ windows: [...limits.windows, { start: '22:00', end: '06:00' }] })}
+          >
+            Add Time Window
+          </Button>
+        </div>
+
+        {error && (
+          <div className="flex items-start space-x-2 text-red-700">
+            <AlertCircle className="w-4 h-4 mt-0.5" />
+            <span>{error}</span>
+          </div>
+        )}
+
+        <Button variant="outline" className="w-full" onClick={handleSave} disabled={saved}>
+          {saved ? 'Saved' : 'Save Upload Limits'}
+        </Button>
+      </CardContent>
+    </Card>
+  )
+}
@@ -0,0 +1,40 @@
+// Error returned by storage commands, see `StorageError` in the backend
+export type StorageErrorCode =
+  | 'auth'
+  | 'not_found'
+  | 'throttled'
+  | 'network'
+  | 'quota'
+  | 'precondition_failed'
+  | 'not_configured'
+  | 'locked'
+  | 'archived'
+  | 'other'
+
+export interface StorageError {
+  code: StorageErrorCode
+  message: string
+}
+
+export function isStorageError(error: unknown): error is StorageError {
+  return typeof error === 'object' && error !== null && 'code' in error && 'message' in error
+}
+
+const hints: Partial<Record<StorageErrorCode, string>> = {
+  auth: 'Check your storage credentials and permissions',
+  throttled: 'The storage provider is busy, please try again shortly',
+  network: 'Could not reach the storage provider',
+  quota: 'Your storage is full',
+  not_configured: 'Configure storage in Settings first',
+  locked: 'Unlock your encrypted library in Settings',
+  archived: 'This file is archived, restore it from the photo view first',
+}
+
+export function describeError(error: unknown): string {
+  if (!isStorageError(error)) {
+    return String(error)
+  }
+
+  const hint = hints[error.code]
+  return hint ? `${hint} (${error.message})` : error.message
+}
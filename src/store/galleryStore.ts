@@ -1,5 +1,21 @@
 import { create } from 'zustand'
 import { invoke } from '@tauri-apps/api/core'
+import { listen } from '@tauri-apps/api/event'
+import { StorageError } from '../lib/errors'
+
+export interface StackAsset {
+  name: string
+  key: string
+  url: string
+  content_type: string
+  file_size: number
+}
+
+export interface PhotoStack {
+  kind: 'live_photo' | 'raw_plus_jpeg'
+  primary: StackAsset
+  secondary: StackAsset
+}
 
 export interface Photo {
   id: string
@@ -13,15 +29,47 @@ export interface Photo {
   original_url: string
   width: number
   height: number
+  stack?: PhotoStack
+  object_keys?: Record<string, string>
+  checksums?: Record<string, string>
+}
+
+export interface DeleteFailure {
+  key: string
+  error: StorageError
+}
+
+export interface UploadFailure {
+  file: string
+  error: StorageError
+}
+
+export interface UploadReport {
+  uploaded: Photo[]
+  failed: UploadFailure[]
+}
+
+export interface DeleteReport {
+  deleted: string[]
+  failed: DeleteFailure[]
+  skipped: string[]
+}
+
+interface PhotoPage {
+  photos: Photo[]
+  next_cursor: string | null
 }
 
 interface GalleryState {
   photos: Photo[]
   isLoading: boolean
   selectedPhoto: Photo | null
+  nextCursor: string | null
   
   fetchPhotos: (useCache: boolean) => Promise<void>
+  fetchPhotoPage: (reset?: boolean, pageSize?: number) => Promise<void>
   deletePhoto: (photoId: string) => Promise<void>
+  deletePhotos: (photoIds: string[], permanent?: boolean) => Promise<DeleteReport>
   setSelectedPhoto: (photo: Photo | null) => void
   getCachedImageUrl: (photoId: string, sizeType: string) => Promise<string | null>
 }
@@ -30,15 +78,39 @@ export const useGalleryStore = create<GalleryState>((set, get) => ({
   photos: [],
   isLoading: false,
   selectedPhoto: null,
+  nextCursor: null,
 
   fetchPhotos: async (useCache = true) => {
     set({ isLoading: true })
+
+    // Show photos as the backend streams them in, before the full list is ready
+    const unlisten = await listen<Photo[]>('photos-batch', (event) => {
+      const known = new Set(get().photos.map((p) => p.id))
+      const added = event.payload.filter((p) => !known.has(p.id))
+      set({ photos: [...get().photos, ...added] })
+    })
+
     try {
       const photos = await invoke<Photo[]>('list_photos', { useCache })
       set({ photos, isLoading: false })
     } catch (error) {
       console.error('Failed to fetch photos:', error)
       set({ isLoading: false })
+    } finally {
+      unlisten()
+    }
+  },
+
+  fetchPhotoPage: async (reset = false, pageSize = 200) => {
+    const cursor = reset ? null : get().nextCursor
+    set({ isLoading: true })
+    try {
+      const page = await invoke<PhotoPage>('list_photos_page', { cursor, pageSize })
+      const photos = reset ? page.photos : [...get().photos, ...page.photos]
+      set({ photos, nextCursor: page.next_cursor, isLoading: false })
+    } catch (error) {
+      console.error('Failed to fetch photo page:', error)
+      set({ isLoading: false })
     }
   },
 
@@ -53,6 +125,14 @@ export const useGalleryStore = create<GalleryState>((set, get) => ({
     }
   },
 
+  deletePhotos: async (photoIds: string[], permanent = false) => {
+    const report = await invoke<DeleteReport>('delete_photos', { photoIds, permanent })
+    // Photos with failed keys are kept, so they can be deleted again
+    const deleted = new Set(report.deleted)
+    set({ photos: get().photos.filter((p) => !deleted.has(p.id)) })
+    return report
+  },
+
   setSelectedPhoto: (photo) => set({ selectedPhoto: photo }),
 
   getCachedImageUrl: async (photoId: string, sizeType: string) => {